@@ -0,0 +1,361 @@
+//! A backend that serves records from memory.
+
+use std::collections::HashMap;
+
+use Result;
+use backend::Backend;
+use error::Error;
+use file::{Calibration, CalibrationTableKind, FileInfo, Record, SosblMode};
+#[cfg(test)]
+use file::{Block, Channel};
+
+/// A backend that serves a fixed set of records from memory.
+///
+/// Records are served exactly as they were provided, so `set_sosbl_mode` has no effect. Seeks past
+/// either end of the records are clamped to the first or last record.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::backend::Memory;
+/// use sdf::file::{File, FileInfo};
+/// let info = FileInfo {
+///     instrument: "Q680I".to_string(),
+///     serial: "9998212".to_string(),
+///     epoch: "UNKNOWN".to_string(),
+///     v_group: 299707502.1266937,
+///     sampling_time: 0.000000001,
+///     gps_synchronized: true,
+///     num_facets: 4,
+/// };
+/// let mut file = File::new(Memory::new(info, Vec::new()));
+/// assert!(file.read().is_err());
+/// ```
+#[derive(Debug)]
+pub struct Memory {
+    info: FileInfo,
+    calibrations: HashMap<CalibrationTableKind, Calibration>,
+    records: Vec<Record>,
+    position: usize,
+}
+
+impl Memory {
+    /// Creates a new memory backend that will serve the given records.
+    pub fn new(info: FileInfo, records: Vec<Record>) -> Memory {
+        Memory {
+            info: info,
+            calibrations: HashMap::new(),
+            records: records,
+            position: 0,
+        }
+    }
+
+    /// Adds a calibration table to this backend.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// # use sdf::file::FileInfo;
+    /// # let info = FileInfo {
+    /// #     instrument: "Q680I".to_string(),
+    /// #     serial: "9998212".to_string(),
+    /// #     epoch: "UNKNOWN".to_string(),
+    /// #     v_group: 299707502.1266937,
+    /// #     sampling_time: 0.000000001,
+    /// #     gps_synchronized: true,
+    /// #     num_facets: 4,
+    /// # };
+    /// use sdf::backend::Memory;
+    /// use sdf::file::{Calibration, CalibrationTableKind, Channel};
+    /// let calibration = Calibration {
+    ///     abscissa: vec![0.0, 1.0],
+    ///     ordinate: vec![0.0, 2.0],
+    /// };
+    /// let memory = Memory::new(info, Vec::new())
+    ///                  .calibration(CalibrationTableKind::Amplitude(Channel::High), calibration);
+    /// ```
+    pub fn calibration(mut self, kind: CalibrationTableKind, calibration: Calibration) -> Memory {
+        let _ = self.calibrations.insert(kind, calibration);
+        self
+    }
+
+    fn clamp(&mut self, position: usize) {
+        self.position = if self.records.is_empty() {
+            0
+        } else if position >= self.records.len() {
+            self.records.len() - 1
+        } else {
+            position
+        };
+    }
+}
+
+impl Backend for Memory {
+    fn reindex(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn indexed(&self) -> bool {
+        true
+    }
+
+    fn remove_index(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn set_sosbl_mode(&mut self, _: SosblMode) -> Result<()> {
+        Ok(())
+    }
+
+    fn info(&mut self) -> Result<FileInfo> {
+        Ok(self.info.clone())
+    }
+
+    fn calibration(&mut self, kind: CalibrationTableKind) -> Result<Calibration> {
+        let _ = try!(kind.as_u16());
+        self.calibrations
+            .get(&kind)
+            .cloned()
+            .ok_or_else(|| {
+                let channel = match kind {
+                    CalibrationTableKind::Amplitude(channel) => channel,
+                    CalibrationTableKind::Range(channel) => channel,
+                };
+                Error::NoCalibrationTableForChannel(channel)
+            })
+    }
+
+    fn read(&mut self) -> Result<Record> {
+        match self.records.get(self.position) {
+            Some(record) => {
+                self.position += 1;
+                Ok(record.clone())
+            }
+            None => Err(Error::EndOfFile("End of the in-memory records".to_string())),
+        }
+    }
+
+    fn seek(&mut self, index: u32) -> Result<()> {
+        let position = (index as usize).saturating_sub(1);
+        self.clamp(position);
+        Ok(())
+    }
+
+    fn seek_time(&mut self, time: f64) -> Result<()> {
+        let position = self.records
+                           .iter()
+                           .position(|r| r.time_sorg >= time)
+                           .unwrap_or(self.records.len());
+        self.clamp(position);
+        Ok(())
+    }
+
+    fn seek_time_external(&mut self, time: f64) -> Result<()> {
+        let position = self.records
+                           .iter()
+                           .position(|r| r.time_external >= time)
+                           .unwrap_or(self.records.len());
+        self.clamp(position);
+        Ok(())
+    }
+
+    fn tell(&mut self) -> Result<u32> {
+        Ok(self.position as u32 + 1)
+    }
+}
+
+/// Returns the info of a Q680i file, for tests that need a `FileInfo` but don't care what's in it.
+#[cfg(test)]
+pub fn test_info() -> FileInfo {
+    FileInfo {
+        instrument: "Q680I".to_string(),
+        serial: "9998212".to_string(),
+        epoch: "UNKNOWN".to_string(),
+        v_group: 299707502.1266937,
+        sampling_time: 0.000000001,
+        gps_synchronized: true,
+        num_facets: 4,
+    }
+}
+
+/// Returns a record with one peak on the reference channel and one on the high channel, for tests
+/// that need a record to discretize. Both of its times are `time`.
+#[cfg(test)]
+pub fn test_record(time: f64) -> Record {
+    Record {
+        time_sorg: time,
+        time_external: time,
+        origin: [0.0; 3],
+        direction: [1.0, 0.0, 0.0],
+        synchronized: true,
+        sync_lastsec: true,
+        housekeeping: false,
+        facet: 0,
+        blocks: vec![Block {
+                         time_sosbl: time,
+                         channel: Channel::Reference,
+                         samples: vec![0, 10, 60, 20, 0, 0],
+                     },
+                     Block {
+                         time_sosbl: time + 1e-6,
+                         channel: Channel::High,
+                         samples: vec![0, 10, 30, 80, 30, 10, 0, 0],
+                     }],
+    }
+}
+
+/// A memory backend that fails to read some of its records, for tests of error handling.
+///
+/// Like sdfifc, it can only seek to finite times. It never says it's indexed, so reindexing a
+/// `File` always reaches the backend.
+#[cfg(test)]
+#[derive(Debug)]
+pub struct Flaky {
+    memory: Memory,
+    bad: Vec<u32>,
+}
+
+#[cfg(test)]
+impl Flaky {
+    /// Creates a backend that serves the given records, except that reading any of the
+    /// one-based indices in `bad` fails.
+    pub fn new(records: Vec<Record>, bad: Vec<u32>) -> Flaky {
+        Flaky {
+            memory: Memory::new(test_info(), records),
+            bad: bad,
+        }
+    }
+}
+
+#[cfg(test)]
+impl Backend for Flaky {
+    fn reindex(&mut self) -> Result<()> {
+        self.memory.reindex()
+    }
+
+    fn indexed(&self) -> bool {
+        false
+    }
+
+    fn remove_index(&self) -> Result<()> {
+        self.memory.remove_index()
+    }
+
+    fn set_sosbl_mode(&mut self, mode: SosblMode) -> Result<()> {
+        self.memory.set_sosbl_mode(mode)
+    }
+
+    fn info(&mut self) -> Result<FileInfo> {
+        self.memory.info()
+    }
+
+    fn calibration(&mut self, kind: CalibrationTableKind) -> Result<Calibration> {
+        Backend::calibration(&mut self.memory, kind)
+    }
+
+    fn read(&mut self) -> Result<Record> {
+        let index = try!(self.memory.tell());
+        if self.bad.contains(&index) {
+            Err(Error::Runtime(format!("bad record {}", index)))
+        } else {
+            self.memory.read()
+        }
+    }
+
+    fn seek(&mut self, index: u32) -> Result<()> {
+        self.memory.seek(index)
+    }
+
+    fn seek_time(&mut self, time: f64) -> Result<()> {
+        try!(check_time(time));
+        self.memory.seek_time(time)
+    }
+
+    fn seek_time_external(&mut self, time: f64) -> Result<()> {
+        try!(check_time(time));
+        self.memory.seek_time_external(time)
+    }
+
+    fn tell(&mut self) -> Result<u32> {
+        self.memory.tell()
+    }
+}
+
+#[cfg(test)]
+fn check_time(time: f64) -> Result<()> {
+    if time.is_finite() {
+        Ok(())
+    } else {
+        Err(Error::BadArg(format!("Can't seek to {}", time)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use file::{File, Record};
+
+    fn record(time: f64) -> Record {
+        let mut record = test_record(time);
+        record.time_external = time + 100.0;
+        record
+    }
+
+    fn file() -> File<Memory> {
+        File::new(Memory::new(test_info(), vec![record(1.0), record(2.0), record(3.0)]))
+    }
+
+    #[test]
+    fn read_to_end() {
+        let mut file = file();
+        assert_eq!(1, file.tell().unwrap());
+        assert_eq!(1.0, file.read().unwrap().time_sorg);
+        assert_eq!(2, file.tell().unwrap());
+        assert_eq!(2.0, file.read().unwrap().time_sorg);
+        assert_eq!(3.0, file.read().unwrap().time_sorg);
+        assert_eq!(4, file.tell().unwrap());
+        match file.read() {
+            Err(Error::EndOfFile(_)) => {}
+            other => panic!("Expected end of file, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn seek() {
+        let mut file = file();
+        file.seek(2).unwrap();
+        assert_eq!(2.0, file.read().unwrap().time_sorg);
+        file.seek(u32::max_value()).unwrap();
+        assert_eq!(3.0, file.read().unwrap().time_sorg);
+    }
+
+    #[test]
+    fn seek_time() {
+        let mut file = file();
+        file.seek_time(1.5).unwrap();
+        assert_eq!(2.0, file.read().unwrap().time_sorg);
+        file.seek_time_external(101.0).unwrap();
+        assert_eq!(1.0, file.read().unwrap().time_sorg);
+    }
+
+    #[test]
+    fn calibration() {
+        let mut file = File::new(Memory::new(test_info(), Vec::new())
+                                     .calibration(CalibrationTableKind::Amplitude(Channel::High),
+                                                  Calibration {
+                                                      abscissa: vec![0.0],
+                                                      ordinate: vec![1.0],
+                                                  }));
+        assert!(file.calibration(CalibrationTableKind::Amplitude(Channel::High)).is_ok());
+        match file.calibration(CalibrationTableKind::Amplitude(Channel::Low)) {
+            Err(Error::NoCalibrationTableForChannel(Channel::Low)) => {}
+            other => panic!("Expected no calibration table, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn iterate() {
+        assert_eq!(3, file().into_iter().count());
+    }
+}
@@ -0,0 +1,77 @@
+//! Backends that provide the data behind a `File`.
+//!
+//! A `File` does not read .sdf data itself, it delegates to a `Backend`. The default backend,
+//! `Sdfifc`, wraps Riegl's libsdfifc. The `Memory` backend serves records that live in memory,
+//! which lets you exercise code that consumes `File`s without the vendor library or real data.
+//!
+//! Record indices are one-based, following sdfifc: the first record has index one, and `tell`
+//! returns the index of the next record to be read.
+
+mod memory;
+mod sdfifc;
+
+pub use self::memory::Memory;
+#[cfg(test)]
+pub use self::memory::{Flaky, test_info, test_record};
+pub use self::sdfifc::Sdfifc;
+
+use Result;
+use error::Error;
+use file::{Calibration, CalibrationTableKind, FileInfo, Record, ReindexProgress, SosblMode};
+
+/// The operations that a `File` needs from its data source.
+///
+/// Backends are constructed on their own (e.g. `Sdfifc::open`, `Memory::new`) and then handed to
+/// `File::new`.
+pub trait Backend {
+    /// (Re-)Creates the index, unconditionally.
+    fn reindex(&mut self) -> Result<()>;
+
+    /// (Re-)Creates the index, unconditionally, reporting progress along the way.
+    ///
+    /// `progress` is called from the calling thread, and returns false to cancel the reindex, in
+    /// which case this returns `Error::Cancelled`. The default implementation can only cancel
+    /// before it starts, and reports once before and once after calling `reindex`.
+    fn reindex_with_progress(&mut self,
+                             progress: &mut FnMut(ReindexProgress) -> bool)
+                             -> Result<()> {
+        if !progress(ReindexProgress::default()) {
+            return Err(Error::Cancelled);
+        }
+        try!(self.reindex());
+        let _ = progress(ReindexProgress { finished: true, ..ReindexProgress::default() });
+        Ok(())
+    }
+
+    /// Returns true if this backend has an index.
+    fn indexed(&self) -> bool;
+
+    /// Removes the index.
+    fn remove_index(&self) -> Result<()>;
+
+    /// Sets the mode of the timestamp of the start of the sample block.
+    fn set_sosbl_mode(&mut self, mode: SosblMode) -> Result<()>;
+
+    /// Returns information about the file.
+    fn info(&mut self) -> Result<FileInfo>;
+
+    /// Returns a calibration table.
+    fn calibration(&mut self, kind: CalibrationTableKind) -> Result<Calibration>;
+
+    /// Reads the next record.
+    ///
+    /// Returns `Error::EndOfFile` when there are no more records.
+    fn read(&mut self) -> Result<Record>;
+
+    /// Seeks to a record index.
+    fn seek(&mut self, index: u32) -> Result<()>;
+
+    /// Seeks to an internal timestamp, in seconds.
+    fn seek_time(&mut self, time: f64) -> Result<()>;
+
+    /// Seeks to an external timestamp, in seconds.
+    fn seek_time_external(&mut self, time: f64) -> Result<()>;
+
+    /// Returns the index of the next record to be read.
+    fn tell(&mut self) -> Result<u32>;
+}
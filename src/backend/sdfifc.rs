@@ -0,0 +1,655 @@
+//! A backend built on Riegl's libsdfifc.
+
+use std::collections::hash_map::DefaultHasher;
+use std::ffi::{CStr, CString};
+use std::fs::{self, metadata, remove_file};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::sync::mpsc::{RecvTimeoutError, channel};
+use std::thread;
+use std::time::{Duration, UNIX_EPOCH};
+
+use libc::c_char;
+
+use Result;
+use backend::Backend;
+use error::Error;
+use ffi::{error_from_code, fwifc_close, fwifc_file, fwifc_get_calib, fwifc_get_info, fwifc_open,
+          fwifc_read, fwifc_reindex, fwifc_sbl_t, fwifc_seek, fwifc_seek_time,
+          fwifc_seek_time_external, fwifc_tell, fwifc_set_sosbl_relative, lock};
+use file::{Block, Calibration, CalibrationTableKind, Channel, FileInfo, IndexLocation, OpenOptions,
+           Record, ReindexProgress, SosblMode, StaleIndexPolicy};
+
+/// How often we report progress while reindexing, in milliseconds.
+const PROGRESS_INTERVAL: u64 = 100;
+
+/// A simple wrapper around an `fwifc_file` handle.
+///
+/// Every call into sdfifc is made while holding the library's process-wide lock, so the handle
+/// can safely be moved to another thread.
+///
+/// sdfifc doesn't notice if the data file changes after it was indexed, so whenever we build an
+/// index we write a stamp file next to it, recording the data file's size and modification time.
+/// An index whose stamp doesn't match is stale, and is treated as missing. An index without a
+/// stamp, e.g. one built by sdfifc on its own, is trusted and gets a stamp when it's opened.
+#[derive(Debug)]
+pub struct Sdfifc {
+    handle: fwifc_file,
+    data_path: PathBuf,
+    index_path: PathBuf,
+}
+
+unsafe impl Send for Sdfifc {}
+
+impl Sdfifc {
+    /// Opens an .sdf data file with libsdfifc.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::backend::Sdfifc;
+    /// let backend = Sdfifc::open("data/110630_174316.sdf").unwrap();
+    /// ```
+    pub fn open<T: Into<Vec<u8>>>(path: T) -> Result<Sdfifc> {
+        let path = try!(CString::new(path));
+        Sdfifc::open_with_options(try!(path.to_str()), &OpenOptions::new())
+    }
+
+    /// Opens an .sdf data file with libsdfifc, putting its index where the options say.
+    ///
+    /// libsdfifc always puts the index next to the file it opened, so when the index goes
+    /// somewhere else we create a symlink to the data file next to the index, and open that. The
+    /// symlink is removed along with the index. On Windows, creating a symlink needs Developer
+    /// Mode or administrator rights, so without them only `IndexLocation::NextToData` works.
+    ///
+    /// If there's a stale index, it is either removed, so it will be rebuilt by the next
+    /// `reindex`, or we return `Error::StaleIndex`, depending on the options.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::backend::Sdfifc;
+    /// use sdf::file::OpenOptions;
+    /// let options = OpenOptions::new().index_dir("/tmp/sdf-indices");
+    /// let backend = Sdfifc::open_with_options("data/110630_174316.sdf", &options).unwrap();
+    /// ```
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: &OpenOptions) -> Result<Sdfifc> {
+        let path = path.as_ref();
+        let index_path = try!(index_path(path, options));
+        try!(check_index(path, &index_path, options.stale_index()));
+        let open_path = match link_path(path, &index_path) {
+            Some(link_path) => {
+                try!(create_link(path, &link_path));
+                link_path
+            }
+            None => path.to_path_buf(),
+        };
+        let _lock = lock();
+        unsafe {
+            let c_path = try!(CString::new(try!(to_str(&open_path))));
+            let mut file: fwifc_file = ptr::null_mut();
+            sdftry!(fwifc_open(c_path.as_ptr(), &mut file));
+            Ok(Sdfifc {
+                handle: file,
+                data_path: path.to_path_buf(),
+                index_path: index_path,
+            })
+        }
+    }
+}
+
+impl Sdfifc {
+    fn progress(&self, finished: bool) -> ReindexProgress {
+        ReindexProgress {
+            index_bytes: metadata(&self.index_path).map(|m| m.len()).unwrap_or(0),
+            data_bytes: metadata(&self.data_path).map(|m| m.len()).ok(),
+            finished: finished,
+        }
+    }
+
+    /// Puts back the symlink to the data file, if we use one and it was removed with the index.
+    fn restore_link(&self) -> Result<()> {
+        match link_path(&self.data_path, &self.index_path) {
+            Some(ref link_path) if fs::symlink_metadata(link_path).is_err() => {
+                create_link(&self.data_path, link_path)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn write_stamp(&self) -> Result<()> {
+        write_stamp(&self.data_path, &self.index_path)
+    }
+}
+
+/// A handle that can be moved to the thread that reindexes it.
+struct Handle(fwifc_file);
+
+unsafe impl Send for Handle {}
+
+fn reindex(handle: fwifc_file) -> Result<()> {
+    let _lock = lock();
+    unsafe { Ok(sdftry!(fwifc_reindex(handle))) }
+}
+
+/// Returns the path of the stamp file that goes with an index.
+fn stamp_path(index_path: &Path) -> PathBuf {
+    index_path.with_extension("idx.stamp")
+}
+
+/// Deals with an existing index before we open the data file.
+///
+/// A stale index is removed or is an error, depending on the policy, and an index without a stamp
+/// gets one.
+fn check_index(data_path: &Path, index_path: &Path, policy: StaleIndexPolicy) -> Result<()> {
+    if metadata(index_path).is_err() {
+        return Ok(());
+    }
+    if try!(is_stale(data_path, index_path)) {
+        match policy {
+            StaleIndexPolicy::Rebuild => {
+                info!("Removing stale index {}", index_path.display());
+                try!(remove_index_files(data_path, index_path));
+            }
+            StaleIndexPolicy::Fail => {
+                return Err(Error::StaleIndex(index_path.display().to_string()));
+            }
+        }
+    } else if metadata(stamp_path(index_path)).is_err() {
+        if let Err(err) = write_stamp(data_path, index_path) {
+            warn!("Unable to stamp index {}: {}", index_path.display(), err);
+        }
+    }
+    Ok(())
+}
+
+/// Records the data file as it is now in the index's stamp.
+fn write_stamp(data_path: &Path, index_path: &Path) -> Result<()> {
+    let stamp = try!(stamp(data_path));
+    try!(try!(fs::File::create(stamp_path(index_path))).write_all(stamp.as_bytes()));
+    Ok(())
+}
+
+/// Describes the data file as it is now, for comparison with the stamp.
+fn stamp(data_path: &Path) -> Result<String> {
+    let data_metadata = try!(metadata(data_path));
+    let modified = data_metadata.modified()
+                                .ok()
+                                .and_then(|m| m.duration_since(UNIX_EPOCH).ok())
+                                .map(|d| format!("{}.{:09}", d.as_secs(), d.subsec_nanos()))
+                                .unwrap_or_else(|| "unknown".to_string());
+    Ok(format!("size {}\nmodified {}\n", data_metadata.len(), modified))
+}
+
+/// Returns true if the index doesn't match the data file.
+///
+/// Empty indices are stale. Indices without a stamp, e.g. ones built by sdfifc on its own, might
+/// or might not match, so we give them the benefit of the doubt.
+fn is_stale(data_path: &Path, index_path: &Path) -> Result<bool> {
+    if try!(metadata(index_path)).len() == 0 {
+        return Ok(true);
+    }
+    let mut recorded = String::new();
+    match fs::File::open(stamp_path(index_path)) {
+        Ok(mut file) => {
+            let _ = try!(file.read_to_string(&mut recorded));
+        }
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => return Ok(false),
+        Err(err) => return Err(Error::from(err)),
+    }
+    Ok(recorded != try!(stamp(data_path)))
+}
+
+/// Removes an index, its stamp, and the symlink to its data file if there is one.
+fn remove_index_files(data_path: &Path, index_path: &Path) -> Result<()> {
+    try!(remove_file(index_path));
+    try!(remove_if_exists(&stamp_path(index_path)));
+    if let Some(link_path) = link_path(data_path, index_path) {
+        // Never remove the data file itself, just our link to it.
+        if fs::symlink_metadata(&link_path).map(|m| m.file_type().is_symlink()).unwrap_or(false) {
+            try!(remove_if_exists(&link_path));
+        }
+    }
+    Ok(())
+}
+
+fn remove_if_exists(path: &Path) -> Result<()> {
+    match remove_file(path) {
+        Err(ref err) if err.kind() == io::ErrorKind::NotFound => Ok(()),
+        result => result.map_err(Error::from),
+    }
+}
+
+/// Returns the path of the symlink to the data file that we open, if the index isn't next to the
+/// data file.
+fn link_path(data_path: &Path, index_path: &Path) -> Option<PathBuf> {
+    if index_path == data_path.with_extension("idx") {
+        None
+    } else {
+        Some(index_path.with_extension("sdf"))
+    }
+}
+
+/// Creates the directory for a symlink, and the symlink to the data file.
+fn create_link(data_path: &Path, link_path: &Path) -> Result<()> {
+    if let Some(parent) = link_path.parent() {
+        try!(fs::create_dir_all(parent));
+    }
+    try!(link(&try!(fs::canonicalize(data_path)), link_path));
+    Ok(())
+}
+
+/// Returns where the index for the data file at `path` should live.
+fn index_path(path: &Path, options: &OpenOptions) -> Result<PathBuf> {
+    let file_name = |extension| {
+        path.file_stem()
+            .map(|stem| Path::new(stem).with_extension(extension))
+            .ok_or_else(|| Error::InvalidOptions(format!("No file name: {}", path.display())))
+    };
+    match *options.index_location() {
+        IndexLocation::NextToData => Ok(path.with_extension("idx")),
+        IndexLocation::Directory(ref directory) => Ok(directory.join(try!(file_name("idx")))),
+        IndexLocation::Path(ref index_path) => {
+            if index_path.extension().map_or(false, |e| e == "idx") {
+                Ok(index_path.clone())
+            } else {
+                Err(Error::InvalidOptions(format!("Index paths must end in .idx: {}",
+                                                  index_path.display())))
+            }
+        }
+        IndexLocation::Cache(ref directory) => {
+            let key = try!(cache_key(path));
+            Ok(directory.join(format!("{:016x}", key)).join(try!(file_name("idx"))))
+        }
+    }
+}
+
+/// Hashes the data file's canonical path, size and modification time.
+///
+/// Reading the whole file would be too slow for multi-gigabyte data files, but this changes
+/// whenever the file is moved or replaced.
+fn cache_key(path: &Path) -> Result<u64> {
+    let data_metadata = try!(metadata(path));
+    let mut hasher = DefaultHasher::new();
+    try!(fs::canonicalize(path)).hash(&mut hasher);
+    data_metadata.len().hash(&mut hasher);
+    if let Ok(modified) = data_metadata.modified() {
+        if let Ok(duration) = modified.duration_since(UNIX_EPOCH) {
+            duration.hash(&mut hasher);
+        }
+    }
+    Ok(hasher.finish())
+}
+
+/// Creates (or replaces) a symlink at `path` that points to `target`.
+fn link(target: &Path, path: &Path) -> io::Result<()> {
+    match fs::read_link(path) {
+        Ok(ref existing) if existing == target => return Ok(()),
+        Ok(_) => try!(remove_file(path)),
+        Err(_) => {}
+    }
+    symlink(target, path)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    ::std::os::unix::fs::symlink(target, path)
+}
+
+#[cfg(windows)]
+fn symlink(target: &Path, path: &Path) -> io::Result<()> {
+    ::std::os::windows::fs::symlink_file(target, path)
+}
+
+fn to_str(path: &Path) -> Result<&str> {
+    path.to_str().ok_or_else(|| {
+        Error::InvalidOptions(format!("Path is not valid UTF-8: {}", path.display()))
+    })
+}
+
+impl Backend for Sdfifc {
+    fn reindex(&mut self) -> Result<()> {
+        try!(self.restore_link());
+        try!(reindex(self.handle));
+        self.write_stamp()
+    }
+
+    /// sdfifc blocks while it reindexes, so it runs on another thread while this one watches the
+    /// index grow. We always wait for that thread, since it's using our handle, so a cancel only
+    /// takes effect once sdfifc returns. Then the index files are removed, whether or not sdfifc
+    /// succeeded.
+    fn reindex_with_progress(&mut self,
+                             progress: &mut FnMut(ReindexProgress) -> bool)
+                             -> Result<()> {
+        if !progress(self.progress(false)) {
+            return Err(Error::Cancelled);
+        }
+        try!(self.restore_link());
+        let handle = Handle(self.handle);
+        let (sender, receiver) = channel();
+        let _ = thread::spawn(move || {
+            let Handle(handle) = handle;
+            let _ = sender.send(reindex(handle));
+        });
+        let mut cancelled = false;
+        let result;
+        loop {
+            match receiver.recv_timeout(Duration::from_millis(PROGRESS_INTERVAL)) {
+                Ok(r) => {
+                    result = r;
+                    break;
+                }
+                Err(RecvTimeoutError::Timeout) => {
+                    if !cancelled && !progress(self.progress(false)) {
+                        info!("Reindex cancelled, waiting for sdfifc to finish");
+                        cancelled = true;
+                    }
+                }
+                Err(RecvTimeoutError::Disconnected) => {
+                    result = Err(Error::Runtime("The reindexing thread panicked".to_string()));
+                    break;
+                }
+            }
+        }
+        if cancelled {
+            // Whether sdfifc finished or failed, the caller asked for no index, so don't leave a
+            // complete or partial one behind.
+            if let Err(err) = result {
+                info!("Reindex failed after it was cancelled: {}", err);
+            }
+            try!(remove_index_files(&self.data_path, &self.index_path));
+            return Err(Error::Cancelled);
+        }
+        try!(result);
+        try!(self.write_stamp());
+        let _ = progress(self.progress(true));
+        Ok(())
+    }
+
+    fn indexed(&self) -> bool {
+        metadata(&self.index_path).map(|m| m.is_file()).unwrap_or(false) &&
+        !is_stale(&self.data_path, &self.index_path).unwrap_or(true)
+    }
+
+    fn remove_index(&self) -> Result<()> {
+        remove_index_files(&self.data_path, &self.index_path)
+    }
+
+    fn set_sosbl_mode(&mut self, mode: SosblMode) -> Result<()> {
+        let _lock = lock();
+        unsafe {
+            let value = match mode {
+                SosblMode::Absolute => 0,
+                SosblMode::Relative => 1,
+            };
+            Ok(sdftry!(fwifc_set_sosbl_relative(self.handle, value)))
+        }
+    }
+
+    fn info(&mut self) -> Result<FileInfo> {
+        let _lock = lock();
+        unsafe {
+            let mut instrument: *const c_char = ptr::null_mut();
+            let mut serial: *const c_char = ptr::null_mut();
+            let mut epoch: *const c_char = ptr::null_mut();
+            let mut v_group = 0f64;
+            let mut sampling_time = 0f64;
+            let mut flags = 0u16;
+            let mut num_facets = 0u16;
+            sdftry!(fwifc_get_info(self.handle,
+                                   &mut instrument,
+                                   &mut serial,
+                                   &mut epoch,
+                                   &mut v_group,
+                                   &mut sampling_time,
+                                   &mut flags,
+                                   &mut num_facets));
+            Ok(FileInfo {
+                instrument: try!(CStr::from_ptr(instrument).to_str()).to_string(),
+                serial: try!(CStr::from_ptr(serial).to_str()).to_string(),
+                epoch: try!(CStr::from_ptr(epoch).to_str()).to_string(),
+                v_group: v_group,
+                sampling_time: sampling_time,
+                gps_synchronized: flags & 0x01 == 1,
+                num_facets: num_facets,
+            })
+        }
+    }
+
+    /// We manually copy all of the calibration info into new vectors because we can't really
+    /// trust the memory behind the fwifc call.
+    fn calibration(&mut self, kind: CalibrationTableKind) -> Result<Calibration> {
+        let _lock = lock();
+        unsafe {
+            let mut count = 0u32;
+            let mut abscissa: *const f64 = ptr::null_mut();
+            let mut ordinate: *const f64 = ptr::null_mut();
+            sdftry!(fwifc_get_calib(self.handle,
+                                    try!(kind.as_u16()),
+                                    &mut count,
+                                    &mut abscissa,
+                                    &mut ordinate));
+            let mut abscissa_vec = Vec::with_capacity(count as usize);
+            let mut ordinate_vec = Vec::with_capacity(count as usize);
+            for i in 0..count {
+                abscissa_vec.push(*abscissa.offset(i as isize));
+                ordinate_vec.push(*ordinate.offset(i as isize));
+            }
+            Ok(Calibration {
+                abscissa: abscissa_vec,
+                ordinate: ordinate_vec,
+            })
+        }
+    }
+
+    fn read(&mut self) -> Result<Record> {
+        let _lock = lock();
+        unsafe {
+            let mut time_sorg = 0.0;
+            let mut time_external = 0.0;
+            let mut origin = [0.0f64; 3];
+            let mut direction = [0.0f64; 3];
+            let mut flags = 0;
+            let mut facet = 0;
+            let mut sbl_count = 0;
+            let mut sbl_size = 0;
+            let mut sbl: *mut fwifc_sbl_t = ptr::null_mut();
+            sdftry!(fwifc_read(self.handle,
+                               &mut time_sorg,
+                               &mut time_external,
+                               origin.as_mut_ptr(),
+                               direction.as_mut_ptr(),
+                               &mut flags,
+                               &mut facet,
+                               &mut sbl_count,
+                               &mut sbl_size,
+                               &mut sbl));
+            let mut blocks = Vec::with_capacity(sbl_count as usize);
+            for i in 0..sbl_count {
+                let ref block = *sbl.offset(i as isize);
+                let mut samples = Vec::with_capacity(block.sample_count as usize);
+                for j in 0..block.sample_count {
+                    samples.push(*block.sample.offset(j as isize));
+                }
+                let channel = try!(Channel::from_u32(block.channel));
+                blocks.push(Block {
+                    time_sosbl: block.time_sosbl,
+                    channel: channel,
+                    samples: samples,
+                });
+            }
+            Ok(Record {
+                time_sorg: time_sorg,
+                time_external: time_external,
+                origin: origin,
+                direction: direction,
+                synchronized: flags & 0x01 == 1,
+                sync_lastsec: flags & 0x02 == 2,
+                housekeeping: flags & 0x04 == 4,
+                facet: facet,
+                blocks: blocks,
+            })
+        }
+    }
+
+    fn seek(&mut self, index: u32) -> Result<()> {
+        let _lock = lock();
+        unsafe { Ok(sdftry!(fwifc_seek(self.handle, index))) }
+    }
+
+    fn seek_time(&mut self, time: f64) -> Result<()> {
+        let _lock = lock();
+        unsafe { Ok(sdftry!(fwifc_seek_time(self.handle, time))) }
+    }
+
+    fn seek_time_external(&mut self, time: f64) -> Result<()> {
+        let _lock = lock();
+        unsafe { Ok(sdftry!(fwifc_seek_time_external(self.handle, time))) }
+    }
+
+    fn tell(&mut self) -> Result<u32> {
+        let mut index = 0u32;
+        let _lock = lock();
+        unsafe { sdftry!(fwifc_tell(self.handle, &mut index)) }
+        Ok(index)
+    }
+}
+
+impl Drop for Sdfifc {
+    fn drop(&mut self) {
+        let _lock = lock();
+        unsafe {
+            let result = fwifc_close(self.handle);
+            if result != 0 {
+                panic!("Error when closing file: {}", error_from_code(result));
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs::{self, File};
+    use std::io::Write;
+    use std::path::Path;
+
+    use error::Error;
+    use file::{OpenOptions, StaleIndexPolicy};
+
+    #[test]
+    fn index_paths() {
+        let data = Path::new("/data/flight/110630_174316.sdf");
+        assert_eq!(Path::new("/data/flight/110630_174316.idx"),
+                   index_path(data, &OpenOptions::new()).unwrap());
+        assert_eq!(Path::new("/indices/110630_174316.idx"),
+                   index_path(data, &OpenOptions::new().index_dir("/indices")).unwrap());
+        assert_eq!(Path::new("/indices/other.idx"),
+                   index_path(data, &OpenOptions::new().index_path("/indices/other.idx"))
+                       .unwrap());
+        assert!(index_path(data, &OpenOptions::new().index_path("/indices/other.txt")).is_err());
+    }
+
+    #[test]
+    fn cache_index_path() {
+        let directory = env::temp_dir().join("sdf-rs-cache-index-path");
+        fs::create_dir_all(&directory).unwrap();
+        let data = directory.join("flight.sdf");
+        let _ = File::create(&data).unwrap();
+        let options = OpenOptions::new().index_cache(directory.join("cache"));
+        let first = index_path(&data, &options).unwrap();
+        assert!(first.starts_with(directory.join("cache")));
+        assert_eq!(Some("flight.idx".as_ref()), first.file_name());
+        File::create(&data).unwrap().write_all(b"replaced").unwrap();
+        assert!(first != index_path(&data, &options).unwrap());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn replace_link() {
+        let directory = env::temp_dir().join("sdf-rs-replace-link");
+        fs::create_dir_all(&directory).unwrap();
+        let (a, b) = (directory.join("a.sdf"), directory.join("b.sdf"));
+        let link_path = directory.join("link.sdf");
+        link(&a, &link_path).unwrap();
+        link(&a, &link_path).unwrap();
+        assert_eq!(a, fs::read_link(&link_path).unwrap());
+        link(&b, &link_path).unwrap();
+        assert_eq!(b, fs::read_link(&link_path).unwrap());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn stale_index() {
+        let directory = env::temp_dir().join("sdf-rs-stale-index");
+        fs::create_dir_all(&directory).unwrap();
+        let (data, index) = (directory.join("flight.sdf"), directory.join("flight.idx"));
+        File::create(&data).unwrap().write_all(b"data").unwrap();
+        File::create(&index).unwrap().write_all(b"index").unwrap();
+        assert!(!is_stale(&data, &index).unwrap());
+        write_stamp(&data, &index).unwrap();
+        assert!(!is_stale(&data, &index).unwrap());
+        File::create(&data).unwrap().write_all(b"more data").unwrap();
+        assert!(is_stale(&data, &index).unwrap());
+        remove_index_files(&data, &index).unwrap();
+        assert!(data.exists());
+        assert!(!index.exists());
+        assert!(!stamp_path(&index).exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn unstamped_index() {
+        let directory = env::temp_dir().join("sdf-rs-unstamped-index");
+        fs::create_dir_all(&directory).unwrap();
+        let (data, index) = (directory.join("flight.sdf"), directory.join("flight.idx"));
+        File::create(&data).unwrap().write_all(b"data").unwrap();
+        File::create(&index).unwrap().write_all(b"index").unwrap();
+        check_index(&data, &index, StaleIndexPolicy::Fail).unwrap();
+        assert!(index.exists());
+        assert!(stamp_path(&index).exists());
+        File::create(&data).unwrap().write_all(b"more data").unwrap();
+        match check_index(&data, &index, StaleIndexPolicy::Fail) {
+            Err(Error::StaleIndex(_)) => {}
+            result => panic!("Expected a stale index, got {:?}", result),
+        }
+        check_index(&data, &index, StaleIndexPolicy::Rebuild).unwrap();
+        assert!(!index.exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn remove_link() {
+        let directory = env::temp_dir().join("sdf-rs-remove-link");
+        fs::create_dir_all(&directory).unwrap();
+        let data = directory.join("flight.sdf");
+        File::create(&data).unwrap().write_all(b"data").unwrap();
+        let index = directory.join("indices").join("flight.idx");
+        let link_path = link_path(&data, &index).unwrap();
+        create_link(&data, &link_path).unwrap();
+        File::create(&index).unwrap().write_all(b"index").unwrap();
+        remove_index_files(&data, &index).unwrap();
+        assert!(fs::symlink_metadata(&link_path).is_err());
+        assert!(data.exists());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+
+    #[test]
+    fn empty_index_is_stale() {
+        let directory = env::temp_dir().join("sdf-rs-empty-index");
+        fs::create_dir_all(&directory).unwrap();
+        let (data, index) = (directory.join("flight.sdf"), directory.join("flight.idx"));
+        File::create(&data).unwrap().write_all(b"data").unwrap();
+        let _ = File::create(&index).unwrap();
+        File::create(stamp_path(&index))
+            .unwrap()
+            .write_all(stamp(&data).unwrap().as_bytes())
+            .unwrap();
+        assert!(is_stale(&data, &index).unwrap());
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -1,30 +1,448 @@
 //! Convert sdf files to other formats.
 
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::fs;
+use std::io::Read;
 use std::iter::repeat;
+use std::path::Path;
 
 use peakbag::{PeakDetector, Peak};
+use rustc_serialize::{Decodable, json};
+use toml;
 
 use Result;
 use error::Error;
-use file::{Block, Channel, FileInfo, Record};
+use backend::Backend;
+use deconvolve;
+use file::{Calibration, CalibrationTableKind, Channel, File, FileInfo, Record};
+use gaussian::{self, Echo};
 
-const HIGH_WIDTH: usize = 2;
-const HIGH_FLOOR: u16 = 15;
-const HIGH_CEILING: u16 = 255;
-const LOW_WIDTHS: (usize, usize) = (3, 2);
-const LOW_FLOOR: u16 = 15;
-const LOW_CEILINGS: (u16, u16) = (250, 255); // heh
-const MIN_HEIGHT_ABOVE_BACKGROUND: f64 = 5.0;
-const MAX_KURTOSIS: f64 = 0.04;
+/// Settings for one channel's peak detector.
+///
+/// These map directly onto `peakbag::PeakDetector`.
+#[derive(Clone, Copy, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct DetectorOptions {
+    /// The number of samples on each side of a peak that must be lower than the peak.
+    pub width: usize,
+    /// Peaks must be above this value.
+    pub floor: u16,
+    /// Peaks must be at or below this value.
+    pub ceiling: u16,
+    /// If provided, samples at or above this value are treated as saturated.
+    pub saturation: Option<u16>,
+    /// The minimum height of a peak above the background level.
+    pub min_height_above_background: f64,
+    /// The maximum kurtosis of a peak.
+    pub max_kurtosis: f64,
+}
 
-/// Turns a single sdf record into zero or more `Point`s.
+impl DetectorOptions {
+    /// Creates new detector options with the default height and kurtosis limits and no
+    /// saturation.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::convert::DetectorOptions;
+    /// let options = DetectorOptions::new(2, 15, 255);
+    /// ```
+    pub fn new(width: usize, floor: u16, ceiling: u16) -> DetectorOptions {
+        DetectorOptions {
+            width: width,
+            floor: floor,
+            ceiling: ceiling,
+            saturation: None,
+            min_height_above_background: 5.0,
+            max_kurtosis: 0.04,
+        }
+    }
+
+    /// Sets the saturation level.
+    pub fn saturation(mut self, saturation: u16) -> DetectorOptions {
+        self.saturation = Some(saturation);
+        self
+    }
+
+    /// Sets the minimum height of a peak above the background level.
+    pub fn min_height_above_background(mut self, height: f64) -> DetectorOptions {
+        self.min_height_above_background = height;
+        self
+    }
+
+    /// Sets the maximum kurtosis of a peak.
+    pub fn max_kurtosis(mut self, kurtosis: f64) -> DetectorOptions {
+        self.max_kurtosis = kurtosis;
+        self
+    }
+
+    fn merge(mut self, options_file: DetectorOptionsFile) -> DetectorOptions {
+        if let Some(width) = options_file.width {
+            self.width = width;
+        }
+        if let Some(floor) = options_file.floor {
+            self.floor = floor;
+        }
+        if let Some(ceiling) = options_file.ceiling {
+            self.ceiling = ceiling;
+        }
+        if let Some(saturation) = options_file.saturation {
+            self.saturation = Some(saturation);
+        }
+        if let Some(height) = options_file.min_height_above_background {
+            self.min_height_above_background = height;
+        }
+        if let Some(kurtosis) = options_file.max_kurtosis {
+            self.max_kurtosis = kurtosis;
+        }
+        self
+    }
+
+    fn detector(&self) -> PeakDetector<u16> {
+        let detector = PeakDetector::new(self.width, self.floor, self.ceiling)
+                           .min_height_above_background(self.min_height_above_background)
+                           .max_kurtosis(self.max_kurtosis);
+        match self.saturation {
+            Some(saturation) => detector.saturation(saturation),
+            None => detector,
+        }
+    }
+}
+
+/// Settings for estimating reflectance from calibrated amplitude.
+///
+/// Reflectance is the calibrated amplitude relative to that of a white, diffuse target at the same
+/// range. The white target's amplitude is `reference_amplitude` at `reference_range`, and falls
+/// off with the square of the range:
+///
+/// ```text
+/// reflectance = amplitude + 20 * log10(range / reference_range) - reference_amplitude
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, RustcDecodable, RustcEncodable)]
+pub struct ReflectanceOptions {
+    /// The range, in meters, at which `reference_amplitude` was measured.
+    pub reference_range: f64,
+    /// The calibrated amplitude, in dB, of a white diffuse target at `reference_range`.
+    pub reference_amplitude: f64,
+}
+
+impl ReflectanceOptions {
+    /// Creates new reflectance options.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::convert::ReflectanceOptions;
+    /// let options = ReflectanceOptions::new(100.0, 30.0);
+    /// ```
+    pub fn new(reference_range: f64, reference_amplitude: f64) -> ReflectanceOptions {
+        ReflectanceOptions {
+            reference_range: reference_range,
+            reference_amplitude: reference_amplitude,
+        }
+    }
+
+    /// Estimates the reflectance, in dB, of a target with the given calibrated amplitude (dB) and
+    /// range (m).
+    ///
+    /// Returns `None` if the range isn't positive.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::convert::ReflectanceOptions;
+    /// let options = ReflectanceOptions::new(100.0, 30.0);
+    /// assert_eq!(Some(-5.0), options.reflectance(25.0, 100.0));
+    /// ```
+    pub fn reflectance(&self, amplitude: f64, range: f64) -> Option<f64> {
+        if range > 0.0 {
+            Some(amplitude + 20.0 * (range / self.reference_range).log10() -
+                 self.reference_amplitude)
+        } else {
+            None
+        }
+    }
+}
+
+/// Options that control how records are discretized.
+///
+/// The defaults were tuned for a Q680i dataset. Options can be built up in code:
+///
+/// ```
+/// use sdf::convert::{DetectorOptions, DiscretizeOptions};
+/// let options = DiscretizeOptions::default().high(DetectorOptions::new(3, 20, 255));
+/// ```
+///
+/// Or loaded from a TOML or JSON file with `DiscretizeOptions::from_path`.
+#[derive(Clone, Debug)]
+pub struct DiscretizeOptions {
+    high: DetectorOptions,
+    low: DetectorOptions,
+    low_with_high: DetectorOptions,
+    reference: DetectorOptions,
+    gaussian_decomposition: bool,
+    deconvolution_iterations: Option<usize>,
+    calibrations: HashMap<CalibrationTableKind, Calibration>,
+    range_correction: bool,
+    reflectance: Option<ReflectanceOptions>,
+}
+
+/// The on-disk representation of `DiscretizeOptions`, where everything is optional.
+#[derive(Debug, RustcDecodable)]
+struct OptionsFile {
+    high: Option<DetectorOptionsFile>,
+    low: Option<DetectorOptionsFile>,
+    low_with_high: Option<DetectorOptionsFile>,
+    reference: Option<DetectorOptionsFile>,
+    gaussian_decomposition: Option<bool>,
+    deconvolution_iterations: Option<usize>,
+    range_correction: Option<bool>,
+    reflectance: Option<ReflectanceOptions>,
+}
+
+/// The on-disk representation of `DetectorOptions`, where everything is optional.
+#[derive(Debug, RustcDecodable)]
+struct DetectorOptionsFile {
+    width: Option<usize>,
+    floor: Option<u16>,
+    ceiling: Option<u16>,
+    saturation: Option<u16>,
+    min_height_above_background: Option<f64>,
+    max_kurtosis: Option<f64>,
+}
+
+impl Default for DiscretizeOptions {
+    fn default() -> DiscretizeOptions {
+        DiscretizeOptions {
+            high: DetectorOptions::new(2, 15, 255),
+            low: DetectorOptions::new(3, 15, 250).saturation(255),
+            low_with_high: DetectorOptions::new(2, 15, 255).saturation(255),
+            reference: DetectorOptions::new(2, 15, 255),
+            gaussian_decomposition: false,
+            deconvolution_iterations: None,
+            calibrations: HashMap::new(),
+            range_correction: true,
+            reflectance: None,
+        }
+    }
+}
+
+impl DiscretizeOptions {
+    /// Loads options from a TOML (.toml) or JSON (.json) file.
+    ///
+    /// Each channel's detector is a table (or object) with the same fields as
+    /// `DetectorOptions`. Channels and fields that aren't in the file keep their default settings,
+    /// so this only raises the high channel's floor and widens the low channel's detector:
+    ///
+    /// ```toml
+    /// [high]
+    /// floor = 20
+    ///
+    /// [low]
+    /// width = 4
+    /// max_kurtosis = 0.1
+    /// ```
+    ///
+    /// The channels are `high`, `low`, `low_with_high` (used for the low channel when the record
+    /// also has high channel blocks) and `reference`. Gaussian decomposition is turned on with a
+    /// top-level `gaussian_decomposition = true`, and deconvolution with e.g.
+    /// `deconvolution_iterations = 20`. Range correction can be turned off with
+    /// `range_correction = false`. Reflectance settings go in a `[reflectance]` table with
+    /// the same fields as `ReflectanceOptions`. Calibration tables aren't read from options files;
+    /// use `load_calibrations` to get them from the .sdf file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<DiscretizeOptions> {
+        let mut s = String::new();
+        let _ = try!(try!(fs::File::open(&path)).read_to_string(&mut s));
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("toml") => DiscretizeOptions::from_toml(&s),
+            Some("json") => DiscretizeOptions::from_json(&s),
+            _ => {
+                Err(Error::InvalidOptions(format!("Unknown options file extension: {}",
+                                                  path.as_ref().display())))
+            }
+        }
+    }
+
+    /// Parses options from a TOML string.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::convert::DiscretizeOptions;
+    /// let options = DiscretizeOptions::from_toml("[high]
+    /// width = 3
+    /// floor = 20").unwrap();
+    /// ```
+    pub fn from_toml(s: &str) -> Result<DiscretizeOptions> {
+        let mut parser = toml::Parser::new(s);
+        let table = match parser.parse() {
+            Some(table) => table,
+            None => {
+                let messages = parser.errors.iter().map(|e| e.to_string()).collect::<Vec<_>>();
+                return Err(Error::InvalidOptions(messages.join(", ")));
+            }
+        };
+        let mut decoder = toml::Decoder::new(toml::Value::Table(table));
+        let options_file = try!(OptionsFile::decode(&mut decoder)
+                                    .map_err(|e| Error::InvalidOptions(e.to_string())));
+        Ok(DiscretizeOptions::default().merge(options_file))
+    }
+
+    /// Parses options from a JSON string.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::convert::DiscretizeOptions;
+    /// let options = DiscretizeOptions::from_json(r#"{"reference": {"floor": 10}}"#).unwrap();
+    /// ```
+    pub fn from_json(s: &str) -> Result<DiscretizeOptions> {
+        let options_file = try!(json::decode::<OptionsFile>(s)
+                                    .map_err(|e| Error::InvalidOptions(e.to_string())));
+        Ok(DiscretizeOptions::default().merge(options_file))
+    }
+
+    /// Sets the high channel's detector options.
+    pub fn high(mut self, options: DetectorOptions) -> DiscretizeOptions {
+        self.high = options;
+        self
+    }
+
+    /// Sets the low channel's detector options, used when the record has no high channel blocks.
+    pub fn low(mut self, options: DetectorOptions) -> DiscretizeOptions {
+        self.low = options;
+        self
+    }
+
+    /// Sets the low channel's detector options, used when the record also has high channel
+    /// blocks.
+    pub fn low_with_high(mut self, options: DetectorOptions) -> DiscretizeOptions {
+        self.low_with_high = options;
+        self
+    }
+
+    /// Sets the reference channel's detector options.
+    pub fn reference(mut self, options: DetectorOptions) -> DiscretizeOptions {
+        self.reference = options;
+        self
+    }
+
+    /// Fits a sum of Gaussians to each waveform, seeded with the detected peaks.
+    ///
+    /// When enabled, times and ranges are computed from the fitted echo centers, rather than the
+    /// peaks' sample indices, and each point carries its fitted `Echo`.
+    pub fn gaussian_decomposition(mut self, enabled: bool) -> DiscretizeOptions {
+        self.gaussian_decomposition = enabled;
+        self
+    }
+
+    /// Deconvolves the high and low channel waveforms before peak detection.
+    ///
+    /// The record's reference block is used as the system's impulse response, and
+    /// `iterations` Richardson-Lucy iterations are run on each waveform. See
+    /// `deconvolve::richardson_lucy`. Peaks are detected on the deconvolved waveform, but each
+    /// point's amplitude is still the raw sample at its peak.
+    pub fn deconvolution(mut self, iterations: usize) -> DiscretizeOptions {
+        self.deconvolution_iterations = Some(iterations);
+        self
+    }
+
+    /// Sets a calibration table.
+    ///
+    /// When an amplitude table is set for a channel, points from that channel get a calibrated
+    /// amplitude. It's looked up from the fitted echo's amplitude if the waveform was decomposed
+    /// (and not deconvolved), and from the raw peak amplitude otherwise.
+    ///
+    /// When a range table is set for a channel, and range correction is enabled, the table's
+    /// ordinate is the range correction in meters for a given raw peak amplitude, and is added to
+    /// the points' ranges.
+    pub fn calibration(mut self,
+                       kind: CalibrationTableKind,
+                       calibration: Calibration)
+                       -> DiscretizeOptions {
+        let _ = self.calibrations.insert(kind, calibration);
+        self
+    }
+
+    /// Loads the high and low channel amplitude and range calibration tables from an .sdf file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::convert::DiscretizeOptions;
+    /// use sdf::file::File;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let options = DiscretizeOptions::default().load_calibrations(&mut file).unwrap();
+    /// ```
+    pub fn load_calibrations<B: Backend>(mut self,
+                                         file: &mut File<B>)
+                                         -> Result<DiscretizeOptions> {
+        for &channel in &[Channel::High, Channel::Low] {
+            for &kind in &[CalibrationTableKind::Amplitude(channel),
+                           CalibrationTableKind::Range(channel)] {
+                let calibration = try!(file.calibration(kind));
+                self = self.calibration(kind, calibration);
+            }
+        }
+        Ok(self)
+    }
+
+    /// Enables or disables range correction, which is on by default.
+    ///
+    /// Range correction is only applied to channels that have a range calibration table, and the
+    /// default options have no tables, so on its own this does nothing; use `load_calibrations`
+    /// or `calibration` to provide them. Turning it off lets you compare corrected and
+    /// uncorrected points from the same tables.
+    pub fn range_correction(mut self, enabled: bool) -> DiscretizeOptions {
+        self.range_correction = enabled;
+        self
+    }
+
+    /// Estimates each point's reflectance from its calibrated amplitude.
+    ///
+    /// Only points with a calibrated amplitude get a reflectance, so this needs amplitude
+    /// calibration tables too.
+    pub fn reflectance(mut self, reflectance: ReflectanceOptions) -> DiscretizeOptions {
+        self.reflectance = Some(reflectance);
+        self
+    }
+
+    fn merge(mut self, options_file: OptionsFile) -> DiscretizeOptions {
+        if let Some(high) = options_file.high {
+            self.high = self.high.merge(high);
+        }
+        if let Some(low) = options_file.low {
+            self.low = self.low.merge(low);
+        }
+        if let Some(low_with_high) = options_file.low_with_high {
+            self.low_with_high = self.low_with_high.merge(low_with_high);
+        }
+        if let Some(reference) = options_file.reference {
+            self.reference = self.reference.merge(reference);
+        }
+        if let Some(gaussian_decomposition) = options_file.gaussian_decomposition {
+            self.gaussian_decomposition = gaussian_decomposition;
+        }
+        if let Some(iterations) = options_file.deconvolution_iterations {
+            self.deconvolution_iterations = Some(iterations);
+        }
+        if let Some(range_correction) = options_file.range_correction {
+            self.range_correction = range_correction;
+        }
+        if let Some(reflectance) = options_file.reflectance {
+            self.reflectance = Some(reflectance);
+        }
+        self
+    }
+}
+
+/// Turns a single sdf record into zero or more `Point`s, using the default options.
 ///
 /// At this point, we assume that the timestamps are absolute. TODO make this smarter to handle the
 /// case when the user has called `File::set_sosbl_mode(SosblMode::Relative)`.
 ///
-/// # Panics
-///
-/// Panics if there are zero or more than one reference blocks in the record.
+/// Returns an error if the record doesn't have exactly one reference block, e.g. if it's a
+/// housekeeping record.
 ///
 /// # Examples
 ///
@@ -38,36 +456,52 @@ const MAX_KURTOSIS: f64 = 0.04;
 /// let points = discretize(record, file_info).unwrap();
 /// ```
 pub fn discretize(record: &Record, file_info: &FileInfo) -> Result<Vec<Point>> {
+    discretize_with_options(record, file_info, &DiscretizeOptions::default())
+}
+
+/// Turns a single sdf record into zero or more `Point`s.
+///
+/// Returns `Error::MissingChannel` if the record has no reference block, and
+/// `Error::MultipleReferenceBlocks` if it has more than one.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::convert::{DetectorOptions, DiscretizeOptions, discretize_with_options};
+/// use sdf::file::File;
+/// let mut file = File::open("data/110630_174316.sdf").unwrap();
+/// let ref file_info = file.info().unwrap();
+/// file.reindex().unwrap();
+/// let ref record = file.read().unwrap();
+/// let options = DiscretizeOptions::default().high(DetectorOptions::new(3, 20, 255));
+/// let points = discretize_with_options(record, file_info, &options).unwrap();
+/// ```
+pub fn discretize_with_options(record: &Record,
+                               file_info: &FileInfo,
+                               options: &DiscretizeOptions)
+                               -> Result<Vec<Point>> {
     let mut high_blocks = Vec::new();
     let mut low_blocks = Vec::new();
-    let mut reference_block = None;
-    for block in &record.blocks {
+    let mut reference_blocks = Vec::new();
+    for (index, block) in record.blocks.iter().enumerate() {
         match block.channel {
-            Channel::High => high_blocks.push(block),
-            Channel::Low => low_blocks.push(block),
-            Channel::Reference => {
-                if reference_block.is_some() {
-                    panic!("More than one reference block in this record");
-                } else {
-                    reference_block = Some(block);
-                }
-            }
+            Channel::High => high_blocks.push((index, block)),
+            Channel::Low => low_blocks.push((index, block)),
+            Channel::Reference => reference_blocks.push(block),
             _ => {}
         }
     }
-    let reference_block = reference_block.unwrap();
-    let high_detector = PeakDetector::new(HIGH_WIDTH, HIGH_FLOOR, HIGH_CEILING)
-                            .min_height_above_background(MIN_HEIGHT_ABOVE_BACKGROUND)
-                            .max_kurtosis(MAX_KURTOSIS);
-    let (low_width, low_ceiling) = match high_blocks.len() {
-        0 => (LOW_WIDTHS.0, LOW_CEILINGS.0),
-        _ => (LOW_WIDTHS.1, LOW_CEILINGS.1),
+    let reference_block = match reference_blocks.len() {
+        0 => return Err(Error::MissingChannel(Channel::Reference)),
+        1 => reference_blocks[0],
+        n => return Err(Error::MultipleReferenceBlocks(n)),
+    };
+    let high_detector = options.high.detector();
+    let low_detector = match high_blocks.len() {
+        0 => options.low.detector(),
+        _ => options.low_with_high.detector(),
     };
-    let low_detector = PeakDetector::new(low_width, LOW_FLOOR, low_ceiling)
-                           .min_height_above_background(MIN_HEIGHT_ABOVE_BACKGROUND)
-                           .max_kurtosis(MAX_KURTOSIS)
-                           .saturation(LOW_CEILINGS.1);
-    let reference_detector = high_detector;
+    let reference_detector = options.reference.detector();
 
     let reference_peaks = reference_detector.detect_peaks(&reference_block.samples[..]);
     if reference_peaks.len() != 1 {
@@ -75,23 +509,71 @@ pub fn discretize(record: &Record, file_info: &FileInfo) -> Result<Vec<Point>> {
                reference_block.samples);
         return Err(Error::NeedSingleReferencePeak(reference_peaks.len()));
     }
-    let ref reference_peak = reference_peaks[0];
-
-    let timestamp = |peak: &Peak<_>, block: &Block| {
-        block.time_sosbl + peak.index as f64 * file_info.sampling_time
+    // Returns each peak with its fitted echo, if we're decomposing.
+    let fit = |samples: &[u16], peaks: Vec<Peak<u16>>| -> Vec<(Peak<u16>, Option<Echo>)> {
+        if options.gaussian_decomposition {
+            let seeds = peaks.iter().map(|p| p.index).collect::<Vec<_>>();
+            let echoes = gaussian::decompose(samples, &seeds);
+            peaks.into_iter().zip(echoes.into_iter().map(Some)).collect()
+        } else {
+            peaks.into_iter().zip(repeat(None)).collect()
+        }
+    };
+    let position = |peak: &Peak<u16>, echo: &Option<Echo>| {
+        match *echo {
+            Some(ref echo) => echo.position,
+            None => peak.index as f64,
+        }
     };
 
-    let t_ref = timestamp(reference_peak, &reference_block);
+    let (ref reference_peak, ref reference_echo) = fit(&reference_block.samples[..],
+                                                       reference_peaks)[0];
+    let t_ref = reference_block.time_sosbl +
+                position(reference_peak, reference_echo) * file_info.sampling_time;
 
     let mut points = Vec::new();
-    for (block, detector) in low_blocks.iter()
-                                       .zip(repeat(low_detector))
-                                       .chain(high_blocks.iter().zip(repeat(high_detector))) {
-        let peaks = detector.detect_peaks(&block.samples[..]);
+    for (&(index, block), detector) in low_blocks.iter()
+                                                 .zip(repeat(low_detector))
+                                                 .chain(high_blocks.iter()
+                                                                   .zip(repeat(high_detector))) {
+        let samples = match options.deconvolution_iterations {
+            Some(iterations) => {
+                Cow::Owned(deconvolve::richardson_lucy(&block.samples[..],
+                                                       &reference_block.samples[..],
+                                                       iterations))
+            }
+            None => Cow::Borrowed(&block.samples[..]),
+        };
+        let peaks = fit(&samples, detector.detect_peaks(&samples));
         let num_target = peaks.len();
-        for (i, peak) in peaks.into_iter().enumerate() {
-            let time = timestamp(&peak, block);
-            let range = file_info.v_group / 2.0 * (time - t_ref);
+        for (i, (mut peak, echo)) in peaks.into_iter().enumerate() {
+            // Deconvolved samples are rescaled, so always take the amplitude from the raw ones.
+            peak.amplitude = block.samples[peak.index];
+            let time = block.time_sosbl + position(&peak, &echo) * file_info.sampling_time;
+            let mut range = file_info.v_group / 2.0 * (time - t_ref);
+            if options.range_correction {
+                let kind = CalibrationTableKind::Range(block.channel);
+                if let Some(calibration) = options.calibrations.get(&kind) {
+                    // Range walk depends on the raw return strength, so this is never the echo's
+                    // amplitude.
+                    range += calibration.interpolate(peak.amplitude as f64).unwrap_or(0.0);
+                }
+            }
+            // A fitted echo's amplitude is less noisy than a single sample, but it's only on the
+            // raw samples' scale if it was fit to the raw samples.
+            let amplitude = match echo {
+                Some(ref echo) if options.deconvolution_iterations.is_none() => echo.amplitude,
+                _ => peak.amplitude as f64,
+            };
+            let calibrated_amplitude = options.calibrations
+                                              .get(&CalibrationTableKind::Amplitude(block.channel))
+                                              .and_then(|c| c.interpolate(amplitude));
+            let reflectance = match (calibrated_amplitude, options.reflectance) {
+                (Some(amplitude), Some(ref reflectance)) => {
+                    reflectance.reflectance(amplitude, range)
+                }
+                _ => None,
+            };
             // x is straight out of the scanner, and the mirror pans it along the
             // z axis.
             let theta = (record.direction[2] / record.direction[0])
@@ -108,7 +590,12 @@ pub fn discretize(record: &Record, file_info: &FileInfo) -> Result<Vec<Point>> {
                 num_target: num_target as u8,
                 facet: record.facet,
                 peak: peak,
+                echo: echo,
+                calibrated_amplitude: calibrated_amplitude.map(|a| a as f32),
+                reflectance: reflectance.map(|r| r as f32),
+                mta_zone: 1,
                 high_channel: block.channel == Channel::High,
+                block: index,
             };
             points.push(point);
         }
@@ -139,17 +626,40 @@ pub struct Point {
     pub num_target: u8,
     /// The mirror facet used to reflect the laser energy.
     pub facet: u16,
-    /// The raw peak information returned from `peakbag`.
+    /// The peak information returned from `peakbag`. The amplitude is always the raw sample at the
+    /// peak, even if the peak was detected on a deconvolved waveform.
     pub peak: Peak<u16>,
+    /// The fitted Gaussian echo, if the record was discretized with Gaussian decomposition.
+    pub echo: Option<Echo>,
+    /// The calibrated amplitude in dB, if there was an amplitude calibration table for this
+    /// point's channel. See `DiscretizeOptions::calibration` for which amplitude is calibrated.
+    pub calibrated_amplitude: Option<f32>,
+    /// The estimated reflectance in dB, if reflectance estimation was enabled and the point has a
+    /// calibrated amplitude.
+    pub reflectance: Option<f32>,
     /// Was this point collected on the high channel?
     pub high_channel: bool,
+    /// The index of the sample block, in the record's `blocks`, that this point was detected in.
+    pub block: usize,
+    /// The multiple-time-around zone of this point, starting at one. `discretize` always assumes
+    /// zone one, i.e. that echoes belong to the pulse in their own record; use
+    /// `mta::MtaResolver` to resolve the actual zone.
+    pub mta_zone: u8,
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
-    use file::File;
+    use backend::{test_info, test_record};
+    use error::Error;
+    use file::{Channel, File, Record};
+
+    fn two_echoes() -> Record {
+        let mut record = test_record(1.0);
+        record.blocks[1].samples = vec![0, 10, 30, 80, 40, 35, 50, 20, 5, 0, 0];
+        record
+    }
 
     #[test]
     fn first_point() {
@@ -162,6 +672,165 @@ mod tests {
         assert_eq!(409397.90336020273, points[0].time);
     }
 
+    #[test]
+    fn reference_blocks() {
+        let mut record = two_echoes();
+        let reference = record.blocks.remove(0);
+        match discretize(&record, &test_info()) {
+            Err(Error::MissingChannel(Channel::Reference)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+        record.blocks.push(reference.clone());
+        record.blocks.push(reference);
+        match discretize(&record, &test_info()) {
+            Err(Error::MultipleReferenceBlocks(2)) => {}
+            result => panic!("Unexpected result: {:?}", result),
+        }
+    }
+
+    #[test]
+    fn options_from_toml() {
+        let options = DiscretizeOptions::from_toml("[low]
+width = 4
+floor = 10
+ceiling = 240
+saturation = 250
+min_height_above_background = 3.0
+max_kurtosis = 0.1
+")
+                          .unwrap();
+        assert_eq!(DetectorOptions::new(4, 10, 240)
+                       .saturation(250)
+                       .min_height_above_background(3.0)
+                       .max_kurtosis(0.1),
+                   options.low);
+        assert_eq!(DiscretizeOptions::default().high, options.high);
+    }
+
+    #[test]
+    fn options_from_json() {
+        let options = DiscretizeOptions::from_json(r#"{"high": {"width": 3, "floor": 20,
+            "ceiling": 255, "min_height_above_background": 5.0, "max_kurtosis": 0.04}}"#)
+                          .unwrap();
+        assert_eq!(DetectorOptions::new(3, 20, 255), options.high);
+        assert_eq!(DiscretizeOptions::default().low, options.low);
+    }
+
+    #[test]
+    fn gaussian_decomposition_option() {
+        assert!(!DiscretizeOptions::default().gaussian_decomposition);
+        let options = DiscretizeOptions::from_toml("gaussian_decomposition = true").unwrap();
+        assert!(options.gaussian_decomposition);
+        assert_eq!(DiscretizeOptions::default().low, options.low);
+    }
+
+    #[test]
+    fn deconvolution_option() {
+        assert_eq!(None, DiscretizeOptions::default().deconvolution_iterations);
+        let options = DiscretizeOptions::from_json(r#"{"deconvolution_iterations": 20}"#).unwrap();
+        assert_eq!(Some(20), options.deconvolution_iterations);
+    }
+
+    #[test]
+    fn reflectance_option() {
+        let options = DiscretizeOptions::from_toml("[reflectance]
+reference_range = 100.0
+reference_amplitude = 30.0
+")
+                          .unwrap();
+        assert_eq!(Some(ReflectanceOptions::new(100.0, 30.0)), options.reflectance);
+        let reflectance = options.reflectance.unwrap();
+        assert_eq!(Some(-10.0), reflectance.reflectance(26.0, 50.0).map(|r| r.round()));
+        assert_eq!(None, reflectance.reflectance(26.0, 0.0));
+    }
+
+    #[test]
+    fn range_correction_option() {
+        assert!(DiscretizeOptions::default().range_correction);
+        let options = DiscretizeOptions::from_toml("range_correction = false").unwrap();
+        assert!(!options.range_correction);
+    }
+
+    #[test]
+    fn partial_options() {
+        let options = DiscretizeOptions::from_toml("[high]\nwidth = 4").unwrap();
+        assert_eq!(DetectorOptions::new(4, 15, 255), options.high);
+        let options = DiscretizeOptions::from_json(r#"{"low": {"floor": 20}}"#).unwrap();
+        assert_eq!(DetectorOptions::new(3, 20, 250).saturation(255), options.low);
+        assert_eq!(DiscretizeOptions::default().high, options.high);
+    }
+
+    #[test]
+    fn bad_options() {
+        assert!(DiscretizeOptions::from_toml("[high]\nwidth = \"two\"").is_err());
+        assert!(DiscretizeOptions::from_json("{").is_err());
+        assert!(DiscretizeOptions::from_path("Cargo.toml.bak").is_err());
+    }
+
+    #[test]
+    fn deconvolved_amplitudes() {
+        let record = two_echoes();
+        let raw = discretize(&record, &test_info()).unwrap();
+        let options = DiscretizeOptions::default().deconvolution(20);
+        let deconvolved = discretize_with_options(&record, &test_info(), &options).unwrap();
+        assert!(!deconvolved.is_empty());
+        for point in &deconvolved {
+            assert_eq!(record.blocks[1].samples[point.peak.index], point.peak.amplitude);
+        }
+        for point in &raw {
+            if let Some(other) = deconvolved.iter().find(|p| p.peak.index == point.peak.index) {
+                assert_eq!(point.peak.amplitude, other.peak.amplitude);
+            }
+        }
+    }
+
+    #[test]
+    fn calibrated_amplitudes() {
+        let record = two_echoes();
+        let identity = Calibration {
+            abscissa: vec![0.0, 1000.0],
+            ordinate: vec![0.0, 1000.0],
+        };
+        let options = DiscretizeOptions::default()
+                          .calibration(CalibrationTableKind::Amplitude(Channel::High), identity);
+        for point in discretize_with_options(&record, &test_info(), &options).unwrap() {
+            assert_eq!(Some(point.peak.amplitude as f32), point.calibrated_amplitude);
+        }
+        let decomposed = options.clone().gaussian_decomposition(true);
+        for point in discretize_with_options(&record, &test_info(), &decomposed).unwrap() {
+            let echo = point.echo.unwrap();
+            assert!(echo.amplitude != point.peak.amplitude as f64);
+            assert_eq!(Some(echo.amplitude as f32), point.calibrated_amplitude);
+        }
+        let deconvolved = decomposed.deconvolution(20);
+        for point in discretize_with_options(&record, &test_info(), &deconvolved).unwrap() {
+            assert_eq!(Some(point.peak.amplitude as f32), point.calibrated_amplitude);
+        }
+    }
+
+    #[test]
+    fn range_correction() {
+        let record = two_echoes();
+        let walk = Calibration {
+            abscissa: vec![0.0, 100.0],
+            ordinate: vec![0.0, 1.0],
+        };
+        let options = DiscretizeOptions::default()
+                          .gaussian_decomposition(true)
+                          .deconvolution(20);
+        let uncorrected = discretize_with_options(&record, &test_info(), &options).unwrap();
+        let options = options.calibration(CalibrationTableKind::Range(Channel::High), walk);
+        let corrected = discretize_with_options(&record, &test_info(), &options).unwrap();
+        assert_eq!(uncorrected.len(), corrected.len());
+        for (uncorrected, corrected) in uncorrected.iter().zip(corrected.iter()) {
+            let raw = record.blocks[1].samples[corrected.peak.index] as f32;
+            assert!((corrected.range - uncorrected.range - raw / 100.0).abs() < 1e-4);
+        }
+        let options = options.range_correction(false);
+        let disabled = discretize_with_options(&record, &test_info(), &options).unwrap();
+        assert_eq!(uncorrected[0].range, disabled[0].range);
+    }
+
     #[test]
     fn angles() {
         let mut file = File::open("data/110630_174316.sdf").unwrap();
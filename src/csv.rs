@@ -0,0 +1,125 @@
+//! Write discretized points to comma-separated text files.
+//!
+//! Each point is one line, after a header line naming the columns:
+//!
+//! ```text
+//! time,x,y,z,range,theta,amplitude,target,num_target,facet,channel,calibrated_amplitude,reflectance
+//! ```
+//!
+//! `channel` is `high` or `low`. Calibrated amplitude and reflectance are left empty for points
+//! that don't have them.
+
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+use Result;
+use convert::Point;
+
+const HEADER: &'static str = "time,x,y,z,range,theta,amplitude,target,num_target,facet,channel,\
+                              calibrated_amplitude,reflectance";
+
+/// Writes points to a .csv file.
+#[derive(Debug)]
+pub struct CsvWriter<W: Write> {
+    writer: W,
+}
+
+impl CsvWriter<BufWriter<fs::File>> {
+    /// Creates a new .csv file at the given path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::csv::CsvWriter;
+    /// let writer = CsvWriter::from_path("/dev/null").unwrap();
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<CsvWriter<BufWriter<fs::File>>> {
+        let file = try!(fs::File::create(path));
+        CsvWriter::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write> CsvWriter<W> {
+    /// Creates a new writer and writes the header line.
+    pub fn new(mut writer: W) -> Result<CsvWriter<W>> {
+        try!(writeln!(writer, "{}", HEADER));
+        Ok(CsvWriter { writer: writer })
+    }
+
+    /// Writes a point.
+    pub fn write_point(&mut self, point: &Point) -> Result<()> {
+        self.write_point_at(point, [point.x as f64, point.y as f64, point.z as f64])
+    }
+
+    /// Writes a point with other coordinates, e.g. georeferenced ones, in place of its own.
+    pub fn write_point_at(&mut self, point: &Point, coordinates: [f64; 3]) -> Result<()> {
+        try!(writeln!(self.writer,
+                      "{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                      point.time,
+                      coordinates[0],
+                      coordinates[1],
+                      coordinates[2],
+                      point.range,
+                      point.theta,
+                      point.peak.amplitude,
+                      point.target,
+                      point.num_target,
+                      point.facet,
+                      if point.high_channel { "high" } else { "low" },
+                      point.calibrated_amplitude.map_or(String::new(), |a| a.to_string()),
+                      point.reflectance.map_or(String::new(), |r| r.to_string())));
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(mut self) -> Result<W> {
+        try!(self.writer.flush());
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use backend::{test_info, test_record};
+    use convert::discretize;
+
+    fn points() -> Vec<Point> {
+        let mut record = test_record(1.0);
+        record.facet = 2;
+        discretize(&record, &test_info()).unwrap()
+    }
+
+    #[test]
+    fn lines() {
+        let points = points();
+        let mut writer = CsvWriter::new(Cursor::new(Vec::new())).unwrap();
+        for point in &points {
+            writer.write_point(point).unwrap();
+        }
+        let text = String::from_utf8(writer.into_inner().unwrap().into_inner()).unwrap();
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(HEADER, lines[0]);
+        assert_eq!(points.len() + 1, lines.len());
+        let fields = lines[1].split(',').collect::<Vec<_>>();
+        assert_eq!(13, fields.len());
+        assert_eq!("80", fields[6]);
+        assert_eq!("2", fields[9]);
+        assert_eq!("high", fields[10]);
+        assert_eq!("", fields[12]);
+    }
+
+    #[test]
+    fn georeferenced() {
+        let points = points();
+        let mut writer = CsvWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.write_point_at(&points[0], [500000.25, 4980000.5, 300.125]).unwrap();
+        let text = String::from_utf8(writer.into_inner().unwrap().into_inner()).unwrap();
+        let fields = text.lines().nth(1).unwrap().split(',').collect::<Vec<_>>();
+        assert_eq!(vec!["500000.25", "4980000.5", "300.125"], fields[1..4].to_vec());
+    }
+}
@@ -0,0 +1,156 @@
+//! Deconvolution of waveforms.
+//!
+//! A returning waveform is, roughly, the emitted pulse convolved with the target's response. Every
+//! record carries the emitted pulse in its reference block, so we can use that pulse as the
+//! system's impulse response and deconvolve it out of the high and low channel waveforms. This
+//! sharpens the returns, which helps separate closely spaced targets, e.g. in vegetation.
+
+const EPSILON: f64 = 1e-6;
+
+/// Sharpens a waveform using Richardson-Lucy deconvolution.
+///
+/// `response` is the system's impulse response, usually the samples of the record's reference
+/// block. The background level (the smallest sample) is removed from both waveforms before
+/// deconvolving, and the response is centered on its largest sample so returns don't shift.
+///
+/// The deconvolved waveform is scaled so that its largest sample matches the original's, so peak
+/// detection thresholds keep their meaning. If the response has no signal, or `iterations` is
+/// zero, the samples are returned unchanged.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::deconvolve::richardson_lucy;
+/// let response = [0, 10, 40, 10, 0];
+/// let samples = [0, 0, 10, 40, 15, 40, 10, 0, 0];
+/// let sharpened = richardson_lucy(&samples, &response, 20);
+/// assert_eq!(samples.len(), sharpened.len());
+/// ```
+pub fn richardson_lucy(samples: &[u16], response: &[u16], iterations: usize) -> Vec<u16> {
+    let (kernel, center) = match kernel(response) {
+        Some(kernel) => kernel,
+        None => return samples.to_vec(),
+    };
+    if iterations == 0 || samples.is_empty() {
+        return samples.to_vec();
+    }
+    let background = samples.iter().cloned().min().unwrap() as f64;
+    let data = samples.iter().map(|&s| s as f64 - background).collect::<Vec<_>>();
+    let mean = data.iter().fold(0.0, |a, b| a + b) / data.len() as f64;
+    let mut estimate = vec![mean.max(EPSILON); data.len()];
+    for _ in 0..iterations {
+        let blurred = convolve(&estimate, &kernel, center);
+        let ratio = data.iter()
+                        .zip(blurred.iter())
+                        .map(|(d, b)| d / b.max(EPSILON))
+                        .collect::<Vec<_>>();
+        let correction = correlate(&ratio, &kernel, center);
+        for (e, c) in estimate.iter_mut().zip(correction.iter()) {
+            *e *= *c;
+        }
+    }
+    let data_max = data.iter().cloned().fold(0.0, f64::max);
+    let estimate_max = estimate.iter().cloned().fold(0.0, f64::max);
+    let scale = if estimate_max > EPSILON {
+        data_max / estimate_max
+    } else {
+        0.0
+    };
+    estimate.iter()
+            .map(|e| (e * scale + background).round().min(u16::max_value() as f64) as u16)
+            .collect()
+}
+
+/// Builds a normalized kernel from an impulse response, returning it and the index of its peak.
+fn kernel(response: &[u16]) -> Option<(Vec<f64>, usize)> {
+    let background = match response.iter().cloned().min() {
+        Some(background) => background,
+        None => return None,
+    };
+    let kernel = response.iter().map(|&s| (s - background) as f64).collect::<Vec<_>>();
+    let sum = kernel.iter().fold(0.0, |a, b| a + b);
+    if sum <= 0.0 {
+        return None;
+    }
+    let mut center = 0;
+    for (i, &k) in kernel.iter().enumerate() {
+        if k > kernel[center] {
+            center = i;
+        }
+    }
+    Some((kernel.iter().map(|k| k / sum).collect(), center))
+}
+
+fn convolve(signal: &[f64], kernel: &[f64], center: usize) -> Vec<f64> {
+    (0..signal.len())
+        .map(|i| {
+            kernel.iter()
+                  .enumerate()
+                  .filter_map(|(j, k)| {
+                      (i + center).checked_sub(j).and_then(|n| signal.get(n)).map(|s| s * k)
+                  })
+                  .fold(0.0, |a, b| a + b)
+        })
+        .collect()
+}
+
+fn correlate(signal: &[f64], kernel: &[f64], center: usize) -> Vec<f64> {
+    (0..signal.len())
+        .map(|i| {
+            kernel.iter()
+                  .enumerate()
+                  .filter_map(|(j, k)| {
+                      (i + j).checked_sub(center).and_then(|n| signal.get(n)).map(|s| s * k)
+                  })
+                  .fold(0.0, |a, b| a + b)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gaussian(amplitude: f64, center: f64, sigma: f64, len: usize) -> Vec<f64> {
+        (0..len)
+            .map(|t| amplitude * (-(t as f64 - center).powi(2) / (2.0 * sigma * sigma)).exp())
+            .collect()
+    }
+
+    fn local_maxima(samples: &[u16]) -> Vec<usize> {
+        (1..samples.len() - 1)
+            .filter(|&i| samples[i] > samples[i - 1] && samples[i] >= samples[i + 1] &&
+                         samples[i] > 20)
+            .collect()
+    }
+
+    #[test]
+    fn separates_close_targets() {
+        let response = gaussian(100.0, 6.0, 2.0, 13).iter().map(|&s| s as u16).collect::<Vec<_>>();
+        let samples = gaussian(100.0, 20.0, 2.0, 40)
+                          .iter()
+                          .zip(gaussian(100.0, 24.0, 2.0, 40).iter())
+                          .map(|(a, b)| (a + b + 5.0).round() as u16)
+                          .collect::<Vec<_>>();
+        assert_eq!(1, local_maxima(&samples).len());
+        let sharpened = richardson_lucy(&samples, &response, 50);
+        let maxima = local_maxima(&sharpened);
+        assert_eq!(2, maxima.len(), "{:?}", sharpened);
+        assert!((maxima[0] as i64 - 20).abs() <= 1);
+        assert!((maxima[1] as i64 - 24).abs() <= 1);
+        assert_eq!(samples.iter().max(), sharpened.iter().max());
+    }
+
+    #[test]
+    fn flat_response() {
+        let samples = vec![0, 10, 50, 10, 0];
+        assert_eq!(samples, richardson_lucy(&samples, &[5, 5, 5], 10));
+        assert_eq!(samples, richardson_lucy(&samples, &[], 10));
+    }
+
+    #[test]
+    fn no_iterations() {
+        let samples = vec![0, 10, 50, 10, 0];
+        assert_eq!(samples, richardson_lucy(&samples, &[0, 10, 0], 0));
+    }
+}
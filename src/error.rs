@@ -1,15 +1,12 @@
 //! Wrapper around fwifc's error handling routines.
 
 use std::error;
-use std::ffi::{CStr, NulError};
+use std::ffi::NulError;
 use std::fmt;
 use std::io;
-use std::ptr;
 use std::str::Utf8Error;
 
-use libc::c_char;
-
-use ffi::fwifc_get_last_error;
+use ffi;
 use file::Channel;
 
 /// Our error type.
@@ -17,14 +14,30 @@ use file::Channel;
 pub enum Error {
     /// A bad argument has been passed to sdfifc.
     BadArg(String),
+    /// The operation was cancelled by its caller.
+    Cancelled,
     /// The end of an sdf file has been reached.
     EndOfFile(String),
+    /// A point refers to a sample block that isn't in its record.
+    InvalidBlock(usize),
     /// The specified channel is invalid.
     InvalidChannel(u32),
+    /// A coordinate can't be stored, e.g. because it's too far from a LAS file's offset.
+    InvalidCoordinate(f64),
+    /// MTA zones start at one, so zone zero can't hold any points.
+    InvalidMtaZone(u8),
+    /// Options could not be parsed, or don't make sense.
+    InvalidOptions(String),
+    /// A trajectory file could not be parsed.
+    InvalidTrajectory(String),
+    /// One of our own indices wasn't built from the file it's being used with.
+    IndexMismatch(String),
     /// A wrapper around a `std::io::Error`.
     Io(io::Error),
     /// The channel is a valid channel, but we couldn't find it when we tried.
     MissingChannel(Channel),
+    /// A record has more than one reference block, so we don't know which one to use.
+    MultipleReferenceBlocks(usize),
     /// The sdf file is missing an index.
     ///
     /// Some file-based operations, namely reads and seeks, require an index. Use `File::reindex()`
@@ -40,6 +53,14 @@ pub enum Error {
     Runtime(String),
     /// Either zero or more than one reference peak.
     NeedSingleReferencePeak(usize),
+    /// The given time is not covered by the trajectory.
+    OutsideTrajectory(f64),
+    /// The index doesn't match its data file, which has changed since it was indexed.
+    ///
+    /// Use `File::remove_index()` and `File::reindex()` to rebuild it.
+    StaleIndex(String),
+    /// LAS files can hold at most 255 wave packet descriptors.
+    TooManyWavePacketDescriptors,
     /// A wrapper around `std::str::Utf8Error`.
     Utf8(Utf8Error),
     /// An unknown code has been provided to an error-mapping routine.
@@ -53,24 +74,15 @@ pub enum Error {
 impl Error {
     /// Converts an i32 error code to an `Error`.
     ///
-    /// This function also calls `last_error` to get the error message from fwifc.
+    /// This function also gets the last error message from fwifc, while holding the sdfifc lock.
     ///
     /// # Panics
     ///
     /// Panics if you pass in zero. That's because zero is not an error, and your code should not
     /// be trying to create an error if there isn't one.
     pub fn from_i32(code: i32) -> Error {
-        match code {
-            -1 => Error::EndOfFile(last_error().to_string()),
-            0 => panic!("Refusing to create an error with code zero"),
-            1 => Error::BadArg(last_error().to_string()),
-            2 => Error::UnsupportedFormat(last_error().to_string()),
-            3 => Error::MissingIndex(last_error().to_string()),
-            4 => Error::UnknownException(last_error().to_string()),
-            5 => Error::NotImplemented(last_error().to_string()),
-            6 => Error::Runtime(last_error().to_string()),
-            _ => Error::UnknownCode(code),
-        }
+        let _lock = ffi::lock();
+        ffi::error_from_code(code)
     }
 }
 
@@ -78,16 +90,27 @@ impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
             Error::BadArg(_) => "bad argument",
+            Error::Cancelled => "cancelled",
             Error::EndOfFile(_) => "end of file",
             Error::Io(ref err) => err.description(),
+            Error::InvalidBlock(_) => "invalid block",
             Error::InvalidChannel(_) => "invalid channel",
+            Error::InvalidCoordinate(_) => "invalid coordinate",
+            Error::InvalidMtaZone(_) => "invalid MTA zone",
+            Error::InvalidOptions(_) => "invalid options",
+            Error::InvalidTrajectory(_) => "invalid trajectory",
+            Error::IndexMismatch(_) => "index mismatch",
             Error::MissingChannel(_) => "missing channel",
             Error::MissingIndex(_) => "missing index",
+            Error::MultipleReferenceBlocks(_) => "more than one reference block",
             Error::NeedSingleReferencePeak(_) => "zero or more than one reference peaks",
             Error::NoCalibrationTableForChannel(_) => "no calibration table for channel",
             Error::NotImplemented(_) => "not implemented",
             Error::Nul(ref err) => err.description(),
+            Error::OutsideTrajectory(_) => "time outside of trajectory",
             Error::Runtime(_) => "runtime error",
+            Error::StaleIndex(_) => "stale index",
+            Error::TooManyWavePacketDescriptors => "too many wave packet descriptors",
             Error::Utf8(ref err) => err.description(),
             Error::UnknownCode(_) => "unknown code",
             Error::UnknownException(_) => "unknown exception",
@@ -110,18 +133,33 @@ impl fmt::Display for Error {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match *self {
             Error::BadArg(ref msg) => write!(f, "Bad argument: {}", msg),
+            Error::Cancelled => write!(f, "Cancelled"),
             Error::EndOfFile(ref msg) => write!(f, "End of file: {}", msg),
             Error::Io(ref err) => write!(f, "IO error: {}", err),
+            Error::InvalidBlock(index) => write!(f, "Invalid block index: {}", index),
             Error::InvalidChannel(u8) => write!(f, "Invalid channel: {}", u8),
+            Error::InvalidCoordinate(value) => write!(f, "Invalid coordinate: {}", value),
+            Error::InvalidMtaZone(zone) => write!(f, "Invalid MTA zone: {}", zone),
+            Error::InvalidOptions(ref msg) => write!(f, "Invalid options: {}", msg),
+            Error::InvalidTrajectory(ref msg) => write!(f, "Invalid trajectory: {}", msg),
+            Error::IndexMismatch(ref msg) => write!(f, "Index mismatch: {}", msg),
             Error::MissingChannel(ref channel) => write!(f, "Missing channel: {}", channel),
             Error::MissingIndex(ref msg) => write!(f, "Missing index: {}", msg),
+            Error::MultipleReferenceBlocks(n) => {
+                write!(f, "Wanted one reference block, got {}", n)
+            }
             Error::NeedSingleReferencePeak(n) => write!(f, "Wanted one reference peak, got {}", n),
             Error::NoCalibrationTableForChannel(channel) => {
                 write!(f, "No calibration table for channel: {}", channel)
             }
             Error::NotImplemented(ref msg) => write!(f, "Not implemented: {}", msg),
             Error::Nul(ref err) => write!(f, "Nul error: {}", err),
+            Error::OutsideTrajectory(time) => write!(f, "Time outside of trajectory: {}", time),
+            Error::StaleIndex(ref msg) => write!(f, "Stale index: {}", msg),
             Error::Runtime(ref msg) => write!(f, "Runtime error: {}", msg),
+            Error::TooManyWavePacketDescriptors => {
+                write!(f, "Too many wave packet descriptors (the maximum is 255)")
+            }
             Error::Utf8(ref err) => write!(f, "Utf8 error: {}", err),
             Error::UnknownCode(code) => write!(f, "Unknown code: {}", code),
             Error::UnknownException(ref msg) => write!(f, "Unknown exception: {}", msg),
@@ -130,6 +168,31 @@ impl fmt::Display for Error {
     }
 }
 
+/// An error reading one record, along with that record's index.
+#[derive(Debug)]
+pub struct RecordError {
+    /// The one-based index of the record that could not be read.
+    pub index: u32,
+    /// The underlying error.
+    pub error: Error,
+}
+
+impl error::Error for RecordError {
+    fn description(&self) -> &str {
+        self.error.description()
+    }
+
+    fn cause(&self) -> Option<&error::Error> {
+        Some(&self.error)
+    }
+}
+
+impl fmt::Display for RecordError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Record {}: {}", self.index, self.error)
+    }
+}
+
 impl From<io::Error> for Error {
     fn from(err: io::Error) -> Error {
         Error::Io(err)
@@ -148,7 +211,10 @@ impl From<Utf8Error> for Error {
     }
 }
 
-/// Retrieves the last error from fwifc and returns it as a `&'static str`.
+/// Retrieves the last error from fwifc.
+///
+/// sdfifc keeps its last error in global state, so this takes the sdfifc lock and copies the
+/// message out.
 ///
 /// # Panics
 ///
@@ -162,25 +228,28 @@ impl From<Utf8Error> for Error {
 /// use sdf::error::last_error;
 /// let message = last_error();
 /// ```
-pub fn last_error() -> &'static str {
-    unsafe {
-        let mut message: *const c_char = ptr::null_mut();
-        let result = fwifc_get_last_error(&mut message);
-        if result != 0 {
-            panic!("Non-zero return code from `fwifc_get_last_error`: {}",
-                   result);
-        }
-        CStr::from_ptr(message).to_str().unwrap()
-    }
+pub fn last_error() -> String {
+    let _lock = ffi::lock();
+    ffi::last_error()
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    use std::thread;
+
     #[test]
     fn no_error_expected() {
         let message = last_error();
         assert_eq!("(no error)", message);
     }
+
+    #[test]
+    fn last_error_from_many_threads() {
+        let handles = (0..8).map(|_| thread::spawn(|| last_error())).collect::<Vec<_>>();
+        for handle in handles {
+            assert_eq!("(no error)", handle.join().unwrap());
+        }
+    }
 }
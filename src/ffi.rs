@@ -1,7 +1,58 @@
 //! The Foreign Function Interface wrappers around libsdfifc.
+//!
+//! sdfifc keeps global state, including its last error message, so every call into the library
+//! must be made while holding the lock returned by `lock`.
+
+use std::ffi::CStr;
+use std::ptr;
+use std::sync::{Mutex, MutexGuard};
 
 use libc::{c_char, c_double, uint16_t, uint32_t};
 
+use error::Error;
+
+lazy_static! {
+    static ref LOCK: Mutex<()> = Mutex::new(());
+}
+
+/// Takes the process-wide sdfifc lock.
+///
+/// A panic while the lock was held doesn't leave sdfifc in any worse state than an error would,
+/// so we ignore poisoning.
+pub fn lock() -> MutexGuard<'static, ()> {
+    LOCK.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Converts an sdfifc return code to an `Error`, assuming the lock is already held.
+pub fn error_from_code(code: i32) -> Error {
+    match code {
+        -1 => Error::EndOfFile(last_error()),
+        0 => panic!("Refusing to create an error with code zero"),
+        1 => Error::BadArg(last_error()),
+        2 => Error::UnsupportedFormat(last_error()),
+        3 => Error::MissingIndex(last_error()),
+        4 => Error::UnknownException(last_error()),
+        5 => Error::NotImplemented(last_error()),
+        6 => Error::Runtime(last_error()),
+        _ => Error::UnknownCode(code),
+    }
+}
+
+/// Retrieves the last error from sdfifc, assuming the lock is already held.
+///
+/// The message is copied out while we hold the lock, since sdfifc reuses its buffer.
+pub fn last_error() -> String {
+    unsafe {
+        let mut message: *const c_char = ptr::null_mut();
+        let result = fwifc_get_last_error(&mut message);
+        if result != 0 {
+            panic!("Non-zero return code from `fwifc_get_last_error`: {}",
+                   result);
+        }
+        CStr::from_ptr(message).to_str().unwrap().to_string()
+    }
+}
+
 #[allow(non_camel_case_types)]
 pub enum fwifc_file_t {}
 
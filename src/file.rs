@@ -1,31 +1,32 @@
-//! Public, safe wrappers around `fwifc_file` and its member functions.
+//! Read sample data records and metadata from .sdf files.
 
-use std::ffi::{CStr, CString, OsString};
+use std::cmp::Ordering;
+use std::f64;
 use std::fmt;
-use std::fs::{metadata, remove_file};
 use std::iter::{Iterator, IntoIterator};
-use std::path::Path;
-use std::ptr;
-
-use libc::c_char;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::result;
+use std::u32;
 
 use Result;
-use error::Error;
-use ffi::{fwifc_close, fwifc_file, fwifc_get_calib, fwifc_get_info, fwifc_open, fwifc_read,
-          fwifc_reindex, fwifc_sbl_t, fwifc_seek, fwifc_seek_time, fwifc_seek_time_external,
-          fwifc_tell, fwifc_set_sosbl_relative};
+use backend::{Backend, Sdfifc};
+use error::{Error, RecordError};
+use index::Index;
 
 /// An .sdf file.
 ///
-/// The file is mostly a simple wrapper around an `fwifc_file` handle, but we do a bit of extra
+/// The file delegates its work to a `Backend`, by default `Sdfifc`, but we do a bit of extra
 /// smarts (or dumbs) to help other users.
 ///
 /// - We ensure that we reindex the file only once, regardless of the number of times that
 /// `reindex` has been called.
+/// - If the file has one of our own `Index`es, time seeks and record counts use it instead of
+/// asking the backend.
 #[derive(Debug)]
-pub struct File {
-    handle: fwifc_file,
-    index_path: OsString,
+pub struct File<B: Backend = Sdfifc> {
+    backend: B,
+    index: Option<Index>,
 }
 
 impl File {
@@ -38,22 +39,143 @@ impl File {
     /// let file = File::open("data/110630_174316.sdf").unwrap();
     /// ```
     pub fn open<T: Into<Vec<u8>>>(path: T) -> Result<File> {
-        unsafe {
-            let path = try!(CString::new(path));
-            let mut file: fwifc_file = ptr::null_mut();
-            sdftry!(fwifc_open(path.as_ptr(), &mut file));
-            let index_path = Path::new(try!(path.to_str())).with_extension("idx").into_os_string();
-            Ok(File {
-                handle: file,
-                index_path: index_path,
-            })
+        Sdfifc::open(path).map(File::new)
+    }
+
+    /// Opens an .sdf data file, with options.
+    ///
+    /// Use this to keep the index somewhere other than next to the data file, e.g. when the data
+    /// are on a read-only mount.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::{File, OpenOptions};
+    /// let options = OpenOptions::new().index_dir("/tmp/sdf-indices");
+    /// let mut file = File::open_with_options("data/110630_174316.sdf", &options).unwrap();
+    /// file.reindex().unwrap();
+    /// assert!(file.indexed());
+    /// ```
+    pub fn open_with_options<P: AsRef<Path>>(path: P, options: &OpenOptions) -> Result<File> {
+        Sdfifc::open_with_options(path, options).map(File::new)
+    }
+}
+
+/// Options for opening a `File`.
+#[derive(Clone, Debug, Default)]
+pub struct OpenOptions {
+    index: IndexLocation,
+    stale_index_policy: StaleIndexPolicy,
+}
+
+/// What to do when opening a file whose index doesn't match the data file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StaleIndexPolicy {
+    /// Remove the stale index, so that `File::reindex()` builds a new one.
+    Rebuild,
+    /// Return `Error::StaleIndex`.
+    Fail,
+}
+
+impl Default for StaleIndexPolicy {
+    fn default() -> StaleIndexPolicy {
+        StaleIndexPolicy::Rebuild
+    }
+}
+
+/// Where a file's index lives.
+///
+/// sdfifc always puts the index next to the file it opens, so everywhere but `NextToData` we also
+/// create a symlink to the data file next to the index. On Windows, creating symlinks needs
+/// Developer Mode or administrator rights.
+#[derive(Clone, Debug)]
+pub enum IndexLocation {
+    /// Next to the data file, with an .idx extension. This is what sdfifc does on its own.
+    NextToData,
+    /// In this directory, with the data file's name and an .idx extension.
+    Directory(PathBuf),
+    /// At this path, which must have an .idx extension.
+    Path(PathBuf),
+    /// In this cache directory, in a subdirectory keyed by a hash of the data file's path, size
+    /// and modification time.
+    Cache(PathBuf),
+}
+
+impl Default for IndexLocation {
+    fn default() -> IndexLocation {
+        IndexLocation::NextToData
+    }
+}
+
+impl OpenOptions {
+    /// Creates new options that keep the index next to the data file.
+    pub fn new() -> OpenOptions {
+        OpenOptions::default()
+    }
+
+    /// Puts the index in the given directory, which is created if needed.
+    pub fn index_dir<P: Into<PathBuf>>(mut self, directory: P) -> OpenOptions {
+        self.index = IndexLocation::Directory(directory.into());
+        self
+    }
+
+    /// Puts the index at the given path, which must have an .idx extension.
+    ///
+    /// A symlink to the data file, with an .sdf extension, is also created next to the index.
+    pub fn index_path<P: Into<PathBuf>>(mut self, path: P) -> OpenOptions {
+        self.index = IndexLocation::Path(path.into());
+        self
+    }
+
+    /// Keeps indices in a cache directory, keyed by the data file.
+    ///
+    /// If the data file is moved or replaced, it gets a new index.
+    pub fn index_cache<P: Into<PathBuf>>(mut self, directory: P) -> OpenOptions {
+        self.index = IndexLocation::Cache(directory.into());
+        self
+    }
+
+    /// Returns where the index lives.
+    pub fn index_location(&self) -> &IndexLocation {
+        &self.index
+    }
+
+    /// Sets what to do if the index is stale, i.e. the data file has changed since it was indexed.
+    ///
+    /// We can only tell once the index has a stamp, which we write whenever we build or open one.
+    pub fn stale_index_policy(mut self, policy: StaleIndexPolicy) -> OpenOptions {
+        self.stale_index_policy = policy;
+        self
+    }
+
+    /// Returns what happens to a stale index.
+    pub fn stale_index(&self) -> StaleIndexPolicy {
+        self.stale_index_policy
+    }
+}
+
+impl<B: Backend> File<B> {
+    /// Creates a new file that reads from the given backend.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::backend::Sdfifc;
+    /// use sdf::file::File;
+    /// let file = File::new(Sdfifc::open("data/110630_174316.sdf").unwrap());
+    /// ```
+    pub fn new(backend: B) -> File<B> {
+        File {
+            backend: backend,
+            index: None,
         }
     }
 
     /// (Re-)Creates the index file.
     ///
     /// The index file is required for navigating the file. This is a blocking operation and may
-    /// take some time. The index file is placed in the same directory as the data file.
+    /// take some time. The index file is placed where the file's `OpenOptions` say, which is next
+    /// to the data file by default.
     ///
     /// # Examples
     ///
@@ -65,11 +187,42 @@ impl File {
     pub fn reindex(&mut self) -> Result<()> {
         if !self.indexed() {
             info!("Reindexing");
-            unsafe { sdftry!(fwifc_reindex(self.handle)) }
+            try!(self.backend.reindex());
         }
         Ok(())
     }
 
+    /// Creates an index for this file, if needed, reporting progress through a callback.
+    ///
+    /// `progress` is called every so often on the calling thread. Return false from it to cancel
+    /// the reindex, which then returns `Error::Cancelled` and leaves the file without an index.
+    /// sdfifc can't be interrupted, so with the default backend a cancel only takes effect once
+    /// sdfifc is done with it, whether it finished or failed. If the file is already indexed,
+    /// `progress` is called once, with `finished` set.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::File;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// file.reindex_with_progress(|progress| {
+    ///         println!("{} bytes indexed", progress.index_bytes);
+    ///         true
+    ///     })
+    ///     .unwrap();
+    /// ```
+    pub fn reindex_with_progress<F>(&mut self, mut progress: F) -> Result<()>
+        where F: FnMut(ReindexProgress) -> bool
+    {
+        if self.indexed() {
+            let _ = progress(ReindexProgress { finished: true, ..ReindexProgress::default() });
+            Ok(())
+        } else {
+            info!("Reindexing");
+            self.backend.reindex_with_progress(&mut progress)
+        }
+    }
+
     /// Remove this file's index from the filesystem.
     ///
     /// # Examples
@@ -81,7 +234,7 @@ impl File {
     /// file.remove_index().unwrap();
     /// ```
     pub fn remove_index(&self) -> Result<()> {
-        remove_file(&self.index_path).map_err(|e| Error::from(e))
+        self.backend.remove_index()
     }
 
     /// Sets the mode timestamp of the start of the sample block.
@@ -95,13 +248,7 @@ impl File {
     /// file.set_sosbl_mode(SosblMode::Absolute).unwrap();
     /// ```
     pub fn set_sosbl_mode(&mut self, mode: SosblMode) -> Result<()> {
-        unsafe {
-            let value = match mode {
-                SosblMode::Absolute => 0,
-                SosblMode::Relative => 1,
-            };
-            Ok(sdftry!(fwifc_set_sosbl_relative(self.handle, value)))
-        }
+        self.backend.set_sosbl_mode(mode)
     }
 
     /// Gets information about the file.
@@ -114,39 +261,11 @@ impl File {
     /// let file_info = file.info();
     /// ```
     pub fn info(&mut self) -> Result<FileInfo> {
-        unsafe {
-            let mut instrument: *const c_char = ptr::null_mut();
-            let mut serial: *const c_char = ptr::null_mut();
-            let mut epoch: *const c_char = ptr::null_mut();
-            let mut v_group = 0f64;
-            let mut sampling_time = 0f64;
-            let mut flags = 0u16;
-            let mut num_facets = 0u16;
-            sdftry!(fwifc_get_info(self.handle,
-                                   &mut instrument,
-                                   &mut serial,
-                                   &mut epoch,
-                                   &mut v_group,
-                                   &mut sampling_time,
-                                   &mut flags,
-                                   &mut num_facets));
-            Ok(FileInfo {
-                instrument: try!(CStr::from_ptr(instrument).to_str()).to_string(),
-                serial: try!(CStr::from_ptr(serial).to_str()).to_string(),
-                epoch: try!(CStr::from_ptr(epoch).to_str()).to_string(),
-                v_group: v_group,
-                sampling_time: sampling_time,
-                gps_synchronized: flags & 0x01 == 1,
-                num_facets: num_facets,
-            })
-        }
+        self.backend.info()
     }
 
     /// Gets the calibration info for the file.
     ///
-    /// We manually copy all of the calibration info into new vectors because we can't really trust
-    /// the memory behind the fwifc call.
-    ///
     /// # Examples
     ///
     /// ```
@@ -155,26 +274,7 @@ impl File {
     /// let calibration = file.calibration(CalibrationTableKind::Amplitude(Channel::High)).unwrap();
     /// ```
     pub fn calibration(&mut self, kind: CalibrationTableKind) -> Result<Calibration> {
-        unsafe {
-            let mut count = 0u32;
-            let mut abscissa: *const f64 = ptr::null_mut();
-            let mut ordinate: *const f64 = ptr::null_mut();
-            sdftry!(fwifc_get_calib(self.handle,
-                                    try!(kind.as_u16()),
-                                    &mut count,
-                                    &mut abscissa,
-                                    &mut ordinate));
-            let mut abscissa_vec = Vec::with_capacity(count as usize);
-            let mut ordinate_vec = Vec::with_capacity(count as usize);
-            for i in 0..count {
-                abscissa_vec.push(*abscissa.offset(i as isize));
-                ordinate_vec.push(*ordinate.offset(i as isize));
-            }
-            Ok(Calibration {
-                abscissa: abscissa_vec,
-                ordinate: ordinate_vec,
-            })
-        }
+        self.backend.calibration(kind)
     }
 
     /// Reads a sample data record from the file.
@@ -194,56 +294,14 @@ impl File {
     /// let record = file.read().unwrap();
     /// ```
     pub fn read(&mut self) -> Result<Record> {
-        unsafe {
-            let mut time_sorg = 0.0;
-            let mut time_external = 0.0;
-            let mut origin = [0.0f64; 3];
-            let mut direction = [0.0f64; 3];
-            let mut flags = 0;
-            let mut facet = 0;
-            let mut sbl_count = 0;
-            let mut sbl_size = 0;
-            let mut sbl: *mut fwifc_sbl_t = ptr::null_mut();
-            sdftry!(fwifc_read(self.handle,
-                               &mut time_sorg,
-                               &mut time_external,
-                               origin.as_mut_ptr(),
-                               direction.as_mut_ptr(),
-                               &mut flags,
-                               &mut facet,
-                               &mut sbl_count,
-                               &mut sbl_size,
-                               &mut sbl));
-            let mut blocks = Vec::with_capacity(sbl_count as usize);
-            for i in 0..sbl_count {
-                let ref block = *sbl.offset(i as isize);
-                let mut samples = Vec::with_capacity(block.sample_count as usize);
-                for j in 0..block.sample_count {
-                    samples.push(*block.sample.offset(j as isize));
-                }
-                let channel = try!(Channel::from_u32(block.channel));
-                blocks.push(Block {
-                    time_sosbl: block.time_sosbl,
-                    channel: channel,
-                    samples: samples,
-                });
-            }
-            Ok(Record {
-                time_sorg: time_sorg,
-                time_external: time_external,
-                origin: origin,
-                direction: direction,
-                synchronized: flags & 0x01 == 1,
-                sync_lastsec: flags & 0x02 == 2,
-                housekeeping: flags & 0x04 == 4,
-                facet: facet,
-                blocks: blocks,
-            })
-        }
+        self.backend.read()
     }
 
     /// Seeks to a record index in the file.
     ///
+    /// This always goes through the backend, even if the file has one of our own indices, since
+    /// those don't know where records are in the data file.
+    ///
     /// # Examples
     ///
     /// Seeks to the first record.
@@ -265,7 +323,7 @@ impl File {
     /// file.seek(u32::MAX).unwrap();
     /// ```
     pub fn seek(&mut self, index: u32) -> Result<()> {
-        unsafe { Ok(sdftry!(fwifc_seek(self.handle, index))) }
+        self.backend.seek(index)
     }
 
     /// Seeks to an internal timestamp, in seconds.
@@ -279,7 +337,10 @@ impl File {
     /// file.seek_time(1.0).unwrap();
     /// ```
     pub fn seek_time(&mut self, time: f64) -> Result<()> {
-        unsafe { Ok(sdftry!(fwifc_seek_time(self.handle, time))) }
+        match self.index.as_ref().map(|index| index.find_time(time, TimeBase::Internal)) {
+            Some(index) => self.backend.seek(index),
+            None => self.backend.seek_time(time),
+        }
     }
 
     /// Seeks to an external time in seconds.
@@ -295,7 +356,10 @@ impl File {
     /// file.seek_time_external(1.0).unwrap();
     /// ```
     pub fn seek_time_external(&mut self, time: f64) -> Result<()> {
-        unsafe { Ok(sdftry!(fwifc_seek_time_external(self.handle, time))) }
+        match self.index.as_ref().map(|index| index.find_time(time, TimeBase::External)) {
+            Some(index) => self.backend.seek(index),
+            None => self.backend.seek_time_external(time),
+        }
     }
 
     /// Returns the index of the next record to be read.
@@ -311,9 +375,149 @@ impl File {
     /// assert_eq!(2, file.tell().unwrap());
     /// ```
     pub fn tell(&mut self) -> Result<u32> {
-        let mut index = 0u32;
-        unsafe { sdftry!(fwifc_tell(self.handle, &mut index)) }
-        Ok(index)
+        self.backend.tell()
+    }
+
+    /// Returns the number of records in this file.
+    ///
+    /// With an `Index` this is free. Otherwise, as with `summary`, the file is reindexed if
+    /// needed, and then we seek to and read the last record, and go back to where we were.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::File;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let records = file.record_count().unwrap();
+    /// ```
+    pub fn record_count(&mut self) -> Result<u32> {
+        if let Some(ref index) = self.index {
+            return Ok(index.len());
+        }
+        try!(self.reindex());
+        let position = try!(self.backend.tell());
+        try!(self.backend.seek(u32::MAX));
+        let count = match self.backend.read() {
+            Ok(_) => try!(self.backend.tell()) - 1,
+            Err(Error::EndOfFile(_)) => 0,
+            Err(err) => return Err(err),
+        };
+        try!(self.backend.seek(position));
+        Ok(count)
+    }
+
+    /// Returns the number of records and the times of the first and last records.
+    ///
+    /// With an `Index` this doesn't touch the data. Otherwise, the file is reindexed if needed,
+    /// the first and last records are read, and we go back to where we were. Returns
+    /// `Error::EndOfFile` if the file has no records.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::File;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let summary = file.summary().unwrap();
+    /// println!("{} records over {} seconds", summary.record_count, summary.duration());
+    /// ```
+    pub fn summary(&mut self) -> Result<Summary> {
+        if let Some(ref index) = self.index {
+            return match (index.entry(1), index.entry(index.len())) {
+                (Some(first), Some(last)) => {
+                    Ok(Summary {
+                        record_count: index.len(),
+                        start_time: first.time_sorg,
+                        end_time: last.time_sorg,
+                        start_time_external: first.time_external,
+                        end_time_external: last.time_external,
+                    })
+                }
+                _ => Err(Error::EndOfFile("The index has no records".to_string())),
+            };
+        }
+        try!(self.reindex());
+        let position = try!(self.backend.tell());
+        try!(self.backend.seek(1));
+        let first = try!(self.backend.read());
+        try!(self.backend.seek(u32::MAX));
+        let last = try!(self.backend.read());
+        let record_count = try!(self.backend.tell()) - 1;
+        try!(self.backend.seek(position));
+        Ok(Summary {
+            record_count: record_count,
+            start_time: first.time_sorg,
+            end_time: last.time_sorg,
+            start_time_external: first.time_external,
+            end_time_external: last.time_external,
+        })
+    }
+
+    /// Builds one of our own indices for this file, and writes it to a path.
+    ///
+    /// The index isn't used until it's handed to `set_index`. See `Index::build` for details.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::File;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let index = file.build_index("/tmp/110630_174316.ridx").unwrap();
+    /// ```
+    pub fn build_index<P: AsRef<Path>>(&mut self, path: P) -> Result<Index> {
+        Index::build(self, path)
+    }
+
+    /// Uses an index, built from this file, for time seeks and record counts.
+    ///
+    /// The file is reindexed if needed. Returns `Error::IndexMismatch`, and keeps using whatever
+    /// index it had before, if the index's first entry doesn't have the times of the file's first
+    /// record or if the two have different numbers of records. The file is left at the position
+    /// it started from.
+    pub fn set_index(&mut self, index: Index) -> Result<()> {
+        try!(self.reindex());
+        let position = try!(self.backend.tell());
+        let result = self.check_index(&index);
+        try!(self.backend.seek(position));
+        try!(result);
+        self.index = Some(index);
+        Ok(())
+    }
+
+    fn check_index(&mut self, index: &Index) -> Result<()> {
+        try!(self.backend.seek(1));
+        let first = match self.backend.read() {
+            Ok(record) => record,
+            Err(Error::EndOfFile(_)) if index.is_empty() => return Ok(()),
+            Err(Error::EndOfFile(_)) => {
+                return Err(Error::IndexMismatch(format!("The file has no records, but the \
+                                                         index has {}",
+                                                        index.len())))
+            }
+            Err(err) => return Err(err),
+        };
+        match index.entry(1) {
+            Some(entry) if entry.time_sorg == first.time_sorg &&
+                           entry.time_external == first.time_external => {}
+            _ => {
+                return Err(Error::IndexMismatch("The first record's times don't match"
+                                                    .to_string()))
+            }
+        }
+        // Seeking to the end doesn't read anything, so the count is cheap.
+        try!(self.backend.seek(u32::MAX));
+        let count = try!(self.backend.tell());
+        if count == index.len() {
+            Ok(())
+        } else {
+            Err(Error::IndexMismatch(format!("The file has {} records, but the index has {}",
+                                             count,
+                                             index.len())))
+        }
+    }
+
+    /// Returns this file's index, if it has one.
+    pub fn index(&self) -> Option<&Index> {
+        self.index.as_ref()
     }
 
     /// Returns true if this file is indexed.
@@ -326,24 +530,101 @@ impl File {
     /// file.indexed();
     /// ```
     pub fn indexed(&self) -> bool {
-        metadata(&self.index_path).map(|m| m.is_file()).unwrap_or(false)
+        self.backend.indexed()
     }
-}
 
-impl Drop for File {
-    fn drop(&mut self) {
-        unsafe {
-            let result = fwifc_close(self.handle);
-            if result != 0 {
-                panic!("Error when closing file: {}", Error::from_i32(result));
-            }
+    /// Returns an iterator over this file's records, starting at the current position.
+    ///
+    /// The file is reindexed first, if needed. Unlike `FileIterator`, this iterator doesn't
+    /// panic: an error reading a record is returned along with that record's index, and the next
+    /// call to `next` skips past the bad record and carries on. To stop at the first error, just
+    /// stop iterating.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::File;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// for result in file.records().unwrap().take(10) {
+    ///     match result {
+    ///         Ok(record) => println!("{}", record.time_sorg),
+    ///         Err(err) => println!("Skipping record: {}", err),
+    ///     }
+    /// }
+    /// ```
+    pub fn records(&mut self) -> Result<Records<B>> {
+        try!(self.reindex());
+        let index = try!(self.tell());
+        Ok(Records {
+            file: self,
+            index: index,
+            skip: false,
+            done: false,
+        })
+    }
+
+    /// Returns an iterator over the records whose time is at least `start` and less than `end`.
+    ///
+    /// The file is reindexed if needed, and then we seek to `start` with `seek_time` or
+    /// `seek_time_external`, depending on the time base. A `start` of `f64::NEG_INFINITY` seeks
+    /// to the first record instead, since sdfifc can't seek to it. Iteration stops at the first
+    /// record at or after `end`. Errors are handled as in `records`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::{File, TimeBase};
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// for record in file.records_between_times(1.0, 2.0, TimeBase::External).unwrap() {
+    ///     let record = record.unwrap();
+    ///     assert!(record.time_external >= 1.0 && record.time_external < 2.0);
+    /// }
+    /// ```
+    pub fn records_between_times(&mut self,
+                                 start: f64,
+                                 end: f64,
+                                 time_base: TimeBase)
+                                 -> Result<Window<B>> {
+        try!(self.reindex());
+        match time_base {
+            _ if start == f64::NEG_INFINITY => try!(self.seek(1)),
+            TimeBase::Internal => try!(self.seek_time(start)),
+            TimeBase::External => try!(self.seek_time_external(start)),
         }
+        Ok(Window {
+            records: try!(self.records()),
+            start: start,
+            end: end,
+            key: WindowKey::Time(time_base),
+        })
+    }
+
+    /// Returns an iterator over the records with one-based indices in the given range.
+    ///
+    /// As with `Range`, the end index is excluded. Errors are handled as in `records`.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::File;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// assert_eq!(10, file.records_in_range(1..11).unwrap().count());
+    /// ```
+    pub fn records_in_range(&mut self, range: Range<u32>) -> Result<Window<B>> {
+        try!(self.reindex());
+        try!(self.seek(range.start));
+        Ok(Window {
+            records: try!(self.records()),
+            start: range.start as f64,
+            end: range.end as f64,
+            key: WindowKey::Index,
+        })
     }
 }
 
-impl IntoIterator for File {
+impl<B: Backend> IntoIterator for File<B> {
     type Item = Record;
-    type IntoIter = FileIterator;
+    type IntoIter = FileIterator<B>;
     fn into_iter(mut self) -> Self::IntoIter {
         self.reindex().unwrap();
         FileIterator { file: self }
@@ -353,13 +634,13 @@ impl IntoIterator for File {
 /// An iterator over a file.
 ///
 /// Note that this iterator will panic on any underlying sdfifc library errors. If you need more
-/// robust error handling, do the iteration yourself.
+/// robust error handling, use `File::records`.
 #[derive(Debug)]
-pub struct FileIterator {
-    file: File,
+pub struct FileIterator<B: Backend = Sdfifc> {
+    file: File<B>,
 }
 
-impl Iterator for FileIterator {
+impl<B: Backend> Iterator for FileIterator<B> {
     type Item = Record;
     fn next(&mut self) -> Option<Self::Item> {
         match self.file.read() {
@@ -370,6 +651,139 @@ impl Iterator for FileIterator {
     }
 }
 
+/// A fallible iterator over a file's records, created by `File::records`.
+#[derive(Debug)]
+pub struct Records<'a, B: Backend + 'a = Sdfifc> {
+    file: &'a mut File<B>,
+    index: u32,
+    skip: bool,
+    done: bool,
+}
+
+impl<'a, B: Backend> Records<'a, B> {
+    /// Returns the index of the next record to be read.
+    ///
+    /// The record, or error, that `next` just returned has the index one less than this.
+    pub fn next_index(&self) -> u32 {
+        self.index
+    }
+}
+
+impl<'a, B: Backend> Iterator for Records<'a, B> {
+    type Item = result::Result<Record, RecordError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        if self.skip {
+            self.skip = false;
+            if let Err(err) = self.file.seek(self.index) {
+                // If we can't get past the bad record, there's no point in going on.
+                self.done = true;
+                return Some(Err(RecordError {
+                    index: self.index,
+                    error: err,
+                }));
+            }
+        }
+        match self.file.read() {
+            Ok(record) => {
+                self.index += 1;
+                Some(Ok(record))
+            }
+            Err(Error::EndOfFile(_)) => {
+                self.done = true;
+                None
+            }
+            Err(err) => {
+                let index = self.index;
+                self.index += 1;
+                self.skip = true;
+                Some(Err(RecordError {
+                    index: index,
+                    error: err,
+                }))
+            }
+        }
+    }
+}
+
+/// Which of a record's times to use.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeBase {
+    /// The internal time, `Record::time_sorg`.
+    Internal,
+    /// The external time, `Record::time_external`.
+    External,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum WindowKey {
+    Index,
+    Time(TimeBase),
+}
+
+/// An iterator over a window of a file's records, created by `File::records_between_times` or
+/// `File::records_in_range`.
+///
+/// Records before the start of the window, e.g. if a seek lands a bit early, are skipped.
+#[derive(Debug)]
+pub struct Window<'a, B: Backend + 'a = Sdfifc> {
+    records: Records<'a, B>,
+    start: f64,
+    end: f64,
+    key: WindowKey,
+}
+
+impl<'a, B: Backend> Window<'a, B> {
+    /// Returns the index of the next record to be read.
+    ///
+    /// The record, or error, that `next` just returned has the index one less than this.
+    pub fn next_index(&self) -> u32 {
+        self.records.next_index()
+    }
+}
+
+impl<'a, B: Backend> Iterator for Window<'a, B> {
+    type Item = result::Result<Record, RecordError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let index = self.records.index;
+            let result = match self.records.next() {
+                Some(result) => result,
+                None => return None,
+            };
+            let value = match (self.key, &result) {
+                (WindowKey::Index, _) => index as f64,
+                (WindowKey::Time(TimeBase::Internal), &Ok(ref record)) => record.time_sorg,
+                (WindowKey::Time(TimeBase::External), &Ok(ref record)) => record.time_external,
+                (WindowKey::Time(_), &Err(_)) => return Some(result),
+            };
+            if value >= self.end {
+                self.records.done = true;
+                return None;
+            } else if value >= self.start {
+                return Some(result);
+            }
+        }
+    }
+}
+
+/// How far along a reindex is.
+///
+/// sdfifc doesn't say how much of the data file it has read, so all we know is how big the index
+/// has grown. Since the index is much smaller than the data, the two sizes can't be compared
+/// directly, but they're enough to show that something is happening.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ReindexProgress {
+    /// The number of bytes written to the index so far.
+    pub index_bytes: u64,
+    /// The size of the data file being indexed, if there is one.
+    pub data_bytes: Option<u64>,
+    /// True once the index is complete.
+    pub finished: bool,
+}
+
 /// The timestamp of the start of the sample block can be relative or absolute.
 ///
 /// If absolute, large values could lose precision.
@@ -383,8 +797,40 @@ pub enum SosblMode {
     Absolute,
 }
 
+/// The extent of a file's records.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    /// The number of records.
+    pub record_count: u32,
+    /// The internal time of the first record, in seconds.
+    pub start_time: f64,
+    /// The internal time of the last record, in seconds.
+    pub end_time: f64,
+    /// The external time of the first record, in seconds.
+    pub start_time_external: f64,
+    /// The external time of the last record, in seconds.
+    pub end_time_external: f64,
+}
+
+impl Summary {
+    /// Returns the time between the first and last records, in seconds of internal time.
+    pub fn duration(&self) -> f64 {
+        self.end_time - self.start_time
+    }
+
+    /// Returns the mean number of records per second, if the records span any time at all.
+    pub fn pulse_rate(&self) -> Option<f64> {
+        let duration = self.duration();
+        if self.record_count > 1 && duration > 0.0 {
+            Some((self.record_count - 1) as f64 / duration)
+        } else {
+            None
+        }
+    }
+}
+
 /// A container for information about a file.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct FileInfo {
     /// The instrument name, e.g. "Q680I".
     pub instrument: String,
@@ -403,7 +849,7 @@ pub struct FileInfo {
 }
 
 /// A container for calibration information.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Calibration {
     /// The abscissa can be assumed to monotonically increase. These are in pairs with the
     /// ordinates.
@@ -412,10 +858,50 @@ pub struct Calibration {
     pub ordinate: Vec<f64>,
 }
 
+impl Calibration {
+    /// Looks up the ordinate for an abscissa value, linearly interpolating between table entries.
+    ///
+    /// Values outside of the table are clamped to the first or last ordinate. Returns `None` if
+    /// the table is empty or `x` is NaN.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::Calibration;
+    /// let calibration = Calibration {
+    ///     abscissa: vec![0.0, 10.0, 20.0],
+    ///     ordinate: vec![0.0, 1.0, 4.0],
+    /// };
+    /// assert_eq!(Some(2.5), calibration.interpolate(15.0));
+    /// assert_eq!(Some(4.0), calibration.interpolate(30.0));
+    /// ```
+    pub fn interpolate(&self, x: f64) -> Option<f64> {
+        let n = self.abscissa.len().min(self.ordinate.len());
+        if n == 0 || x.is_nan() {
+            return None;
+        }
+        let abscissa = &self.abscissa[..n];
+        let ordinate = &self.ordinate[..n];
+        if x <= abscissa[0] {
+            return Some(ordinate[0]);
+        }
+        if x >= abscissa[n - 1] {
+            return Some(ordinate[n - 1]);
+        }
+        match abscissa.binary_search_by(|a| a.partial_cmp(&x).unwrap_or(Ordering::Less)) {
+            Ok(i) => Some(ordinate[i]),
+            Err(i) => {
+                let fraction = (x - abscissa[i - 1]) / (abscissa[i] - abscissa[i - 1]);
+                Some(ordinate[i - 1] + fraction * (ordinate[i] - ordinate[i - 1]))
+            }
+        }
+    }
+}
+
 /// A type of calibration table.
 ///
 /// Really a pair between type and channel number.
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
 pub enum CalibrationTableKind {
     /// An amplitude calibration table.
     Amplitude(Channel),
@@ -457,7 +943,7 @@ impl CalibrationTableKind {
 }
 
 /// A sample data record.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Record {
     /// The start of the range gate, in second.
     pub time_sorg: f64,
@@ -501,7 +987,7 @@ impl fmt::Display for Record {
 }
 
 /// A sample block.
-#[derive(Debug)]
+#[derive(Clone, Debug)]
 pub struct Block {
     /// The start of the sample block, in seconds.
     pub time_sosbl: f64,
@@ -581,8 +1067,24 @@ impl fmt::Display for Channel {
 mod tests {
     use super::*;
 
+    use std::env;
+    use std::f64;
     use std::fs::remove_file;
 
+    use backend::{Backend, Flaky, Memory, test_info, test_record};
+    use error::Error;
+    use index::Index;
+
+    fn flaky(bad: Vec<u32>) -> File<Flaky> {
+        File::new(Flaky::new((0..5).map(|i| test_record(i as f64)).collect(), bad))
+    }
+
+    #[test]
+    fn file_is_send() {
+        fn assert_send<T: Send>() {}
+        assert_send::<File>();
+    }
+
     #[test]
     fn open_throws_on_bad_filename() {
         assert!(File::open("notafile.sdf").is_err());
@@ -609,6 +1111,172 @@ mod tests {
         assert_eq!(calib.ordinate.len(), calib.abscissa.len());
     }
 
+    #[test]
+    fn calibration_interpolate() {
+        let calibration = Calibration {
+            abscissa: vec![1.0, 2.0, 4.0],
+            ordinate: vec![10.0, 20.0, 0.0],
+        };
+        assert_eq!(Some(10.0), calibration.interpolate(0.0));
+        assert_eq!(Some(15.0), calibration.interpolate(1.5));
+        assert_eq!(Some(20.0), calibration.interpolate(2.0));
+        assert_eq!(Some(10.0), calibration.interpolate(3.0));
+        assert_eq!(Some(0.0), calibration.interpolate(5.0));
+        assert_eq!(None, calibration.interpolate(::std::f64::NAN));
+        let empty = Calibration {
+            abscissa: Vec::new(),
+            ordinate: Vec::new(),
+        };
+        assert_eq!(None, empty.interpolate(1.0));
+    }
+
+    #[test]
+    fn records_skip_bad_records() {
+        let mut file = flaky(vec![2, 4]);
+        let results = file.records().unwrap().collect::<Vec<_>>();
+        assert_eq!(5, results.len());
+        assert_eq!(0.0, results[0].as_ref().unwrap().time_sorg);
+        assert_eq!(2, results[1].as_ref().unwrap_err().index);
+        assert_eq!(2.0, results[2].as_ref().unwrap().time_sorg);
+        assert_eq!(4, results[3].as_ref().unwrap_err().index);
+        assert_eq!(4.0, results[4].as_ref().unwrap().time_sorg);
+    }
+
+    #[test]
+    fn records_from_current_position() {
+        let mut file = flaky(Vec::new());
+        file.seek(4).unwrap();
+        let times = file.records().unwrap().map(|r| r.unwrap().time_sorg).collect::<Vec<_>>();
+        assert_eq!(vec![3.0, 4.0], times);
+    }
+
+    #[test]
+    fn records_stop_at_first_error() {
+        let mut file = flaky(vec![3]);
+        let records = file.records().unwrap().take_while(|r| r.is_ok()).count();
+        assert_eq!(2, records);
+    }
+
+    #[test]
+    fn records_in_range() {
+        let mut file = flaky(vec![3]);
+        let results = file.records_in_range(2..4).unwrap().collect::<Vec<_>>();
+        assert_eq!(2, results.len());
+        assert_eq!(1.0, results[0].as_ref().unwrap().time_sorg);
+        assert_eq!(3, results[1].as_ref().unwrap_err().index);
+        assert_eq!(0, file.records_in_range(6..10).unwrap().count());
+        assert_eq!(0, file.records_in_range(3..3).unwrap().count());
+    }
+
+    #[test]
+    fn records_between_times() {
+        let mut file = flaky(Vec::new());
+        let times = file.records_between_times(0.5, 3.0, TimeBase::Internal)
+                        .unwrap()
+                        .map(|r| r.unwrap().time_sorg)
+                        .collect::<Vec<_>>();
+        assert_eq!(vec![1.0, 2.0], times);
+        let times = file.records_between_times(3.0, 10.0, TimeBase::External)
+                        .unwrap()
+                        .map(|r| r.unwrap().time_external)
+                        .collect::<Vec<_>>();
+        assert_eq!(vec![3.0, 4.0], times);
+        assert_eq!(0,
+                   file.records_between_times(10.0, 20.0, TimeBase::External).unwrap().count());
+        let times = file.records_between_times(f64::NEG_INFINITY, 2.0, TimeBase::Internal)
+                        .unwrap()
+                        .map(|r| r.unwrap().time_sorg)
+                        .collect::<Vec<_>>();
+        assert_eq!(vec![0.0, 1.0], times);
+    }
+
+    #[test]
+    fn reindex_with_progress() {
+        let mut file = flaky(Vec::new());
+        let mut reports = Vec::new();
+        file.reindex_with_progress(|progress| {
+                reports.push(progress);
+                true
+            })
+            .unwrap();
+        assert_eq!(2, reports.len());
+        assert!(!reports[0].finished);
+        assert!(reports[1].finished);
+        let mut calls = 0;
+        match Backend::reindex_with_progress(&mut file.backend, &mut |_| {
+            calls += 1;
+            false
+        }) {
+            Err(Error::Cancelled) => {}
+            result => panic!("Expected a cancelled reindex, got {:?}", result),
+        }
+        assert_eq!(1, calls);
+    }
+
+    #[test]
+    fn record_count() {
+        let mut file = flaky(Vec::new());
+        file.seek(3).unwrap();
+        assert_eq!(5, file.record_count().unwrap());
+        assert_eq!(3, file.tell().unwrap());
+        let path = env::temp_dir().join("sdf-rs-record-count.ridx");
+        let index = file.build_index(&path).unwrap();
+        file.set_index(index).unwrap();
+        assert_eq!(5, file.record_count().unwrap());
+        file.seek_time(2.5).unwrap();
+        assert_eq!(4, file.tell().unwrap());
+        file.seek_time_external(-1.0).unwrap();
+        assert_eq!(1, file.tell().unwrap());
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn summary() {
+        let mut file = flaky(Vec::new());
+        file.seek(2).unwrap();
+        let summary = file.summary().unwrap();
+        assert_eq!(2, file.tell().unwrap());
+        assert_eq!(Summary {
+                       record_count: 5,
+                       start_time: 0.0,
+                       end_time: 4.0,
+                       start_time_external: 0.0,
+                       end_time_external: 4.0,
+                   },
+                   summary);
+        assert_eq!(4.0, summary.duration());
+        assert_eq!(Some(1.0), summary.pulse_rate());
+        let path = env::temp_dir().join("sdf-rs-summary.ridx");
+        let index = file.build_index(&path).unwrap();
+        file.set_index(index).unwrap();
+        assert_eq!(summary, file.summary().unwrap());
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn set_index_mismatch() {
+        let path = env::temp_dir().join("sdf-rs-set-index-mismatch.ridx");
+        let index = flaky(Vec::new()).build_index(&path).unwrap();
+        let records = flaky(Vec::new()).records().unwrap().map(|r| r.unwrap()).collect::<Vec<_>>();
+        let mut file = File::new(Memory::new(test_info(), records[..4].to_vec()));
+        file.seek(2).unwrap();
+        match file.set_index(index) {
+            Err(Error::IndexMismatch(_)) => {}
+            result => panic!("Expected an index mismatch, got {:?}", result),
+        }
+        assert!(file.index().is_none());
+        assert_eq!(2, file.tell().unwrap());
+        let index = Index::open(&path).unwrap();
+        let mut records = records;
+        records[0].time_external = 100.0;
+        let mut file = File::new(Memory::new(test_info(), records));
+        match file.set_index(index) {
+            Err(Error::IndexMismatch(_)) => {}
+            result => panic!("Expected an index mismatch, got {:?}", result),
+        }
+        remove_file(&path).unwrap();
+    }
+
     #[test]
     fn smart_index() {
         remove_file("data/110630_174316.idx").unwrap_or(());
@@ -0,0 +1,303 @@
+//! Gaussian decomposition of waveforms.
+//!
+//! A waveform is modeled as a constant background plus a sum of Gaussian echoes:
+//!
+//! ```text
+//! y(t) = b + sum(a_i * exp(-(t - mu_i)^2 / (2 * sigma_i^2)))
+//! ```
+//!
+//! The model is fit to the samples with the Levenberg-Marquardt algorithm, seeded with one echo at
+//! each detected peak. Time is measured in samples, so an echo's position is a sub-sample index
+//! into the waveform. Positions are kept within the waveform, so an echo that the fit wants to
+//! move past either end stops at the first or last sample.
+
+use std::cmp::Ordering;
+
+const MAX_ITERATIONS: usize = 100;
+const INITIAL_LAMBDA: f64 = 1e-3;
+const MAX_LAMBDA: f64 = 1e10;
+const TOLERANCE: f64 = 1e-10;
+const MIN_SIGMA: f64 = 0.1;
+const FWHM_PER_SIGMA: f64 = 2.3548200450309493;
+
+/// One fitted Gaussian echo.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Echo {
+    /// The echo's amplitude above the background, in digitizer units.
+    pub amplitude: f64,
+    /// The echo's center, as a (fractional) sample index.
+    pub position: f64,
+    /// The echo's standard deviation, in samples.
+    pub sigma: f64,
+    /// The root-mean-square residual of the whole waveform's fit.
+    pub residual: f64,
+}
+
+impl Echo {
+    /// Returns the echo's full width at half maximum, in samples.
+    pub fn fwhm(&self) -> f64 {
+        self.sigma * FWHM_PER_SIGMA
+    }
+}
+
+/// Fits a sum of Gaussians to a waveform.
+///
+/// One echo is fit for each seed, which is the sample index of a detected peak. The echoes are
+/// returned in the same order as the seeds. If the fit fails to improve on the seeds, e.g.
+/// because there are too few samples, the seeded echoes are returned as-is.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::gaussian::decompose;
+/// let samples = [10, 12, 30, 80, 30, 12, 10];
+/// let echoes = decompose(&samples, &[3]);
+/// assert_eq!(1, echoes.len());
+/// assert!((echoes[0].position - 3.0).abs() < 0.01);
+/// ```
+pub fn decompose(samples: &[u16], seeds: &[usize]) -> Vec<Echo> {
+    if seeds.is_empty() || samples.is_empty() {
+        return Vec::new();
+    }
+    let data = samples.iter().map(|&s| s as f64).collect::<Vec<_>>();
+    let mut params = seed(&data, seeds);
+    let mut sse = sum_of_squares(&data, &params);
+    if data.len() > params.len() {
+        let mut lambda = INITIAL_LAMBDA;
+        for _ in 0..MAX_ITERATIONS {
+            let (jtj, jtr) = normal_equations(&data, &params);
+            let mut improved = false;
+            while lambda < MAX_LAMBDA {
+                let mut damped = jtj.clone();
+                for i in 0..params.len() {
+                    damped[i][i] += lambda * jtj[i][i].max(TOLERANCE);
+                }
+                if let Some(step) = solve(damped, jtr.clone()) {
+                    let candidate = constrain(params.iter()
+                                                    .zip(step.iter())
+                                                    .map(|(p, s)| p + s)
+                                                    .collect(),
+                                              data.len());
+                    let candidate_sse = sum_of_squares(&data, &candidate);
+                    if candidate_sse < sse {
+                        let change = (sse - candidate_sse) / sse.max(TOLERANCE);
+                        params = candidate;
+                        sse = candidate_sse;
+                        lambda /= 10.0;
+                        improved = change > TOLERANCE;
+                        break;
+                    }
+                }
+                lambda *= 10.0;
+            }
+            if !improved {
+                break;
+            }
+        }
+    }
+    let residual = (sse / data.len() as f64).sqrt();
+    params[1..]
+        .chunks(3)
+        .map(|p| {
+            Echo {
+                amplitude: p[0],
+                position: p[1],
+                sigma: p[2],
+                residual: residual,
+            }
+        })
+        .collect()
+}
+
+/// Builds the initial parameter vector `[b, a_0, mu_0, sigma_0, a_1, ...]`.
+///
+/// The background is the smallest sample, and each echo's width is estimated from where the
+/// waveform falls to half of the peak's height.
+fn seed(data: &[f64], seeds: &[usize]) -> Vec<f64> {
+    let background = data.iter().cloned().fold(data[0], f64::min);
+    let mut params = vec![background];
+    for &index in seeds {
+        let index = index.min(data.len() - 1);
+        let amplitude = data[index] - background;
+        let half = background + amplitude / 2.0;
+        let mut left = index;
+        while left > 0 && data[left] > half {
+            left -= 1;
+        }
+        let mut right = index;
+        while right < data.len() - 1 && data[right] > half {
+            right += 1;
+        }
+        let sigma = ((right - left) as f64 / FWHM_PER_SIGMA).max(MIN_SIGMA);
+        params.push(amplitude);
+        params.push(index as f64);
+        params.push(sigma);
+    }
+    params
+}
+
+fn model(params: &[f64], t: f64) -> f64 {
+    params[0] +
+    params[1..]
+        .chunks(3)
+        .map(|p| p[0] * (-(t - p[1]).powi(2) / (2.0 * p[2].powi(2))).exp())
+        .fold(0.0, |a, b| a + b)
+}
+
+fn sum_of_squares(data: &[f64], params: &[f64]) -> f64 {
+    data.iter()
+        .enumerate()
+        .map(|(t, y)| (y - model(params, t as f64)).powi(2))
+        .fold(0.0, |a, b| a + b)
+}
+
+/// Returns `(J^T J, J^T r)` for the current parameters.
+fn normal_equations(data: &[f64], params: &[f64]) -> (Vec<Vec<f64>>, Vec<f64>) {
+    let n = params.len();
+    let mut jtj = vec![vec![0.0; n]; n];
+    let mut jtr = vec![0.0; n];
+    let mut row = vec![0.0; n];
+    for (t, y) in data.iter().enumerate() {
+        let t = t as f64;
+        row[0] = 1.0;
+        for (i, p) in params[1..].chunks(3).enumerate() {
+            let (a, mu, sigma) = (p[0], p[1], p[2]);
+            let d = t - mu;
+            let g = (-d * d / (2.0 * sigma * sigma)).exp();
+            row[1 + 3 * i] = g;
+            row[2 + 3 * i] = a * g * d / (sigma * sigma);
+            row[3 + 3 * i] = a * g * d * d / (sigma * sigma * sigma);
+        }
+        let r = y - model(params, t);
+        for i in 0..n {
+            jtr[i] += row[i] * r;
+            for j in 0..n {
+                jtj[i][j] += row[i] * row[j];
+            }
+        }
+    }
+    (jtj, jtr)
+}
+
+/// Keeps widths physically meaningful, and positions within the waveform's samples.
+fn constrain(mut params: Vec<f64>, len: usize) -> Vec<f64> {
+    for i in 0..(params.len() - 1) / 3 {
+        params[2 + 3 * i] = params[2 + 3 * i].max(0.0).min((len - 1) as f64);
+        params[3 + 3 * i] = params[3 + 3 * i].abs().max(MIN_SIGMA);
+    }
+    params
+}
+
+/// Solves `a x = b` by Gaussian elimination with partial pivoting, returning `None` if `a` is
+/// singular or the solution isn't finite, e.g. because `a` has NaNs in it.
+fn solve(mut a: Vec<Vec<f64>>, mut b: Vec<f64>) -> Option<Vec<f64>> {
+    let n = b.len();
+    for col in 0..n {
+        let pivot = (col..n)
+                        .max_by(|&i, &j| {
+                            a[i][col]
+                                .abs()
+                                .partial_cmp(&a[j][col].abs())
+                                .unwrap_or(Ordering::Equal)
+                        })
+                        .unwrap();
+        // Written this way round so that a NaN pivot is rejected too.
+        if !(a[pivot][col].abs() >= TOLERANCE) {
+            return None;
+        }
+        a.swap(col, pivot);
+        b.swap(col, pivot);
+        for row in col + 1..n {
+            let factor = a[row][col] / a[col][col];
+            for k in col..n {
+                a[row][k] -= factor * a[col][k];
+            }
+            b[row] -= factor * b[col];
+        }
+    }
+    let mut x = vec![0.0; n];
+    for row in (0..n).rev() {
+        let sum = (row + 1..n).map(|k| a[row][k] * x[k]).fold(0.0, |a, b| a + b);
+        x[row] = (b[row] - sum) / a[row][row];
+    }
+    if x.iter().all(|value| value.is_finite()) {
+        Some(x)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn waveform(background: f64, echoes: &[(f64, f64, f64)], len: usize) -> Vec<u16> {
+        (0..len)
+            .map(|t| {
+                let t = t as f64;
+                let y = echoes.iter()
+                              .map(|&(a, mu, sigma)| {
+                                  a * (-(t - mu).powi(2) / (2.0 * sigma * sigma)).exp()
+                              })
+                              .fold(background, |a, b| a + b);
+                y.round() as u16
+            })
+            .collect()
+    }
+
+    #[test]
+    fn single_echo() {
+        let samples = waveform(10.0, &[(150.0, 12.3, 1.8)], 30);
+        let echoes = decompose(&samples, &[12]);
+        assert_eq!(1, echoes.len());
+        assert!((echoes[0].amplitude - 150.0).abs() < 2.0, "{:?}", echoes);
+        assert!((echoes[0].position - 12.3).abs() < 0.05, "{:?}", echoes);
+        assert!((echoes[0].sigma - 1.8).abs() < 0.05, "{:?}", echoes);
+        assert!(echoes[0].residual < 1.0);
+    }
+
+    #[test]
+    fn overlapping_echoes() {
+        let samples = waveform(8.0, &[(120.0, 10.0, 1.5), (80.0, 14.5, 1.5)], 30);
+        let echoes = decompose(&samples, &[10, 15]);
+        assert_eq!(2, echoes.len());
+        assert!((echoes[0].position - 10.0).abs() < 0.1, "{:?}", echoes);
+        assert!((echoes[1].position - 14.5).abs() < 0.1, "{:?}", echoes);
+        assert!((echoes[1].amplitude - 80.0).abs() < 3.0, "{:?}", echoes);
+    }
+
+    #[test]
+    fn too_few_samples() {
+        let echoes = decompose(&[10, 50, 10], &[1]);
+        assert_eq!(1, echoes.len());
+        assert_eq!(1.0, echoes[0].position);
+    }
+
+    #[test]
+    fn flat_waveform() {
+        let echoes = decompose(&[10; 20], &[5, 12]);
+        assert_eq!(2, echoes.len());
+        assert!(echoes.iter().all(|e| e.position >= 0.0 && e.position <= 19.0));
+    }
+
+    #[test]
+    fn echo_at_the_edge() {
+        let samples = waveform(10.0, &[(150.0, 21.0, 2.0)], 20);
+        let echoes = decompose(&samples, &[19]);
+        assert!(echoes[0].position >= 0.0 && echoes[0].position <= 19.0,
+                "{:?}",
+                echoes);
+    }
+
+    #[test]
+    fn nan_pivot() {
+        let nan = ::std::f64::NAN;
+        assert_eq!(None, solve(vec![vec![nan, 1.0], vec![1.0, 1.0]], vec![1.0, 1.0]));
+        assert_eq!(None, solve(vec![vec![1.0, nan], vec![1.0, 1.0]], vec![1.0, 1.0]));
+    }
+
+    #[test]
+    fn no_seeds() {
+        assert!(decompose(&[10, 50, 10], &[]).is_empty());
+    }
+}
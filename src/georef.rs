@@ -0,0 +1,599 @@
+//! Georeference discretized points with a trajectory.
+//!
+//! A `Point`'s coordinates are in the scanner's own frame. To put them on a map, we need the
+//! platform's position and attitude at the time of each point, which comes from a `Trajectory`,
+//! plus the scanner's mounting: the lever arm from the navigation system to the scanner and the
+//! boresight angles between the two.
+//!
+//! The scanner frame is assumed to line up with the body frame (x forward, y right, z down) once
+//! the boresight rotation has been applied. Attitude is applied in the usual roll, pitch, heading
+//! order to get a north-east-down vector, which is then placed on the WGS84 ellipsoid.
+
+use std::cmp::Ordering;
+use std::f64::consts::PI;
+use std::fs;
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use Result;
+use convert::Point;
+use error::Error;
+
+const WGS84_A: f64 = 6378137.0;
+const WGS84_F: f64 = 1.0 / 298.257223563;
+const UTM_SCALE: f64 = 0.9996;
+const UTM_FALSE_EASTING: f64 = 500000.0;
+const UTM_FALSE_NORTHING: f64 = 10000000.0;
+const SBET_RECORD_SIZE: usize = 17 * 8;
+
+/// The platform's position and attitude at one instant.
+///
+/// Angles are in radians and the height is ellipsoidal, in meters.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Pose {
+    /// The time, in the trajectory's time base.
+    pub time: f64,
+    /// The geodetic latitude.
+    pub latitude: f64,
+    /// The longitude.
+    pub longitude: f64,
+    /// The height above the ellipsoid.
+    pub height: f64,
+    /// The roll, positive right wing down.
+    pub roll: f64,
+    /// The pitch, positive nose up.
+    pub pitch: f64,
+    /// The true heading, clockwise from north.
+    pub heading: f64,
+}
+
+/// A time series of platform poses.
+#[derive(Clone, Debug)]
+pub struct Trajectory {
+    poses: Vec<Pose>,
+}
+
+impl Trajectory {
+    /// Creates a trajectory from poses, which are sorted by time.
+    pub fn new(mut poses: Vec<Pose>) -> Trajectory {
+        poses.sort_by(|a, b| a.time.partial_cmp(&b.time).unwrap_or(Ordering::Equal));
+        Trajectory { poses: poses }
+    }
+
+    /// Reads a trajectory from an SBET file.
+    ///
+    /// SBET files are a sequence of records, each seventeen little-endian doubles. We use the
+    /// time, latitude, longitude, height, roll, pitch and heading and ignore the velocities,
+    /// accelerations and angular rates.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sdf::georef::Trajectory;
+    /// let trajectory = Trajectory::from_sbet_path("sbet.out").unwrap();
+    /// ```
+    pub fn from_sbet_path<P: AsRef<Path>>(path: P) -> Result<Trajectory> {
+        Trajectory::from_sbet(try!(fs::File::open(path)))
+    }
+
+    /// Reads a trajectory from SBET data.
+    pub fn from_sbet<R: Read>(mut read: R) -> Result<Trajectory> {
+        let mut bytes = Vec::new();
+        let _ = try!(read.read_to_end(&mut bytes));
+        if bytes.len() % SBET_RECORD_SIZE != 0 {
+            return Err(Error::InvalidTrajectory(format!("SBET data is {} bytes, which is not a \
+                                                         multiple of the record size ({})",
+                                                        bytes.len(),
+                                                        SBET_RECORD_SIZE)));
+        }
+        let poses = bytes.chunks(SBET_RECORD_SIZE)
+                         .map(|record| {
+                             let field = |i: usize| LittleEndian::read_f64(&record[i * 8..]);
+                             Pose {
+                                 time: field(0),
+                                 latitude: field(1),
+                                 longitude: field(2),
+                                 height: field(3),
+                                 roll: field(7),
+                                 pitch: field(8),
+                                 heading: field(9),
+                             }
+                         })
+                         .collect();
+        Ok(Trajectory::new(poses))
+    }
+
+    /// Reads a trajectory from a CSV file.
+    ///
+    /// Each line is `time,latitude,longitude,height,roll,pitch,heading`, with angles in degrees.
+    /// Blank lines, lines starting with `#` and a header line are skipped.
+    ///
+    /// # Examples
+    ///
+    /// ```no_run
+    /// use sdf::georef::Trajectory;
+    /// let trajectory = Trajectory::from_csv_path("trajectory.csv").unwrap();
+    /// ```
+    pub fn from_csv_path<P: AsRef<Path>>(path: P) -> Result<Trajectory> {
+        Trajectory::from_csv(BufReader::new(try!(fs::File::open(path))))
+    }
+
+    /// Reads a trajectory from CSV data.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::georef::Trajectory;
+    /// let csv = "time,latitude,longitude,height,roll,pitch,heading
+    /// 1.0,45.0,-93.0,1000.0,0.0,0.0,90.0
+    /// ";
+    /// let trajectory = Trajectory::from_csv(csv.as_bytes()).unwrap();
+    /// assert_eq!(1, trajectory.len());
+    /// ```
+    pub fn from_csv<R: BufRead>(read: R) -> Result<Trajectory> {
+        let mut poses = Vec::new();
+        let mut first = true;
+        for (i, line) in read.lines().enumerate() {
+            let line = try!(line);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let fields = line.split(',').map(|f| f.trim().parse::<f64>()).collect::<Vec<_>>();
+            // Only the first line can be a header.
+            if first && fields.iter().any(|f| f.is_err()) {
+                first = false;
+                continue;
+            }
+            first = false;
+            if fields.len() != 7 {
+                return Err(Error::InvalidTrajectory(format!("Expected seven fields on line {}, \
+                                                             got {}",
+                                                            i + 1,
+                                                            fields.len())));
+            }
+            let mut values = [0.0; 7];
+            for (value, field) in values.iter_mut().zip(fields.into_iter()) {
+                *value = try!(field.map_err(|e| {
+                    Error::InvalidTrajectory(format!("Line {}: {}", i + 1, e))
+                }));
+            }
+            poses.push(Pose {
+                time: values[0],
+                latitude: values[1].to_radians(),
+                longitude: values[2].to_radians(),
+                height: values[3],
+                roll: values[4].to_radians(),
+                pitch: values[5].to_radians(),
+                heading: values[6].to_radians(),
+            });
+        }
+        Ok(Trajectory::new(poses))
+    }
+
+    /// Returns the number of poses in this trajectory.
+    pub fn len(&self) -> usize {
+        self.poses.len()
+    }
+
+    /// Returns true if this trajectory has no poses.
+    pub fn is_empty(&self) -> bool {
+        self.poses.is_empty()
+    }
+
+    /// Interpolates the platform's pose at the given time.
+    ///
+    /// Returns `None` if the time is outside of the trajectory.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::georef::{Pose, Trajectory};
+    /// let pose = |time, height| Pose { time: time, latitude: 0.0, longitude: 0.0, height: height,
+    ///     roll: 0.0, pitch: 0.0, heading: 0.0 };
+    /// let trajectory = Trajectory::new(vec![pose(0.0, 100.0), pose(1.0, 200.0)]);
+    /// assert_eq!(150.0, trajectory.pose(0.5).unwrap().height);
+    /// assert!(trajectory.pose(2.0).is_none());
+    /// ```
+    pub fn pose(&self, time: f64) -> Option<Pose> {
+        if self.poses.is_empty() || time < self.poses[0].time ||
+           time > self.poses[self.poses.len() - 1].time {
+            return None;
+        }
+        match self.poses
+                  .binary_search_by(|p| p.time.partial_cmp(&time).unwrap_or(Ordering::Less)) {
+            Ok(i) => Some(self.poses[i]),
+            Err(i) => {
+                let (a, b) = (&self.poses[i - 1], &self.poses[i]);
+                let fraction = (time - a.time) / (b.time - a.time);
+                let linear = |a: f64, b: f64| a + fraction * (b - a);
+                let angle = |a: f64, b: f64| a + fraction * wrap(b - a);
+                Some(Pose {
+                    time: time,
+                    latitude: linear(a.latitude, b.latitude),
+                    longitude: angle(a.longitude, b.longitude),
+                    height: linear(a.height, b.height),
+                    roll: angle(a.roll, b.roll),
+                    pitch: angle(a.pitch, b.pitch),
+                    heading: angle(a.heading, b.heading),
+                })
+            }
+        }
+    }
+}
+
+/// The coordinate system of georeferenced points.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Projection {
+    /// Earth-centered, earth-fixed cartesian coordinates, in meters.
+    Ecef,
+    /// Universal Transverse Mercator easting, northing and ellipsoidal height, in meters.
+    Utm {
+        /// The UTM zone number, from 1 to 60.
+        zone: u8,
+        /// Is this a northern hemisphere zone?
+        north: bool,
+    },
+}
+
+const WGS84_WKT: &'static str = "DATUM[\"WGS_1984\",SPHEROID[\"WGS 84\",6378137,298.257223563]],\
+                                 PRIMEM[\"Greenwich\",0]";
+
+impl Projection {
+    /// Returns the OGC WKT description of this coordinate system, e.g. for a LAS file.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::georef::Projection;
+    /// let wkt = Projection::Utm { zone: 15, north: true }.wkt();
+    /// assert!(wkt.starts_with("PROJCS[\"WGS 84 / UTM zone 15N\""));
+    /// ```
+    pub fn wkt(&self) -> String {
+        match *self {
+            Projection::Ecef => {
+                format!("GEOCCS[\"WGS 84\",{},UNIT[\"metre\",1],AXIS[\"Geocentric X\",OTHER],\
+                         AXIS[\"Geocentric Y\",OTHER],AXIS[\"Geocentric Z\",NORTH],\
+                         AUTHORITY[\"EPSG\",\"4978\"]]",
+                        WGS84_WKT)
+            }
+            Projection::Utm { zone, north } => {
+                format!("PROJCS[\"WGS 84 / UTM zone {}{}\",GEOGCS[\"WGS 84\",{},\
+                         UNIT[\"degree\",0.0174532925199433]],\
+                         PROJECTION[\"Transverse_Mercator\"],\
+                         PARAMETER[\"latitude_of_origin\",0],\
+                         PARAMETER[\"central_meridian\",{}],PARAMETER[\"scale_factor\",{}],\
+                         PARAMETER[\"false_easting\",{}],PARAMETER[\"false_northing\",{}],\
+                         UNIT[\"metre\",1],AUTHORITY[\"EPSG\",\"{}\"]]",
+                        zone,
+                        if north { "N" } else { "S" },
+                        WGS84_WKT,
+                        zone as i32 * 6 - 183,
+                        UTM_SCALE,
+                        UTM_FALSE_EASTING,
+                        if north { 0.0 } else { UTM_FALSE_NORTHING },
+                        if north { 32600 } else { 32700 } + zone as u32)
+            }
+        }
+    }
+}
+
+/// Returns the UTM zone number for a longitude in degrees.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::georef::utm_zone;
+/// assert_eq!(15, utm_zone(-93.0));
+/// assert_eq!(31, utm_zone(3.0));
+/// ```
+pub fn utm_zone(longitude: f64) -> u8 {
+    let zone = ((longitude + 180.0) / 6.0).floor() as i64 % 60 + 1;
+    if zone < 1 {
+        (zone + 60) as u8
+    } else {
+        zone as u8
+    }
+}
+
+/// Georeferences points with a trajectory and the scanner's mounting.
+///
+/// # Examples
+///
+/// ```no_run
+/// use sdf::convert::discretize;
+/// use sdf::file::File;
+/// use sdf::georef::{Georeferencer, Projection, Trajectory};
+/// let trajectory = Trajectory::from_sbet_path("sbet.out").unwrap();
+/// let georeferencer = Georeferencer::new(trajectory, Projection::Utm { zone: 15, north: true })
+///                         .lever_arm([0.1, 0.0, -0.3])
+///                         .boresight(0.01, -0.02, 0.1);
+/// let mut file = File::open("data/110630_174316.sdf").unwrap();
+/// let ref file_info = file.info().unwrap();
+/// file.reindex().unwrap();
+/// for ref record in file {
+///     for point in discretize(record, file_info).unwrap() {
+///         let xyz = georeferencer.georeference(&point).unwrap();
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Georeferencer {
+    trajectory: Trajectory,
+    projection: Projection,
+    lever_arm: [f64; 3],
+    boresight: [[f64; 3]; 3],
+    time_offset: f64,
+}
+
+impl Georeferencer {
+    /// Creates a new georeferencer with no lever arm, boresight or time offset.
+    pub fn new(trajectory: Trajectory, projection: Projection) -> Georeferencer {
+        Georeferencer {
+            trajectory: trajectory,
+            projection: projection,
+            lever_arm: [0.0; 3],
+            boresight: rotation(0.0, 0.0, 0.0),
+            time_offset: 0.0,
+        }
+    }
+
+    /// Sets the lever arm, the scanner's position in the body frame, in meters.
+    pub fn lever_arm(mut self, lever_arm: [f64; 3]) -> Georeferencer {
+        self.lever_arm = lever_arm;
+        self
+    }
+
+    /// Sets the boresight angles, in degrees, that rotate the scanner frame into the body frame.
+    pub fn boresight(mut self, roll: f64, pitch: f64, yaw: f64) -> Georeferencer {
+        self.boresight = rotation(roll.to_radians(), pitch.to_radians(), yaw.to_radians());
+        self
+    }
+
+    /// Sets an offset, in seconds, that is added to point times to get trajectory times.
+    ///
+    /// Use this when the two are in different time bases, e.g. seconds of the day and seconds of
+    /// the GPS week.
+    pub fn time_offset(mut self, time_offset: f64) -> Georeferencer {
+        self.time_offset = time_offset;
+        self
+    }
+
+    /// Returns the coordinate system of georeferenced points.
+    pub fn projection(&self) -> Projection {
+        self.projection
+    }
+
+    /// Georeferences a point, returning its coordinates in the georeferencer's projection.
+    ///
+    /// Returns an error if the point's time isn't covered by the trajectory.
+    pub fn georeference(&self, point: &Point) -> Result<[f64; 3]> {
+        let time = point.time + self.time_offset;
+        let pose = match self.trajectory.pose(time) {
+            Some(pose) => pose,
+            None => return Err(Error::OutsideTrajectory(time)),
+        };
+        let scanner = [point.x as f64, point.y as f64, point.z as f64];
+        let body = add(multiply(&self.boresight, scanner), self.lever_arm);
+        let ned = multiply(&rotation(pose.roll, pose.pitch, pose.heading), body);
+        let origin = geodetic_to_ecef(pose.latitude, pose.longitude, pose.height);
+        let ecef = add(multiply(&ned_to_ecef(pose.latitude, pose.longitude), ned), origin);
+        Ok(match self.projection {
+            Projection::Ecef => ecef,
+            Projection::Utm { zone, north } => {
+                let (latitude, longitude, height) = ecef_to_geodetic(ecef);
+                let (easting, northing) = utm(latitude, longitude, zone, north);
+                [easting, northing, height]
+            }
+        })
+    }
+}
+
+/// Wraps an angle difference into [-pi, pi).
+fn wrap(angle: f64) -> f64 {
+    let angle = (angle + PI) % (2.0 * PI);
+    if angle < 0.0 {
+        angle + PI
+    } else {
+        angle - PI
+    }
+}
+
+/// Returns the body-to-navigation rotation matrix for the given roll, pitch and yaw.
+fn rotation(roll: f64, pitch: f64, yaw: f64) -> [[f64; 3]; 3] {
+    let (sr, cr) = roll.sin_cos();
+    let (sp, cp) = pitch.sin_cos();
+    let (sy, cy) = yaw.sin_cos();
+    [[cy * cp, cy * sp * sr - sy * cr, cy * sp * cr + sy * sr],
+     [sy * cp, sy * sp * sr + cy * cr, sy * sp * cr - cy * sr],
+     [-sp, cp * sr, cp * cr]]
+}
+
+fn ned_to_ecef(latitude: f64, longitude: f64) -> [[f64; 3]; 3] {
+    let (sl, cl) = latitude.sin_cos();
+    let (so, co) = longitude.sin_cos();
+    [[-sl * co, -so, -cl * co], [-sl * so, co, -cl * so], [cl, 0.0, -sl]]
+}
+
+fn multiply(m: &[[f64; 3]; 3], v: [f64; 3]) -> [f64; 3] {
+    [m[0][0] * v[0] + m[0][1] * v[1] + m[0][2] * v[2],
+     m[1][0] * v[0] + m[1][1] * v[1] + m[1][2] * v[2],
+     m[2][0] * v[0] + m[2][1] * v[1] + m[2][2] * v[2]]
+}
+
+fn add(a: [f64; 3], b: [f64; 3]) -> [f64; 3] {
+    [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+}
+
+fn eccentricity_squared() -> f64 {
+    WGS84_F * (2.0 - WGS84_F)
+}
+
+fn geodetic_to_ecef(latitude: f64, longitude: f64, height: f64) -> [f64; 3] {
+    let e2 = eccentricity_squared();
+    let n = WGS84_A / (1.0 - e2 * latitude.sin().powi(2)).sqrt();
+    [(n + height) * latitude.cos() * longitude.cos(),
+     (n + height) * latitude.cos() * longitude.sin(),
+     (n * (1.0 - e2) + height) * latitude.sin()]
+}
+
+fn ecef_to_geodetic(ecef: [f64; 3]) -> (f64, f64, f64) {
+    let e2 = eccentricity_squared();
+    let p = (ecef[0] * ecef[0] + ecef[1] * ecef[1]).sqrt();
+    let longitude = ecef[1].atan2(ecef[0]);
+    let mut latitude = ecef[2].atan2(p * (1.0 - e2));
+    let mut height = 0.0;
+    for _ in 0..10 {
+        let n = WGS84_A / (1.0 - e2 * latitude.sin().powi(2)).sqrt();
+        height = p / latitude.cos() - n;
+        latitude = ecef[2].atan2(p * (1.0 - e2 * n / (n + height)));
+    }
+    (latitude, longitude, height)
+}
+
+/// Projects a geodetic position, in radians, to UTM easting and northing.
+fn utm(latitude: f64, longitude: f64, zone: u8, north: bool) -> (f64, f64) {
+    let e2 = eccentricity_squared();
+    let e4 = e2 * e2;
+    let e6 = e4 * e2;
+    let ep2 = e2 / (1.0 - e2);
+    let central_meridian = (zone as f64 * 6.0 - 183.0).to_radians();
+    let n = WGS84_A / (1.0 - e2 * latitude.sin().powi(2)).sqrt();
+    let t = latitude.tan().powi(2);
+    let c = ep2 * latitude.cos().powi(2);
+    let a = latitude.cos() * wrap(longitude - central_meridian);
+    let m = WGS84_A *
+            ((1.0 - e2 / 4.0 - 3.0 * e4 / 64.0 - 5.0 * e6 / 256.0) * latitude -
+             (3.0 * e2 / 8.0 + 3.0 * e4 / 32.0 + 45.0 * e6 / 1024.0) * (2.0 * latitude).sin() +
+             (15.0 * e4 / 256.0 + 45.0 * e6 / 1024.0) * (4.0 * latitude).sin() -
+             (35.0 * e6 / 3072.0) * (6.0 * latitude).sin());
+    let easting = UTM_SCALE * n *
+                  (a + (1.0 - t + c) * a.powi(3) / 6.0 +
+                   (5.0 - 18.0 * t + t * t + 72.0 * c - 58.0 * ep2) * a.powi(5) / 120.0) +
+                  UTM_FALSE_EASTING;
+    let northing = UTM_SCALE *
+                   (m +
+                    n * latitude.tan() *
+                    (a * a / 2.0 + (5.0 - t + 9.0 * c + 4.0 * c * c) * a.powi(4) / 24.0 +
+                     (61.0 - 58.0 * t + t * t + 600.0 * c - 330.0 * ep2) * a.powi(6) / 720.0));
+    if north {
+        (easting, northing)
+    } else {
+        (easting, northing + UTM_FALSE_NORTHING)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use byteorder::{LittleEndian, WriteBytesExt};
+
+    fn pose(time: f64, latitude: f64, longitude: f64, heading: f64) -> Pose {
+        Pose {
+            time: time,
+            latitude: latitude.to_radians(),
+            longitude: longitude.to_radians(),
+            height: 0.0,
+            roll: 0.0,
+            pitch: 0.0,
+            heading: heading.to_radians(),
+        }
+    }
+
+    #[test]
+    fn sbet() {
+        let mut bytes = Vec::new();
+        for i in 0..2 {
+            for field in 0..17 {
+                bytes.write_f64::<LittleEndian>((i * 100 + field) as f64).unwrap();
+            }
+        }
+        let trajectory = Trajectory::from_sbet(Cursor::new(bytes)).unwrap();
+        assert_eq!(2, trajectory.len());
+        let pose = trajectory.pose(100.0).unwrap();
+        assert_eq!(101.0, pose.latitude);
+        assert_eq!(102.0, pose.longitude);
+        assert_eq!(103.0, pose.height);
+        assert_eq!(107.0, pose.roll);
+        assert_eq!(108.0, pose.pitch);
+        assert_eq!(109.0, pose.heading);
+        assert!(Trajectory::from_sbet(Cursor::new(vec![0; 100])).is_err());
+    }
+
+    #[test]
+    fn csv() {
+        let csv = "# a comment
+time, latitude, longitude, height, roll, pitch, heading
+1.0, 45.0, -93.0, 1000.0, 0.0, 1.0, 350.0
+
+2.0, 45.0, -93.0, 1100.0, 0.0, 3.0, 10.0
+";
+        let trajectory = Trajectory::from_csv(csv.as_bytes()).unwrap();
+        assert_eq!(2, trajectory.len());
+        let pose = trajectory.pose(1.5).unwrap();
+        assert_eq!(1050.0, pose.height);
+        assert!((pose.pitch.to_degrees() - 2.0).abs() < 1e-9);
+        assert!(wrap(pose.heading).to_degrees().abs() < 1e-9);
+        assert!(Trajectory::from_csv("1.0,2.0,3.0\n".as_bytes()).is_err());
+        assert!(Trajectory::from_csv("1,2,3,4,5,6,7\n1,2,3,4,5,6,x\n".as_bytes()).is_err());
+        assert!(Trajectory::from_csv("t,a,b,c,d,e,f\nt,a,b,c,d,e,f\n".as_bytes()).is_err());
+        let csv = "# comment\nt,a,b,c,d,e,f\n1,2,3,4,5,6,7\n";
+        assert_eq!(1, Trajectory::from_csv(csv.as_bytes()).unwrap().len());
+    }
+
+    #[test]
+    fn wkt() {
+        let wkt = Projection::Utm { zone: 15, north: true }.wkt();
+        assert!(wkt.contains("PARAMETER[\"central_meridian\",-93]"));
+        assert!(wkt.ends_with("AUTHORITY[\"EPSG\",\"32615\"]]"));
+        let wkt = Projection::Utm { zone: 56, north: false }.wkt();
+        assert!(wkt.contains("PARAMETER[\"false_northing\",10000000]"));
+        assert!(wkt.ends_with("AUTHORITY[\"EPSG\",\"32756\"]]"));
+        assert!(Projection::Ecef.wkt().starts_with("GEOCCS[\"WGS 84\",DATUM["));
+    }
+
+    #[test]
+    fn utm_projection() {
+        let (easting, northing) = utm(0.0, 3f64.to_radians(), 31, true);
+        assert!((easting - 500000.0).abs() < 1e-6);
+        assert!(northing.abs() < 1e-6);
+        let (easting, northing) = utm(45f64.to_radians(), -93f64.to_radians(), 15, true);
+        assert!((easting - 500000.0).abs() < 1e-6);
+        assert!((northing - 4982950.4).abs() < 0.1, "{}", northing);
+        let (_, northing) = utm(-45f64.to_radians(), -93f64.to_radians(), 15, false);
+        assert!((northing - (10000000.0 - 4982950.4)).abs() < 0.1);
+    }
+
+    #[test]
+    fn ecef_roundtrip() {
+        let ecef = geodetic_to_ecef(45f64.to_radians(), -93f64.to_radians(), 1234.5);
+        let (latitude, longitude, height) = ecef_to_geodetic(ecef);
+        assert!((latitude.to_degrees() - 45.0).abs() < 1e-9);
+        assert!((longitude.to_degrees() + 93.0).abs() < 1e-9);
+        assert!((height - 1234.5).abs() < 1e-6);
+    }
+
+    #[test]
+    fn body_to_ned() {
+        let heading_east = rotation(0.0, 0.0, 90f64.to_radians());
+        let ned = multiply(&heading_east, [1.0, 0.0, 0.0]);
+        assert!(ned[0].abs() < 1e-12);
+        assert!((ned[1] - 1.0).abs() < 1e-12);
+        let origin = [0.0, 0.0, 0.0];
+        let down = multiply(&ned_to_ecef(0.0, 0.0), [0.0, 0.0, 10.0]);
+        assert_eq!([-10.0, 0.0, 0.0], add(down, origin));
+    }
+
+    #[test]
+    fn outside_trajectory() {
+        let trajectory = Trajectory::new(vec![pose(0.0, 0.0, 0.0, 0.0), pose(1.0, 0.0, 0.0, 0.0)]);
+        assert!(trajectory.pose(-0.1).is_none());
+        assert!(trajectory.pose(1.1).is_none());
+        assert_eq!(Some(pose(1.0, 0.0, 0.0, 0.0)), trajectory.pose(1.0));
+    }
+}
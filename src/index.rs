@@ -0,0 +1,379 @@
+//! A record index that we build and read ourselves.
+//!
+//! sdfifc's .idx files are opaque, so there's no cheap way to ask them how many records a file has
+//! or where a given time falls. Our index is built in one pass over the records and holds each
+//! record's timestamps and facet. It is memory-mapped when opened, so the record count is free and
+//! finding a timestamp is a binary search.
+//!
+//! sdfifc doesn't tell us where a record lives in the data file, so entries hold no record
+//! offsets. They're addressed by their (one-based) record index, and records are still read
+//! through the backend. That means the index doesn't make `File::seek` any faster: seeking to a
+//! record index costs whatever it costs sdfifc.
+//!
+//! An index file is a header followed by fixed-size entries, all little-endian. The header is
+//! the magic bytes `SDFRSIDX`, the format version (`u16`), two reserved bytes, and the number of
+//! records (`u32`). Each entry is:
+//!
+//! | Field | Type |
+//! | ----- | ---- |
+//! | internal time | `f64` |
+//! | external time | `f64` |
+//! | facet | `u16` |
+
+use std::fmt;
+use std::fs;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{ByteOrder, LittleEndian, WriteBytesExt};
+
+use Result;
+use backend::Backend;
+use error::Error;
+use file::{File, TimeBase};
+
+const MAGIC: &'static [u8; 8] = b"SDFRSIDX";
+const VERSION: u16 = 1;
+const HEADER_SIZE: usize = 16;
+const ENTRY_SIZE: usize = 18;
+
+/// One record's entry in an index.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Entry {
+    /// The internal time of the record, in seconds.
+    pub time_sorg: f64,
+    /// The external time of the record, in seconds.
+    pub time_external: f64,
+    /// The facet of the record.
+    pub facet: u16,
+}
+
+/// A memory-mapped record index.
+///
+/// The index doesn't know which data file it belongs to. `File::set_index` checks that the two
+/// have the same first record and the same number of records, but it's still up to the caller to
+/// keep them together.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::file::File;
+/// let mut file = File::open("data/110630_174316.sdf").unwrap();
+/// let index = file.build_index("/tmp/110630_174316.ridx").unwrap();
+/// file.set_index(index).unwrap();
+/// let records = file.record_count().unwrap();
+/// ```
+pub struct Index {
+    map: mmap::Mmap,
+    len: u32,
+}
+
+impl Index {
+    /// Builds an index from all of a file's records, and writes it to a path.
+    ///
+    /// The file is reindexed if needed, and is left at the position it started from, even if the
+    /// build fails. The index is written to a temporary file next to `path`, with `.tmp` appended
+    /// to its name, so an existing index at `path` is only replaced once the new one is complete.
+    pub fn build<B: Backend, P: AsRef<Path>>(file: &mut File<B>, path: P) -> Result<Index> {
+        let path = path.as_ref();
+        try!(file.reindex());
+        let position = try!(file.tell());
+        let mut tmp_path = path.as_os_str().to_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let result = write_entries(file, &tmp_path)
+                         .and_then(|()| fs::rename(&tmp_path, path).map_err(Error::from));
+        if result.is_err() {
+            let _ = fs::remove_file(&tmp_path);
+        }
+        let seek = file.seek(position);
+        try!(result);
+        try!(seek);
+        Index::open(path)
+    }
+
+    /// Opens and memory-maps an existing index.
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Index> {
+        let path = path.as_ref();
+        let map = try!(mmap::Mmap::open(&try!(fs::File::open(path))));
+        let len = {
+            let bytes = map.as_slice();
+            if bytes.len() < HEADER_SIZE || &bytes[0..8] != MAGIC {
+                return Err(Error::UnsupportedFormat(format!("{} is not an index",
+                                                            path.display())));
+            }
+            let version = LittleEndian::read_u16(&bytes[8..10]);
+            if version != VERSION {
+                return Err(Error::UnsupportedFormat(format!("Index version {} in {}",
+                                                            version,
+                                                            path.display())));
+            }
+            let len = LittleEndian::read_u32(&bytes[12..16]);
+            if bytes.len() != HEADER_SIZE + len as usize * ENTRY_SIZE {
+                return Err(Error::UnsupportedFormat(format!("{} should have {} entries",
+                                                            path.display(),
+                                                            len)));
+            }
+            len
+        };
+        Ok(Index {
+            map: map,
+            len: len,
+        })
+    }
+
+    /// Returns the number of records in the index.
+    pub fn len(&self) -> u32 {
+        self.len
+    }
+
+    /// Returns true if the index has no records.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Returns the entry for a one-based record index, if there is one.
+    pub fn entry(&self, index: u32) -> Option<Entry> {
+        if index == 0 || index > self.len {
+            return None;
+        }
+        let start = HEADER_SIZE + (index as usize - 1) * ENTRY_SIZE;
+        let bytes = &self.map.as_slice()[start..start + ENTRY_SIZE];
+        Some(Entry {
+            time_sorg: LittleEndian::read_f64(&bytes[0..8]),
+            time_external: LittleEndian::read_f64(&bytes[8..16]),
+            facet: LittleEndian::read_u16(&bytes[16..18]),
+        })
+    }
+
+    /// Returns the one-based index of the first record at or after a time.
+    ///
+    /// Times are assumed not to decrease through the file. Like seeks, times past the end are
+    /// clamped to the last record. Returns one if the index is empty.
+    pub fn find_time(&self, time: f64, base: TimeBase) -> u32 {
+        let time_of = |index| {
+            let entry = self.entry(index).expect("index is in range");
+            match base {
+                TimeBase::Internal => entry.time_sorg,
+                TimeBase::External => entry.time_external,
+            }
+        };
+        let (mut low, mut high) = (1, self.len + 1);
+        while low < high {
+            let middle = low + (high - low) / 2;
+            if time_of(middle) < time {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low.min(self.len.max(1))
+    }
+}
+
+impl fmt::Debug for Index {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Index {{ len: {} }}", self.len)
+    }
+}
+
+/// Writes an index of all of a file's records to a path.
+fn write_entries<B: Backend>(file: &mut File<B>, path: &Path) -> Result<()> {
+    try!(file.seek(1));
+    let mut writer = BufWriter::new(try!(fs::File::create(path)));
+    try!(write_header(&mut writer, 0));
+    let mut len = 0u32;
+    loop {
+        let record = match file.read() {
+            Ok(record) => record,
+            Err(Error::EndOfFile(_)) => break,
+            Err(err) => return Err(err),
+        };
+        try!(writer.write_f64::<LittleEndian>(record.time_sorg));
+        try!(writer.write_f64::<LittleEndian>(record.time_external));
+        try!(writer.write_u16::<LittleEndian>(record.facet));
+        len += 1;
+    }
+    let _ = try!(writer.seek(SeekFrom::Start(0)));
+    try!(write_header(&mut writer, len));
+    try!(writer.flush());
+    Ok(())
+}
+
+fn write_header<W: Write>(writer: &mut W, len: u32) -> Result<()> {
+    try!(writer.write_all(MAGIC));
+    try!(writer.write_u16::<LittleEndian>(VERSION));
+    try!(writer.write_u16::<LittleEndian>(0));
+    try!(writer.write_u32::<LittleEndian>(len));
+    Ok(())
+}
+
+#[cfg(unix)]
+mod mmap {
+    use std::fs::File;
+    use std::io;
+    use std::os::unix::io::AsRawFd;
+    use std::ptr;
+    use std::slice;
+
+    use libc::{MAP_FAILED, MAP_PRIVATE, PROT_READ, c_void, mmap, munmap};
+
+    /// A read-only memory map of a whole file.
+    pub struct Mmap {
+        ptr: *mut c_void,
+        len: usize,
+    }
+
+    unsafe impl Send for Mmap {}
+    unsafe impl Sync for Mmap {}
+
+    impl Mmap {
+        pub fn open(file: &File) -> io::Result<Mmap> {
+            let len = try!(file.metadata()).len() as usize;
+            if len == 0 {
+                return Ok(Mmap {
+                    ptr: ptr::null_mut(),
+                    len: 0,
+                });
+            }
+            let ptr = unsafe {
+                mmap(ptr::null_mut(), len, PROT_READ, MAP_PRIVATE, file.as_raw_fd(), 0)
+            };
+            if ptr == MAP_FAILED {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(Mmap {
+                    ptr: ptr,
+                    len: len,
+                })
+            }
+        }
+
+        pub fn as_slice(&self) -> &[u8] {
+            if self.len == 0 {
+                &[]
+            } else {
+                unsafe { slice::from_raw_parts(self.ptr as *const u8, self.len) }
+            }
+        }
+    }
+
+    impl Drop for Mmap {
+        fn drop(&mut self) {
+            if self.len > 0 {
+                let _ = unsafe { munmap(self.ptr, self.len) };
+            }
+        }
+    }
+}
+
+#[cfg(not(unix))]
+mod mmap {
+    use std::fs::File;
+    use std::io::{self, Read};
+
+    /// Reads the whole file, on platforms where we don't map it.
+    pub struct Mmap {
+        bytes: Vec<u8>,
+    }
+
+    impl Mmap {
+        pub fn open(mut file: &File) -> io::Result<Mmap> {
+            let mut bytes = Vec::new();
+            let _ = try!(file.read_to_end(&mut bytes));
+            Ok(Mmap { bytes: bytes })
+        }
+
+        pub fn as_slice(&self) -> &[u8] {
+            &self.bytes
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::env;
+    use std::fs;
+
+    use backend::{Flaky, Memory, test_info, test_record};
+    use file::{File, Record, TimeBase};
+
+    fn file(n: usize) -> File<Memory> {
+        File::new(Memory::new(test_info(), records(n)))
+    }
+
+    fn records(n: usize) -> Vec<Record> {
+        (0..n)
+            .map(|i| {
+                let mut record = test_record(i as f64);
+                record.time_external = 100.0 + i as f64;
+                record.facet = (i % 4) as u16;
+                record
+            })
+            .collect()
+    }
+
+    #[test]
+    fn build_and_open() {
+        let path = env::temp_dir().join("sdf-rs-build-and-open.ridx");
+        let mut file = file(10);
+        file.seek(4).unwrap();
+        let index = Index::build(&mut file, &path).unwrap();
+        assert_eq!(4, file.tell().unwrap());
+        assert_eq!(10, index.len());
+        assert_eq!(Some(Entry {
+                       time_sorg: 2.0,
+                       time_external: 102.0,
+                       facet: 2,
+                   }),
+                   index.entry(3));
+        assert_eq!(None, index.entry(0));
+        assert_eq!(None, index.entry(11));
+        let index = Index::open(&path).unwrap();
+        assert_eq!(10, index.len());
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn find_time() {
+        let path = env::temp_dir().join("sdf-rs-find-time.ridx");
+        let index = Index::build(&mut file(10), &path).unwrap();
+        assert_eq!(1, index.find_time(-1.0, TimeBase::Internal));
+        assert_eq!(3, index.find_time(2.0, TimeBase::Internal));
+        assert_eq!(4, index.find_time(2.5, TimeBase::Internal));
+        assert_eq!(10, index.find_time(20.0, TimeBase::Internal));
+        assert_eq!(6, index.find_time(105.0, TimeBase::External));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn empty() {
+        let path = env::temp_dir().join("sdf-rs-empty.ridx");
+        let index = Index::build(&mut file(0), &path).unwrap();
+        assert!(index.is_empty());
+        assert_eq!(1, index.find_time(1.0, TimeBase::Internal));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn read_error() {
+        let path = env::temp_dir().join("sdf-rs-read-error.ridx");
+        let tmp_path = env::temp_dir().join("sdf-rs-read-error.ridx.tmp");
+        let mut file = File::new(Flaky::new(records(10), vec![6]));
+        file.seek(4).unwrap();
+        assert!(Index::build(&mut file, &path).is_err());
+        assert_eq!(4, file.tell().unwrap());
+        assert!(!path.exists());
+        assert!(!tmp_path.exists());
+    }
+
+    #[test]
+    fn not_an_index() {
+        let path = env::temp_dir().join("sdf-rs-not-an-index.ridx");
+        let _ = fs::File::create(&path).unwrap();
+        assert!(Index::open(&path).is_err());
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,503 @@
+//! Write discretized points to LAS 1.4 files.
+//!
+//! Points are written as point data record format 6 or 7. The `convert::Point` fields are mapped
+//! onto LAS attributes as follows:
+//!
+//! - GPS time from `time`.
+//! - Return number from `target` and number of returns from `num_target`, both capped at 15.
+//! - Intensity from the peak amplitude.
+//! - Scan angle from `theta`.
+//! - `range` (`f32`), `facet` (`u16`) and `high_channel` (`u8`) as extra bytes, described by an
+//! extra bytes variable length record.
+//!
+//! Coordinates are stored with a scale of 0.001. The offsets are taken from the first point,
+//! rounded to the meter, so points far from the origin keep their precision. Points must lie
+//! within about 2,000 km of the first point to fit in LAS's 32-bit integer coordinates, and
+//! writing one that doesn't is an `Error::InvalidCoordinate`.
+//!
+//! LAS 1.4 requires a coordinate system for these point formats. `convert::Point`s are in the
+//! scanner's own frame, so by default the file gets an OGC WKT variable length record that
+//! describes a local coordinate system in meters. Georeferenced points are written with
+//! `write_point_at`, by a writer created with their coordinate system's WKT.
+//!
+//! To keep the waveforms alongside the points, use the LAS 1.3 writer in `wdp`.
+
+pub mod wdp;
+
+use std::fs;
+use std::i32;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use Result;
+use convert::Point;
+use error::Error;
+use file::FileInfo;
+use util::{creation_date, write_fixed_str};
+
+const HEADER_SIZE: u16 = 375;
+const VLR_HEADER_SIZE: u16 = 54;
+const EXTRA_BYTES_DESCRIPTOR_SIZE: u16 = 192;
+const EXTRA_BYTES_RECORD_ID: u16 = 4;
+const WKT_RECORD_ID: u16 = 2112;
+const SCANNER_WKT: &'static str = "LOCAL_CS[\"Scanner's own coordinate system\",UNIT[\"metre\",1]]";
+const EXTRA_BYTES_SIZE: u16 = 7;
+const SCALE: f64 = 0.001;
+const SCAN_ANGLE_SCALE: f32 = 0.006;
+const MAX_RETURN_NUMBER: u8 = 15;
+const GLOBAL_ENCODING_WKT: u16 = 0b1_0000;
+
+/// The LAS point data record formats that we can write.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PointFormat {
+    /// Point data record format 6, the basic LAS 1.4 point.
+    Six,
+    /// Point data record format 7, which adds (empty) RGB values to format 6.
+    Seven,
+}
+
+impl PointFormat {
+    fn as_u8(&self) -> u8 {
+        match *self {
+            PointFormat::Six => 6,
+            PointFormat::Seven => 7,
+        }
+    }
+
+    fn record_length(&self) -> u16 {
+        let base = match *self {
+            PointFormat::Six => 30,
+            PointFormat::Seven => 36,
+        };
+        base + EXTRA_BYTES_SIZE
+    }
+}
+
+/// Writes discretized points to a LAS 1.4 file.
+///
+/// The header is written with placeholder values when the writer is created, and is rewritten
+/// with the final point counts and bounds by `close`. A writer that is dropped without being
+/// closed leaves an invalid file behind.
+#[derive(Debug)]
+pub struct LasWriter<W: Write + Seek> {
+    writer: W,
+    format: PointFormat,
+    system_identifier: String,
+    wkt: String,
+    number_of_points: u64,
+    number_of_points_by_return: [u64; 15],
+    extent: Extent,
+}
+
+impl LasWriter<BufWriter<fs::File>> {
+    /// Creates a new LAS file at the given path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::File;
+    /// use sdf::las::{LasWriter, PointFormat};
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let ref file_info = file.info().unwrap();
+    /// let writer = LasWriter::from_path("/dev/null", file_info, PointFormat::Six).unwrap();
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P,
+                                     file_info: &FileInfo,
+                                     format: PointFormat)
+                                     -> Result<LasWriter<BufWriter<fs::File>>> {
+        let file = try!(fs::File::create(path));
+        LasWriter::new(BufWriter::new(file), file_info, format)
+    }
+}
+
+impl<W: Write + Seek> LasWriter<W> {
+    /// Creates a new writer and writes the header and variable length records.
+    ///
+    /// The system identifier is taken from the file info's instrument.
+    pub fn new(writer: W, file_info: &FileInfo, format: PointFormat) -> Result<LasWriter<W>> {
+        LasWriter::with_wkt(writer, file_info, format, SCANNER_WKT)
+    }
+
+    /// Creates a new writer for points in the coordinate system described by an OGC WKT string.
+    pub fn with_wkt(writer: W,
+                    file_info: &FileInfo,
+                    format: PointFormat,
+                    wkt: &str)
+                    -> Result<LasWriter<W>> {
+        let mut writer = LasWriter {
+            writer: writer,
+            format: format,
+            system_identifier: file_info.instrument.clone(),
+            wkt: wkt.to_string(),
+            number_of_points: 0,
+            number_of_points_by_return: [0; 15],
+            extent: Extent::new(),
+        };
+        try!(writer.write_header());
+        try!(writer.write_extra_bytes_vlr());
+        try!(writer.write_wkt_vlr());
+        Ok(writer)
+    }
+
+    /// Writes a point.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use sdf::convert::discretize;
+    /// use sdf::file::File;
+    /// use sdf::las::{LasWriter, PointFormat};
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let ref file_info = file.info().unwrap();
+    /// file.reindex().unwrap();
+    /// let ref record = file.read().unwrap();
+    /// let mut writer = LasWriter::new(Cursor::new(Vec::new()), file_info, PointFormat::Six)
+    ///                      .unwrap();
+    /// for ref point in discretize(record, file_info).unwrap() {
+    ///     writer.write_point(point).unwrap();
+    /// }
+    /// writer.close().unwrap();
+    /// ```
+    pub fn write_point(&mut self, point: &Point) -> Result<()> {
+        self.write_point_at(point, [point.x as f64, point.y as f64, point.z as f64])
+    }
+
+    /// Writes a point with other coordinates, e.g. georeferenced ones, in place of its own.
+    pub fn write_point_at(&mut self, point: &Point, coordinates: [f64; 3]) -> Result<()> {
+        let quantized = try!(self.extent.quantize(coordinates));
+        let return_number = point.target.min(MAX_RETURN_NUMBER);
+        let number_of_returns = point.num_target.min(MAX_RETURN_NUMBER);
+        if return_number > 0 {
+            self.number_of_points_by_return[return_number as usize - 1] += 1;
+        }
+        self.number_of_points += 1;
+
+        for &value in &quantized {
+            try!(self.writer.write_i32::<LittleEndian>(value));
+        }
+        try!(self.writer.write_u16::<LittleEndian>(point.peak.amplitude));
+        try!(self.writer.write_u8(return_number | number_of_returns << 4));
+        try!(self.writer.write_u8(0)); // classification flags, scanner channel, direction, edge
+        try!(self.writer.write_u8(0)); // classification
+        try!(self.writer.write_u8(0)); // user data
+        let scan_angle = (point.theta / SCAN_ANGLE_SCALE).round() as i16;
+        try!(self.writer.write_i16::<LittleEndian>(scan_angle));
+        try!(self.writer.write_u16::<LittleEndian>(0)); // point source id
+        try!(self.writer.write_f64::<LittleEndian>(point.time));
+        if self.format == PointFormat::Seven {
+            for _ in 0..3 {
+                try!(self.writer.write_u16::<LittleEndian>(0));
+            }
+        }
+        try!(self.writer.write_f32::<LittleEndian>(point.range));
+        try!(self.writer.write_u16::<LittleEndian>(point.facet));
+        try!(self.writer.write_u8(point.high_channel as u8));
+        Ok(())
+    }
+
+    /// Rewrites the header with the final counts and bounds, and returns the underlying writer.
+    pub fn close(mut self) -> Result<W> {
+        let _ = try!(self.writer.seek(SeekFrom::Start(0)));
+        try!(self.write_header());
+        let _ = try!(self.writer.seek(SeekFrom::End(0)));
+        try!(self.writer.flush());
+        Ok(self.writer)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let (day, year) = creation_date();
+        let offset_to_point_data = HEADER_SIZE as u32 + 2 * VLR_HEADER_SIZE as u32 +
+                                   3 * EXTRA_BYTES_DESCRIPTOR_SIZE as u32 +
+                                   self.wkt_record_length() as u32;
+        try!(self.writer.write_all(b"LASF"));
+        try!(self.writer.write_u16::<LittleEndian>(0)); // file source id
+        try!(self.writer.write_u16::<LittleEndian>(GLOBAL_ENCODING_WKT));
+        try!(self.writer.write_all(&[0; 16])); // project id
+        try!(self.writer.write_u8(1));
+        try!(self.writer.write_u8(4));
+        try!(write_fixed_str(&mut self.writer, &self.system_identifier, 32));
+        try!(write_fixed_str(&mut self.writer,
+                             &format!("sdf-rs {}", env!("CARGO_PKG_VERSION")),
+                             32));
+        try!(self.writer.write_u16::<LittleEndian>(day));
+        try!(self.writer.write_u16::<LittleEndian>(year));
+        try!(self.writer.write_u16::<LittleEndian>(HEADER_SIZE));
+        try!(self.writer.write_u32::<LittleEndian>(offset_to_point_data));
+        try!(self.writer.write_u32::<LittleEndian>(2)); // number of variable length records
+        try!(self.writer.write_u8(self.format.as_u8()));
+        try!(self.writer.write_u16::<LittleEndian>(self.format.record_length()));
+        // Legacy point counts must be zero for formats 6 and up.
+        for _ in 0..6 {
+            try!(self.writer.write_u32::<LittleEndian>(0));
+        }
+        for _ in 0..3 {
+            try!(self.writer.write_f64::<LittleEndian>(SCALE));
+        }
+        for &offset in &self.extent.offset {
+            try!(self.writer.write_f64::<LittleEndian>(offset));
+        }
+        try!(self.extent.write(&mut self.writer));
+        try!(self.writer.write_u64::<LittleEndian>(0)); // start of waveform data packet record
+        try!(self.writer.write_u64::<LittleEndian>(0)); // start of first extended vlr
+        try!(self.writer.write_u32::<LittleEndian>(0)); // number of extended vlrs
+        try!(self.writer.write_u64::<LittleEndian>(self.number_of_points));
+        for &count in &self.number_of_points_by_return {
+            try!(self.writer.write_u64::<LittleEndian>(count));
+        }
+        Ok(())
+    }
+
+    fn write_extra_bytes_vlr(&mut self) -> Result<()> {
+        try!(self.writer.write_u16::<LittleEndian>(0)); // reserved
+        try!(write_fixed_str(&mut self.writer, "LASF_Spec", 16));
+        try!(self.writer.write_u16::<LittleEndian>(EXTRA_BYTES_RECORD_ID));
+        try!(self.writer.write_u16::<LittleEndian>(3 * EXTRA_BYTES_DESCRIPTOR_SIZE));
+        try!(write_fixed_str(&mut self.writer, "Extra bytes", 32));
+        try!(self.write_extra_bytes_descriptor(9, "range", "Raw range in meters"));
+        try!(self.write_extra_bytes_descriptor(3, "facet", "Mirror facet"));
+        try!(self.write_extra_bytes_descriptor(1, "high_channel", "1 if from the high channel"));
+        Ok(())
+    }
+
+    fn write_wkt_vlr(&mut self) -> Result<()> {
+        let record_length = self.wkt_record_length();
+        try!(self.writer.write_u16::<LittleEndian>(0)); // reserved
+        try!(write_fixed_str(&mut self.writer, "LASF_Projection", 16));
+        try!(self.writer.write_u16::<LittleEndian>(WKT_RECORD_ID));
+        try!(self.writer.write_u16::<LittleEndian>(record_length));
+        try!(write_fixed_str(&mut self.writer, "OGC coordinate system WKT", 32));
+        try!(write_fixed_str(&mut self.writer, &self.wkt, record_length as usize));
+        Ok(())
+    }
+
+    /// The WKT is stored with a terminating null.
+    fn wkt_record_length(&self) -> u16 {
+        self.wkt.len() as u16 + 1
+    }
+
+    fn write_extra_bytes_descriptor(&mut self,
+                                    data_type: u8,
+                                    name: &str,
+                                    description: &str)
+                                    -> Result<()> {
+        try!(self.writer.write_all(&[0; 2])); // reserved
+        try!(self.writer.write_u8(data_type));
+        try!(self.writer.write_u8(0)); // options: no no_data, min, max, scale, or offset
+        try!(write_fixed_str(&mut self.writer, name, 32));
+        try!(self.writer.write_all(&[0; 4])); // unused
+        try!(self.writer.write_all(&[0; 24 * 5])); // no_data, min, max, scale, offset
+        try!(write_fixed_str(&mut self.writer, description, 32));
+        Ok(())
+    }
+}
+
+/// The offset and bounds of the points written so far.
+///
+/// The offset is set by the first point, and is zero until then.
+#[derive(Clone, Copy, Debug)]
+struct Extent {
+    offset: [f64; 3],
+    min: [f64; 3],
+    max: [f64; 3],
+    empty: bool,
+}
+
+impl Extent {
+    fn new() -> Extent {
+        Extent {
+            offset: [0.0; 3],
+            min: [0.0; 3],
+            max: [0.0; 3],
+            empty: true,
+        }
+    }
+
+    /// Quantizes coordinates and grows the extent to include them.
+    ///
+    /// Returns an error, and leaves the extent as it was, if a coordinate isn't finite or doesn't
+    /// fit in an `i32` once the offset is taken away.
+    fn quantize(&mut self, coordinates: [f64; 3]) -> Result<[i32; 3]> {
+        let mut offset = self.offset;
+        let mut quantized = [0i32; 3];
+        for i in 0..3 {
+            if self.empty {
+                offset[i] = coordinates[i].round();
+            }
+            let value = ((coordinates[i] - offset[i]) / SCALE).round();
+            if !(value >= i32::MIN as f64 && value <= i32::MAX as f64) {
+                return Err(Error::InvalidCoordinate(coordinates[i]));
+            }
+            quantized[i] = value as i32;
+        }
+        self.offset = offset;
+        for i in 0..3 {
+            let value = quantized[i] as f64 * SCALE + self.offset[i];
+            if self.empty {
+                self.min[i] = value;
+                self.max[i] = value;
+            } else {
+                self.min[i] = self.min[i].min(value);
+                self.max[i] = self.max[i].max(value);
+            }
+        }
+        self.empty = false;
+        Ok(quantized)
+    }
+
+    /// Writes the extent in header order, i.e. max x, min x, max y, etc.
+    fn write<W: Write>(&self, writer: &mut W) -> Result<()> {
+        for i in 0..3 {
+            try!(writer.write_f64::<LittleEndian>(self.max[i]));
+            try!(writer.write_f64::<LittleEndian>(self.min[i]));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f64;
+    use std::io::{Cursor, Read};
+
+    use byteorder::{LittleEndian, ReadBytesExt};
+
+    use backend::{test_info, test_record};
+    use convert::{Point, discretize};
+    use error::Error;
+    use file::File;
+
+    const VLRS_SIZE: usize = 2 * 54 + 3 * 192 + 60;
+
+    fn point() -> Point {
+        discretize(&test_record(1.0), &test_info()).unwrap()[0]
+    }
+
+    #[test]
+    fn empty_header() {
+        let writer = LasWriter::new(Cursor::new(Vec::new()), &test_info(), PointFormat::Seven)
+                         .unwrap();
+        let mut cursor = writer.close().unwrap();
+        let len = cursor.get_ref().len();
+        assert_eq!(375 + VLRS_SIZE, len);
+        cursor.set_position(0);
+        let mut signature = [0; 4];
+        cursor.read_exact(&mut signature).unwrap();
+        assert_eq!(b"LASF", &signature);
+        cursor.set_position(24);
+        assert_eq!(1, cursor.read_u8().unwrap());
+        assert_eq!(4, cursor.read_u8().unwrap());
+        let mut system_identifier = [0; 5];
+        cursor.read_exact(&mut system_identifier).unwrap();
+        assert_eq!(b"Q680I", &system_identifier);
+        cursor.set_position(104);
+        assert_eq!(7, cursor.read_u8().unwrap());
+        assert_eq!(43, cursor.read_u16::<LittleEndian>().unwrap());
+    }
+
+    #[test]
+    fn counts_and_bounds() {
+        let mut file = File::open("data/110630_174316.sdf").unwrap();
+        file.reindex().unwrap();
+        let ref file_info = file.info().unwrap();
+        let ref record = file.read().unwrap();
+        let points = discretize(record, file_info).unwrap();
+        let mut writer = LasWriter::new(Cursor::new(Vec::new()), file_info, PointFormat::Six)
+                             .unwrap();
+        for point in &points {
+            writer.write_point(point).unwrap();
+        }
+        let mut cursor = writer.close().unwrap();
+        assert_eq!(375 + VLRS_SIZE + 37 * points.len(), cursor.get_ref().len());
+        cursor.set_position(179);
+        let max_x = cursor.read_f64::<LittleEndian>().unwrap();
+        let min_x = cursor.read_f64::<LittleEndian>().unwrap();
+        assert!(min_x <= max_x);
+        cursor.set_position(247);
+        assert_eq!(points.len() as u64, cursor.read_u64::<LittleEndian>().unwrap());
+        assert_eq!(points.iter().filter(|p| p.target == 1).count() as u64,
+                   cursor.read_u64::<LittleEndian>().unwrap());
+    }
+
+    #[test]
+    fn wkt() {
+        let writer = LasWriter::new(Cursor::new(Vec::new()), &test_info(), PointFormat::Six)
+                         .unwrap();
+        let mut cursor = writer.close().unwrap();
+        cursor.set_position(100);
+        assert_eq!(2, cursor.read_u32::<LittleEndian>().unwrap());
+        cursor.set_position(375 + 54 + 3 * 192 + 2);
+        let mut user_id = [0; 15];
+        cursor.read_exact(&mut user_id).unwrap();
+        assert_eq!(b"LASF_Projection", &user_id);
+        cursor.set_position(375 + 54 + 3 * 192 + 18);
+        assert_eq!(2112, cursor.read_u16::<LittleEndian>().unwrap());
+        let record_length = cursor.read_u16::<LittleEndian>().unwrap() as usize;
+        cursor.set_position(375 + 2 * 54 + 3 * 192);
+        let mut wkt = vec![0; record_length];
+        cursor.read_exact(&mut wkt).unwrap();
+        assert!(wkt.starts_with(b"LOCAL_CS["));
+        assert_eq!(Some(&0), wkt.last());
+    }
+
+    #[test]
+    fn offset_from_first_point() {
+        let mut point = point();
+        point.x = 123456.7;
+        point.y = -2345.6;
+        point.z = 0.4;
+        let mut writer = LasWriter::new(Cursor::new(Vec::new()), &test_info(), PointFormat::Six)
+                             .unwrap();
+        writer.write_point(&point).unwrap();
+        point.x += 1.0;
+        writer.write_point(&point).unwrap();
+        let mut cursor = writer.close().unwrap();
+        cursor.set_position(155);
+        assert_eq!(123457.0, cursor.read_f64::<LittleEndian>().unwrap());
+        assert_eq!(-2346.0, cursor.read_f64::<LittleEndian>().unwrap());
+        assert_eq!(0.0, cursor.read_f64::<LittleEndian>().unwrap());
+        cursor.set_position(375 + VLRS_SIZE as u64);
+        assert_eq!(-297, cursor.read_i32::<LittleEndian>().unwrap());
+        cursor.set_position(375 + VLRS_SIZE as u64 + 37);
+        assert_eq!(703, cursor.read_i32::<LittleEndian>().unwrap());
+    }
+
+    #[test]
+    fn too_far_from_offset() {
+        let mut writer = LasWriter::new(Cursor::new(Vec::new()), &test_info(), PointFormat::Six)
+                             .unwrap();
+        writer.write_point_at(&point(), [0.0, 0.0, 0.0]).unwrap();
+        match writer.write_point_at(&point(), [3.0e6, 0.0, 0.0]) {
+            Err(Error::InvalidCoordinate(x)) => assert_eq!(3.0e6, x),
+            result => panic!("Expected an invalid coordinate, got {:?}", result),
+        }
+        assert!(writer.write_point_at(&point(), [0.0, f64::NAN, 0.0]).is_err());
+        assert_eq!(1, writer.number_of_points);
+        assert_eq!(0.0, writer.extent.max[0]);
+    }
+
+    #[test]
+    fn georeferenced() {
+        let wkt = "PROJCS[\"WGS 84 / UTM zone 15N\"]";
+        let mut writer = LasWriter::with_wkt(Cursor::new(Vec::new()),
+                                             &test_info(),
+                                             PointFormat::Six,
+                                             wkt)
+                             .unwrap();
+        writer.write_point_at(&point(), [480000.1234, 4980000.5678, 250.0]).unwrap();
+        let mut cursor = writer.close().unwrap();
+        cursor.set_position(96);
+        let offset_to_point_data = cursor.read_u32::<LittleEndian>().unwrap() as u64;
+        assert_eq!(375 + 2 * 54 + 3 * 192 + wkt.len() as u64 + 1, offset_to_point_data);
+        cursor.set_position(155);
+        assert_eq!(480000.0, cursor.read_f64::<LittleEndian>().unwrap());
+        assert_eq!(4980001.0, cursor.read_f64::<LittleEndian>().unwrap());
+        cursor.set_position(offset_to_point_data);
+        assert_eq!(123, cursor.read_i32::<LittleEndian>().unwrap());
+        assert_eq!(-432, cursor.read_i32::<LittleEndian>().unwrap());
+    }
+}
@@ -0,0 +1,368 @@
+//! Write discretized points and their waveforms to LAS 1.3 files.
+//!
+//! Points are written as point data record format 4. The sample blocks that the points were
+//! detected in are written as waveform data packets to an external .wdp file, which sits next to
+//! the .las file and has the same base name. Each distinct combination of channel and sample count
+//! gets its own wave packet descriptor, up to the LAS limit of 255.
+//!
+//! Each point's waveform location is its offset into the sample block, taken from the fitted echo
+//! center if the waveform was decomposed and from the peak otherwise. Its parametric line follows
+//! the record's direction vector at half the group velocity, so samples after the return lie
+//! further from the scanner.
+
+use std::fs;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use Result;
+use convert::Point;
+use error::Error;
+use file::{Channel, FileInfo, Record};
+use super::Extent;
+use util::{creation_date, write_fixed_str};
+
+const HEADER_SIZE: u16 = 235;
+const POINT_RECORD_LENGTH: u16 = 57;
+const VLR_HEADER_SIZE: u64 = 54;
+const DESCRIPTOR_SIZE: u16 = 26;
+const MAX_DESCRIPTORS: usize = 255;
+const FIRST_DESCRIPTOR_RECORD_ID: u16 = 100;
+const EVLR_HEADER_SIZE: u64 = 60;
+const WAVEFORM_DATA_RECORD_ID: u16 = 65535;
+const GLOBAL_ENCODING_EXTERNAL_WAVEFORMS: u16 = 0b100;
+const MAX_RETURN_NUMBER: u8 = 7;
+const BITS_PER_SAMPLE: u8 = 16;
+const PICOSECONDS_PER_SECOND: f64 = 1e12;
+
+/// Writes discretized points to a LAS 1.3 file, and their waveforms to a .wdp file.
+///
+/// Space for all 255 wave packet descriptors is reserved before the point data, and the
+/// descriptors that were actually used are filled in by `close`, along with the final header. A
+/// writer that is dropped without being closed leaves invalid files behind.
+#[derive(Debug)]
+pub struct WdpWriter<W: Write + Seek, V: Write + Seek> {
+    las: W,
+    wdp: V,
+    system_identifier: String,
+    v_group: f64,
+    sampling_time: f64,
+    descriptors: Vec<(Channel, usize)>,
+    wdp_offset: u64,
+    number_of_points: u32,
+    number_of_points_by_return: [u32; 5],
+    extent: Extent,
+}
+
+impl WdpWriter<BufWriter<fs::File>, BufWriter<fs::File>> {
+    /// Creates a new .las file at the given path, and a .wdp file alongside it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::File;
+    /// use sdf::las::wdp::WdpWriter;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let ref file_info = file.info().unwrap();
+    /// let writer = WdpWriter::from_path("/tmp/110630_174316.las", file_info).unwrap();
+    /// ```
+    pub fn from_path<P: AsRef<Path>>
+        (path: P,
+         file_info: &FileInfo)
+         -> Result<WdpWriter<BufWriter<fs::File>, BufWriter<fs::File>>> {
+        let las = try!(fs::File::create(&path));
+        let wdp = try!(fs::File::create(path.as_ref().with_extension("wdp")));
+        WdpWriter::new(BufWriter::new(las), BufWriter::new(wdp), file_info)
+    }
+}
+
+impl<W: Write + Seek, V: Write + Seek> WdpWriter<W, V> {
+    /// Creates a new writer and writes placeholder headers to both outputs.
+    ///
+    /// The sampling time and group velocity are taken from the file info, and are used to build
+    /// the wave packet descriptors and each point's parametric line.
+    pub fn new(las: W, wdp: V, file_info: &FileInfo) -> Result<WdpWriter<W, V>> {
+        let mut writer = WdpWriter {
+            las: las,
+            wdp: wdp,
+            system_identifier: file_info.instrument.clone(),
+            v_group: file_info.v_group,
+            sampling_time: file_info.sampling_time,
+            descriptors: Vec::new(),
+            wdp_offset: EVLR_HEADER_SIZE,
+            number_of_points: 0,
+            number_of_points_by_return: [0; 5],
+            extent: Extent::new(),
+        };
+        try!(writer.write_header());
+        let reserved = MAX_DESCRIPTORS * (VLR_HEADER_SIZE as usize + DESCRIPTOR_SIZE as usize);
+        try!(writer.las.write_all(&vec![0; reserved]));
+        try!(writer.write_waveform_data_header());
+        Ok(writer)
+    }
+
+    /// Writes the points that were discretized from a record, along with their waveforms.
+    ///
+    /// Each sample block that at least one point was detected in is written to the .wdp file
+    /// once, no matter how many points share it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use sdf::convert::discretize;
+    /// use sdf::file::File;
+    /// use sdf::las::wdp::WdpWriter;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let ref file_info = file.info().unwrap();
+    /// file.reindex().unwrap();
+    /// let ref record = file.read().unwrap();
+    /// let mut writer = WdpWriter::new(Cursor::new(Vec::new()),
+    ///                                 Cursor::new(Vec::new()),
+    ///                                 file_info)
+    ///                      .unwrap();
+    /// let points = discretize(record, file_info).unwrap();
+    /// writer.write_record(record, &points).unwrap();
+    /// writer.close().unwrap();
+    /// ```
+    pub fn write_record(&mut self, record: &Record, points: &[Point]) -> Result<()> {
+        let mut packets: Vec<(usize, u8, u64, u32)> = Vec::new();
+        for point in points {
+            let block = try!(record.blocks
+                                   .get(point.block)
+                                   .ok_or(Error::InvalidBlock(point.block)));
+            let packet = match packets.iter().find(|p| p.0 == point.block).cloned() {
+                Some(packet) => packet,
+                None => {
+                    let descriptor = try!(self.descriptor(block.channel, block.samples.len()));
+                    let offset = self.wdp_offset;
+                    for &sample in &block.samples {
+                        try!(self.wdp.write_u16::<LittleEndian>(sample));
+                    }
+                    let size = 2 * block.samples.len() as u32;
+                    self.wdp_offset += size as u64;
+                    packets.push((point.block, descriptor, offset, size));
+                    (point.block, descriptor, offset, size)
+                }
+            };
+            try!(self.write_point(point, record, packet));
+        }
+        Ok(())
+    }
+
+    /// Finalizes both headers and returns the underlying writers.
+    pub fn close(mut self) -> Result<(W, V)> {
+        let _ = try!(self.las.seek(SeekFrom::Start(0)));
+        try!(self.write_header());
+        for i in 0..self.descriptors.len() {
+            try!(self.write_descriptor(i));
+        }
+        let _ = try!(self.las.seek(SeekFrom::End(0)));
+        try!(self.las.flush());
+        let _ = try!(self.wdp.seek(SeekFrom::Start(0)));
+        try!(self.write_waveform_data_header());
+        let _ = try!(self.wdp.seek(SeekFrom::End(0)));
+        try!(self.wdp.flush());
+        Ok((self.las, self.wdp))
+    }
+
+    /// Returns the one-based descriptor index for this channel and sample count, adding a new
+    /// descriptor if necessary.
+    fn descriptor(&mut self, channel: Channel, sample_count: usize) -> Result<u8> {
+        match self.descriptors.iter().position(|&d| d == (channel, sample_count)) {
+            Some(i) => Ok(i as u8 + 1),
+            None => {
+                if self.descriptors.len() == MAX_DESCRIPTORS {
+                    return Err(Error::TooManyWavePacketDescriptors);
+                }
+                self.descriptors.push((channel, sample_count));
+                Ok(self.descriptors.len() as u8)
+            }
+        }
+    }
+
+    fn write_point(&mut self,
+                   point: &Point,
+                   record: &Record,
+                   packet: (usize, u8, u64, u32))
+                   -> Result<()> {
+        let (_, descriptor, offset, size) = packet;
+        let quantized = try!(self.extent.quantize([point.x as f64,
+                                                   point.y as f64,
+                                                   point.z as f64]));
+        let return_number = point.target.min(MAX_RETURN_NUMBER);
+        let number_of_returns = point.num_target.min(MAX_RETURN_NUMBER);
+        if return_number > 0 && return_number <= 5 {
+            self.number_of_points_by_return[return_number as usize - 1] += 1;
+        }
+        self.number_of_points += 1;
+        let position = match point.echo {
+            Some(ref echo) => echo.position,
+            None => point.peak.index as f64,
+        };
+        let location = position * self.sampling_time * PICOSECONDS_PER_SECOND;
+        let meters_per_picosecond = self.v_group / 2.0 / PICOSECONDS_PER_SECOND;
+
+        for &value in &quantized {
+            try!(self.las.write_i32::<LittleEndian>(value));
+        }
+        try!(self.las.write_u16::<LittleEndian>(point.peak.amplitude));
+        try!(self.las.write_u8(return_number | number_of_returns << 3));
+        try!(self.las.write_u8(0)); // classification
+        try!(self.las.write_i8(point.theta.round().max(-90.0).min(90.0) as i8));
+        try!(self.las.write_u8(0)); // user data
+        try!(self.las.write_u16::<LittleEndian>(0)); // point source id
+        try!(self.las.write_f64::<LittleEndian>(point.time));
+        try!(self.las.write_u8(descriptor));
+        try!(self.las.write_u64::<LittleEndian>(offset));
+        try!(self.las.write_u32::<LittleEndian>(size));
+        try!(self.las.write_f32::<LittleEndian>(location as f32));
+        for i in 0..3 {
+            try!(self.las.write_f32::<LittleEndian>((record.direction[i] *
+                                                      meters_per_picosecond) as
+                                                     f32));
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let (day, year) = creation_date();
+        let offset_to_point_data = HEADER_SIZE as u32 +
+                                   MAX_DESCRIPTORS as u32 *
+                                   (VLR_HEADER_SIZE as u32 + DESCRIPTOR_SIZE as u32);
+        try!(self.las.write_all(b"LASF"));
+        try!(self.las.write_u16::<LittleEndian>(0)); // file source id
+        try!(self.las.write_u16::<LittleEndian>(GLOBAL_ENCODING_EXTERNAL_WAVEFORMS));
+        try!(self.las.write_all(&[0; 16])); // project id
+        try!(self.las.write_u8(1));
+        try!(self.las.write_u8(3));
+        try!(write_fixed_str(&mut self.las, &self.system_identifier, 32));
+        try!(write_fixed_str(&mut self.las,
+                             &format!("sdf-rs {}", env!("CARGO_PKG_VERSION")),
+                             32));
+        try!(self.las.write_u16::<LittleEndian>(day));
+        try!(self.las.write_u16::<LittleEndian>(year));
+        try!(self.las.write_u16::<LittleEndian>(HEADER_SIZE));
+        try!(self.las.write_u32::<LittleEndian>(offset_to_point_data));
+        try!(self.las.write_u32::<LittleEndian>(self.descriptors.len() as u32));
+        try!(self.las.write_u8(4));
+        try!(self.las.write_u16::<LittleEndian>(POINT_RECORD_LENGTH));
+        try!(self.las.write_u32::<LittleEndian>(self.number_of_points));
+        for &count in &self.number_of_points_by_return {
+            try!(self.las.write_u32::<LittleEndian>(count));
+        }
+        for _ in 0..3 {
+            try!(self.las.write_f64::<LittleEndian>(super::SCALE));
+        }
+        for &offset in &self.extent.offset {
+            try!(self.las.write_f64::<LittleEndian>(offset));
+        }
+        try!(self.extent.write(&mut self.las));
+        // The waveforms are external, so there's no internal waveform data packet record.
+        try!(self.las.write_u64::<LittleEndian>(0));
+        Ok(())
+    }
+
+    fn write_descriptor(&mut self, i: usize) -> Result<()> {
+        let (channel, sample_count) = self.descriptors[i];
+        try!(self.las.write_u16::<LittleEndian>(0)); // reserved
+        try!(write_fixed_str(&mut self.las, "LASF_Spec", 16));
+        try!(self.las.write_u16::<LittleEndian>(FIRST_DESCRIPTOR_RECORD_ID + i as u16));
+        try!(self.las.write_u16::<LittleEndian>(DESCRIPTOR_SIZE));
+        try!(write_fixed_str(&mut self.las, &format!("{} channel waveform", channel), 32));
+        try!(self.las.write_u8(BITS_PER_SAMPLE));
+        try!(self.las.write_u8(0)); // no compression
+        try!(self.las.write_u32::<LittleEndian>(sample_count as u32));
+        try!(self.las.write_u32::<LittleEndian>((self.sampling_time * PICOSECONDS_PER_SECOND)
+                                                    .round() as
+                                                u32));
+        try!(self.las.write_f64::<LittleEndian>(1.0)); // digitizer gain
+        try!(self.las.write_f64::<LittleEndian>(0.0)); // digitizer offset
+        Ok(())
+    }
+
+    fn write_waveform_data_header(&mut self) -> Result<()> {
+        try!(self.wdp.write_u16::<LittleEndian>(0)); // reserved
+        try!(write_fixed_str(&mut self.wdp, "LASF_Spec", 16));
+        try!(self.wdp.write_u16::<LittleEndian>(WAVEFORM_DATA_RECORD_ID));
+        try!(self.wdp.write_u64::<LittleEndian>(self.wdp_offset - EVLR_HEADER_SIZE));
+        try!(write_fixed_str(&mut self.wdp, "sdf waveforms", 32));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Cursor, Read};
+
+    use byteorder::{LittleEndian, ReadBytesExt};
+
+    use backend::{test_info, test_record};
+    use convert::discretize;
+    use file::File;
+    use gaussian::Echo;
+
+    #[test]
+    fn waveforms() {
+        let mut file = File::open("data/110630_174316.sdf").unwrap();
+        file.reindex().unwrap();
+        let ref file_info = file.info().unwrap();
+        let ref record = file.read().unwrap();
+        let points = discretize(record, file_info).unwrap();
+        let mut writer = WdpWriter::new(Cursor::new(Vec::new()),
+                                        Cursor::new(Vec::new()),
+                                        file_info)
+                             .unwrap();
+        writer.write_record(record, &points).unwrap();
+        let (mut las, mut wdp) = writer.close().unwrap();
+
+        las.set_position(107);
+        assert_eq!(points.len() as u32, las.read_u32::<LittleEndian>().unwrap());
+
+        let first = &points[0];
+        let ref block = record.blocks[first.block];
+        las.set_position(235 + 255 * 80 + 28);
+        assert!(las.read_u8().unwrap() > 0);
+        let offset = las.read_u64::<LittleEndian>().unwrap();
+        let size = las.read_u32::<LittleEndian>().unwrap();
+        assert_eq!(2 * block.samples.len() as u32, size);
+        wdp.set_position(offset);
+        let mut samples = Vec::new();
+        for _ in 0..block.samples.len() {
+            samples.push(wdp.read_u16::<LittleEndian>().unwrap());
+        }
+        assert_eq!(block.samples, samples);
+
+        let mut signature = [0; 4];
+        las.set_position(0);
+        las.read_exact(&mut signature).unwrap();
+        assert_eq!(b"LASF", &signature);
+    }
+
+    #[test]
+    fn echo_location() {
+        let record = test_record(1.0);
+        let file_info = test_info();
+        let mut points = discretize(&record, &file_info).unwrap();
+        points.truncate(1);
+        points[0].echo = Some(Echo {
+            amplitude: 80.0,
+            position: 3.25,
+            sigma: 1.0,
+            residual: 0.0,
+        });
+        let mut writer = WdpWriter::new(Cursor::new(Vec::new()),
+                                        Cursor::new(Vec::new()),
+                                        &file_info)
+                             .unwrap();
+        writer.write_record(&record, &points).unwrap();
+        let (mut las, _) = writer.close().unwrap();
+
+        las.set_position(235 + 255 * 80 + 41);
+        let location = las.read_f32::<LittleEndian>().unwrap();
+        assert_eq!((3.25 * file_info.sampling_time * 1e12) as f32, location);
+    }
+}
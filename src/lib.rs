@@ -2,32 +2,62 @@
 //!
 //! This is in part a wrapper library around `libsdfifc.so`, Riegl's sdf library. This library also
 //! provides functions to convert .sdf files to discrete return .sdc files using Gaussian
-//! decomposition.
+//! decomposition. Discretized points can be written to .sdc files with `sdc::SdcWriter` and to
+//! LAS 1.4 files with `las::LasWriter`, and raw waveforms can be exported to PulseWaves with
+//! `pulsewaves::PulseWavesWriter` or to NumPy .npz archives with `waveforms::Waveforms`.
 //!
-//! The `sdfifc` library **is not thread-safe**, and so this library should only be used in
-//! single-threaded applications. When running this library's test suite, you must set
-//! `RUST_TEST_THREADS=1` or else you most likely will get a segfault.
+//! A `File` reads its data through a `Backend`. By default that's libsdfifc, but the
+//! `backend::Memory` backend can serve synthetic records, e.g. for testing processing code without
+//! the vendor library or real data.
+//!
+//! The `sdfifc` library is not thread-safe, so every call into it is made while holding a
+//! process-wide lock. A `File` can be moved to, and used from, any thread, but calls into sdfifc
+//! never run in parallel. To discretize on several cores, `pipeline::Pipeline` reads the file on
+//! one thread and hands records off to a pool of workers.
 
 #![deny(box_pointers, fat_ptr_transmutes, missing_copy_implementations, missing_debug_implementations, missing_docs, trivial_casts, trivial_numeric_casts, unused_extern_crates, unused_import_braces, unused_qualifications, unused_results, variant_size_differences)]
 
+extern crate byteorder;
+#[macro_use]
+extern crate lazy_static;
 extern crate libc;
 #[macro_use]
 extern crate log;
+extern crate num_cpus;
 extern crate peakbag;
+extern crate rustc_serialize;
+extern crate toml;
 
+// Must be used while holding the sdfifc lock, so the error message is the one for our call.
 macro_rules! sdftry {
     ($expr:expr) => {{
         match $expr {
             0 => {},
-            code @ _ => return Err(Error::from_i32(code)),
+            code @ _ => return Err(::ffi::error_from_code(code)),
         }
     }}
 }
 
+pub mod backend;
 pub mod convert;
+pub mod csv;
+pub mod deconvolve;
 pub mod error;
 mod ffi;
 pub mod file;
+pub mod gaussian;
+pub mod georef;
+pub mod index;
+pub mod las;
+pub mod mta;
+pub mod npy;
+pub mod pipeline;
+pub mod ply;
+pub mod pulsewaves;
+pub mod sdc;
+pub mod stats;
+mod util;
+pub mod waveforms;
 
 pub use error::Error;
 pub use file::File;
@@ -59,6 +89,7 @@ pub struct LibraryVersion {
 /// let library_version = sdf::library_version();
 /// ```
 pub fn library_version() -> Result<LibraryVersion> {
+    let _lock = ffi::lock();
     unsafe {
         let mut api_major = 0u16;
         let mut api_minor = 0u16;
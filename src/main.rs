@@ -5,12 +5,28 @@ extern crate env_logger;
 extern crate rustc_serialize;
 extern crate sdf;
 
+use std::collections::BTreeMap;
+use std::f64;
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
 use std::process::exit;
 use std::u32;
 
 use docopt::Docopt;
+use rustc_serialize::json;
 
-use sdf::error::Error;
+use sdf::convert::{DiscretizeOptions, Point};
+use sdf::csv::CsvWriter;
+use sdf::error::{Error, RecordError};
+use sdf::file::{FileInfo, TimeBase};
+use sdf::georef::{Georeferencer, Projection, Trajectory};
+use sdf::las::{LasWriter, PointFormat};
+use sdf::pipeline::Pipeline;
+use sdf::ply::PlyWriter;
+use sdf::sdc::SdcWriter;
+use sdf::stats::Stats;
+use sdf::waveforms::Waveforms;
 
 const USAGE: &'static str = "
 Read and process .sdf files.
@@ -18,23 +34,232 @@ Read and process .sdf files.
 Usage:
     sdf info <infile> [--brief]
     sdf record <infile> <index>
+    sdf stats <infile> [--json] [--bin=<seconds>]
+    sdf discretize <infile> <outfile> [options]
+    sdf export-waveforms <infile> <outfile> [options]
     sdf (-h | --help)
     sdf --version
 
 Options:
-    -h --help   Show this screen.
-    --version   Show sdf-rs and sdfifc library versions.
-    --brief     Only provide file information from the header, do not inspect the file itself.
+    -h --help            Show this screen.
+    --version            Show sdf-rs and sdfifc library versions.
+    --brief              Only provide file information from the header, do not inspect the file
+                         itself.
+    --json               Write statistics as JSON.
+    --bin=<seconds>      The width of the pulse rate bins, in seconds [default: 1].
+    --options=<path>     Read discretization options, e.g. detector settings, from a TOML or
+                         JSON file.
+    --start=<time>       Only discretize records at or after this time, in seconds.
+    --end=<time>         Only discretize records before this time, in seconds.
+    --external           Use external time, rather than internal time, for --start and --end.
+    --channel=<channel>  Only write points from this channel: high, low or all [default: all].
+    --trajectory=<path>  Georeference points with a trajectory, either an SBET file or a .csv file
+                         of time,latitude,longitude,height,roll,pitch,heading in degrees.
+    --utm-zone=<zone>    Write georeferenced points in this UTM zone, e.g. 15N, rather than in
+                         earth-centered, earth-fixed coordinates.
+    --lever-arm=<xyz>    The scanner's position in the body frame, as x,y,z in meters
+                         [default: 0,0,0].
+    --boresight=<rpy>    The boresight angles, as roll,pitch,yaw in degrees [default: 0,0,0].
+    --time-offset=<s>    Seconds to add to point times to get trajectory times [default: 0].
+    --first=<index>      Only export waveforms from records at or after this one-based index.
+    --last=<index>       Only export waveforms from records at or before this one-based index.
+
+The discretize output format is chosen by the extension of <outfile>: .csv, .las, .ply or .sdc.
+Amplitudes and ranges are calibrated with the tables in <infile>. Georeferenced points can only
+be written to .csv and .las files.
+Waveforms are exported to a NumPy .npz archive. They're held in memory until they're written, so
+records must be selected either by time, with --start and --end, or by record index, with --first
+and --last.
 ";
 
 #[derive(Debug, RustcDecodable)]
 struct Args {
+    flag_bin: f64,
+    flag_boresight: String,
     flag_brief: bool,
+    flag_channel: String,
+    flag_end: Option<f64>,
+    flag_external: bool,
+    flag_first: Option<u32>,
+    flag_json: bool,
+    flag_last: Option<u32>,
+    flag_lever_arm: String,
+    flag_options: Option<String>,
+    flag_start: Option<f64>,
+    flag_time_offset: f64,
+    flag_trajectory: Option<String>,
+    flag_utm_zone: Option<String>,
     flag_version: bool,
     arg_index: u32,
     arg_infile: String,
+    arg_outfile: String,
+    cmd_discretize: bool,
+    cmd_export_waveforms: bool,
     cmd_info: bool,
     cmd_record: bool,
+    cmd_stats: bool,
+}
+
+enum PointWriter {
+    Csv(CsvWriter<BufWriter<fs::File>>),
+    Las(LasWriter<BufWriter<fs::File>>),
+    Ply(PlyWriter<BufWriter<fs::File>>),
+    Sdc(SdcWriter<BufWriter<fs::File>>),
+}
+
+impl PointWriter {
+    /// Creates a writer, for georeferenced points if there's a projection.
+    fn from_path(path: &str,
+                 info: &FileInfo,
+                 projection: Option<Projection>)
+                 -> Result<PointWriter, Error> {
+        let extension = Path::new(path).extension().and_then(|e| e.to_str());
+        match (extension, projection) {
+            (Some("csv"), _) => CsvWriter::from_path(path).map(PointWriter::Csv),
+            (Some("las"), None) => {
+                LasWriter::from_path(path, info, PointFormat::Six).map(PointWriter::Las)
+            }
+            (Some("las"), Some(projection)) => {
+                let file = BufWriter::new(try!(fs::File::create(path)));
+                LasWriter::with_wkt(file, info, PointFormat::Six, &projection.wkt())
+                    .map(PointWriter::Las)
+            }
+            (Some("ply"), None) => PlyWriter::from_path(path).map(PointWriter::Ply),
+            (Some("sdc"), None) => SdcWriter::from_path(path).map(PointWriter::Sdc),
+            (Some("ply"), Some(_)) |
+            (Some("sdc"), Some(_)) => {
+                Err(Error::InvalidOptions(format!("Can't write georeferenced points to {}, use \
+                                                   .csv or .las",
+                                                  path)))
+            }
+            _ => Err(Error::InvalidOptions(format!("Unknown output file extension: {}", path))),
+        }
+    }
+
+    /// Writes a point, at georeferenced coordinates if there are any.
+    fn write_point(&mut self, point: &Point, coordinates: Option<[f64; 3]>) -> Result<(), Error> {
+        match (self, coordinates) {
+            (&mut PointWriter::Csv(ref mut writer), Some(coordinates)) => {
+                writer.write_point_at(point, coordinates)
+            }
+            (&mut PointWriter::Las(ref mut writer), Some(coordinates)) => {
+                writer.write_point_at(point, coordinates)
+            }
+            (&mut PointWriter::Csv(ref mut writer), None) => writer.write_point(point),
+            (&mut PointWriter::Las(ref mut writer), None) => writer.write_point(point),
+            (&mut PointWriter::Ply(ref mut writer), _) => writer.write_point(point),
+            (&mut PointWriter::Sdc(ref mut writer), _) => writer.write_point(point),
+        }
+    }
+
+    fn close(self) -> Result<(), Error> {
+        match self {
+            PointWriter::Csv(writer) => writer.into_inner().map(|_| ()),
+            PointWriter::Las(writer) => writer.close().map(|_| ()),
+            PointWriter::Ply(writer) => writer.close().map(|_| ()),
+            PointWriter::Sdc(writer) => writer.into_inner().map(|_| ()),
+        }
+    }
+}
+
+/// What happened to the records we tried to discretize.
+#[derive(Debug, Default)]
+struct DiscretizeSummary {
+    records: u64,
+    points: u64,
+    outside_trajectory: u64,
+    skipped: BTreeMap<String, u64>,
+}
+
+/// Writes each record's points, as they come out of the pipeline.
+fn discretize<I>(results: I,
+                 channel: Option<bool>,
+                 georeferencer: Option<&Georeferencer>,
+                 writer: &mut PointWriter)
+                 -> DiscretizeSummary
+    where I: Iterator<Item = Result<Vec<Point>, RecordError>>
+{
+    let mut summary = DiscretizeSummary::default();
+    for points in results {
+        summary.records += 1;
+        let points = match points {
+            Ok(points) => points,
+            Err(err) => {
+                *summary.skipped.entry(err.error.to_string()).or_insert(0) += 1;
+                continue;
+            }
+        };
+        for point in points.iter().filter(|p| channel.map_or(true, |high| p.high_channel == high)) {
+            let coordinates = match georeferencer.map(|g| g.georeference(point)) {
+                Some(Ok(coordinates)) => Some(coordinates),
+                Some(Err(_)) => {
+                    summary.outside_trajectory += 1;
+                    continue;
+                }
+                None => None,
+            };
+            writer.write_point(point, coordinates)
+                  .unwrap_or_else(|e| error_exit("Unable to write point", e));
+            summary.points += 1;
+        }
+    }
+    summary
+}
+
+
+/// Parses a comma-separated triple, e.g. a lever arm.
+fn parse_triple(name: &str, value: &str) -> Result<[f64; 3], Error> {
+    let values = value.split(',').map(|v| v.trim().parse::<f64>()).collect::<Vec<_>>();
+    if values.len() != 3 || values.iter().any(|v| v.is_err()) {
+        return Err(Error::InvalidOptions(format!("Expected three comma-separated numbers for \
+                                                  {}, got {}",
+                                                 name,
+                                                 value)));
+    }
+    let mut triple = [0.0; 3];
+    for (t, v) in triple.iter_mut().zip(values.into_iter()) {
+        *t = v.expect("the value parsed");
+    }
+    Ok(triple)
+}
+
+/// Parses a UTM zone number followed by a hemisphere, e.g. 15N.
+fn parse_utm_zone(value: &str) -> Result<Projection, Error> {
+    let mut zone = value.trim().to_uppercase();
+    let hemisphere = zone.pop();
+    match (zone.parse::<u8>(), hemisphere) {
+        (Ok(zone), Some('N')) |
+        (Ok(zone), Some('S')) if zone >= 1 && zone <= 60 => {
+            Ok(Projection::Utm {
+                zone: zone,
+                north: hemisphere == Some('N'),
+            })
+        }
+        _ => Err(Error::InvalidOptions(format!("Invalid UTM zone: {}", value))),
+    }
+}
+
+/// Reads the trajectory and the scanner mounting from the arguments, if there's a trajectory.
+fn georeferencer(args: &Args) -> Result<Option<Georeferencer>, Error> {
+    let path = match args.flag_trajectory {
+        Some(ref path) => path,
+        None => return Ok(None),
+    };
+    let trajectory = if Path::new(path).extension().and_then(|e| e.to_str()) == Some("csv") {
+        try!(Trajectory::from_csv_path(path))
+    } else {
+        try!(Trajectory::from_sbet_path(path))
+    };
+    let projection = match args.flag_utm_zone {
+        Some(ref zone) => try!(parse_utm_zone(zone)),
+        None => Projection::Ecef,
+    };
+    let lever_arm = try!(parse_triple("--lever-arm", &args.flag_lever_arm));
+    let boresight = try!(parse_triple("--boresight", &args.flag_boresight));
+    Ok(Some(Georeferencer::new(trajectory, projection)
+                .lever_arm(lever_arm)
+                .boresight(boresight[0], boresight[1], boresight[2])
+                .time_offset(args.flag_time_offset)))
 }
 
 fn error_exit(message: &str, err: Error) -> ! {
@@ -62,10 +287,33 @@ fn main() {
         exit(0);
     }
 
+    if args.cmd_export_waveforms {
+        let by_time = args.flag_start.is_some() || args.flag_end.is_some();
+        let by_index = args.flag_first.is_some() || args.flag_last.is_some();
+        if by_time == by_index {
+            let message = "Use either --start and --end, or --first and --last".to_string();
+            error_exit("Invalid selection", Error::InvalidOptions(message));
+        }
+    }
+
     let mut file = sdf::File::open(args.arg_infile.clone())
                        .unwrap_or_else(|e| error_exit("Unable to open file", e));
     if !args.flag_brief {
-        file.reindex().unwrap_or_else(|e| error_exit("Unable to reindex file", e));
+        // An indexed file only reports that it's finished, so there's nothing to print.
+        let mut indexing = false;
+        file.reindex_with_progress(|progress| {
+                let stderr = &mut io::stderr();
+                if !progress.finished {
+                    indexing = true;
+                    let _ = write!(stderr, "\rIndexing, {} KiB", progress.index_bytes / 1024);
+                } else if indexing {
+                    let _ = writeln!(stderr,
+                                     "\rIndexing, {} KiB, done",
+                                     progress.index_bytes / 1024);
+                }
+                true
+            })
+            .unwrap_or_else(|e| error_exit("Unable to reindex file", e));
     }
 
     if args.cmd_info {
@@ -81,16 +329,15 @@ fn main() {
             exit(0);
         }
 
-        let record = file.read().unwrap_or_else(|e| error_exit("Unable to read first record", e));
-        let start_time = record.time_external;
-        println!("      start time: {}", start_time);
-        file.seek(u32::MAX).unwrap_or_else(|e| error_exit("Unable to seek to end of file", e));
-        let record = file.read().unwrap_or_else(|e| error_exit("Unable to read last record", e));
-        let end_time = record.time_external;
-        println!("        end time: {}", end_time);
-        let records = file.tell()
-                          .unwrap_or_else(|e| error_exit("Unable to get index of next record", e));
-        println!("number of records: {}", records);
+        let summary = file.summary()
+                          .unwrap_or_else(|e| error_exit("Unable to summarize records", e));
+        println!("      start time: {}", summary.start_time_external);
+        println!("        end time: {}", summary.end_time_external);
+        println!("number of records: {}", summary.record_count);
+        println!("        duration: {}", summary.duration());
+        if let Some(pulse_rate) = summary.pulse_rate() {
+            println!("      pulse rate: {}", pulse_rate);
+        }
 
         exit(0);
     }
@@ -110,5 +357,116 @@ fn main() {
         exit(0);
     }
 
+    if args.cmd_stats {
+        let stats = Stats::from_file(&mut file, args.flag_bin)
+                        .unwrap_or_else(|e| error_exit("Unable to compute statistics", e));
+        if args.flag_json {
+            println!("{}", json::as_pretty_json(&stats));
+        } else {
+            println!("{}", stats);
+        }
+        exit(0);
+    }
+
+    if args.cmd_discretize {
+        let channel = match &args.flag_channel[..] {
+            "all" => None,
+            "high" => Some(true),
+            "low" => Some(false),
+            other => {
+                error_exit("Invalid channel",
+                           Error::InvalidOptions(format!("Unknown channel: {}", other)))
+            }
+        };
+        let options = match args.flag_options {
+            Some(ref path) => {
+                DiscretizeOptions::from_path(path)
+                    .unwrap_or_else(|e| error_exit("Unable to read options", e))
+            }
+            None => DiscretizeOptions::default(),
+        };
+        let options = match options.clone().load_calibrations(&mut file) {
+            Ok(options) => options,
+            Err(err) => {
+                let _ = writeln!(io::stderr(),
+                                 "Not calibrating amplitudes or ranges, since the calibration \
+                                  tables couldn't be read: {}",
+                                 err);
+                options
+            }
+        };
+        let georeferencer = georeferencer(&args)
+                                .unwrap_or_else(|e| error_exit("Unable to georeference", e));
+        if georeferencer.is_none() && args.flag_utm_zone.is_some() {
+            let message = "--utm-zone needs a --trajectory".to_string();
+            error_exit("Invalid options", Error::InvalidOptions(message));
+        }
+        let projection = georeferencer.as_ref().map(|g| g.projection());
+        let info = file.info().unwrap_or_else(|e| error_exit("Unable to retrieve file info", e));
+        let mut writer = PointWriter::from_path(&args.arg_outfile, &info, projection)
+                             .unwrap_or_else(|e| error_exit("Unable to create output file", e));
+        let mut pipeline = Pipeline::new().options(options);
+        if args.flag_start.is_some() || args.flag_end.is_some() {
+            let base = if args.flag_external {
+                TimeBase::External
+            } else {
+                TimeBase::Internal
+            };
+            pipeline = pipeline.between_times(args.flag_start.unwrap_or(f64::NEG_INFINITY),
+                                              args.flag_end.unwrap_or(f64::INFINITY),
+                                              base);
+        }
+        let results = pipeline.run(move || Ok(file))
+                              .unwrap_or_else(|e| error_exit("Unable to read records", e));
+        let summary = discretize(results, channel, georeferencer.as_ref(), &mut writer);
+        writer.close().unwrap_or_else(|e| error_exit("Unable to finish output file", e));
+        let skipped = summary.skipped.values().fold(0, |sum, n| sum + n);
+        println!("                  records: {}", summary.records);
+        println!("                   points: {}", summary.points);
+        if georeferencer.is_some() {
+            println!("points outside trajectory: {}", summary.outside_trajectory);
+        }
+        println!("          skipped records: {}", skipped);
+        for (reason, count) in &summary.skipped {
+            println!("  {}: {}", reason, count);
+        }
+        exit(0);
+    }
+
+    if args.cmd_export_waveforms {
+        let by_time = args.flag_start.is_some() || args.flag_end.is_some();
+        let records = if by_time {
+            let base = if args.flag_external {
+                TimeBase::External
+            } else {
+                TimeBase::Internal
+            };
+            file.records_between_times(args.flag_start.unwrap_or(f64::NEG_INFINITY),
+                                       args.flag_end.unwrap_or(f64::INFINITY),
+                                       base)
+        } else {
+            let first = args.flag_first.unwrap_or(1);
+            let end = args.flag_last.map_or(u32::MAX, |last| last.saturating_add(1));
+            file.records_in_range(first..end)
+        };
+        let mut records = records.unwrap_or_else(|e| error_exit("Unable to select records", e));
+        let mut waveforms = Waveforms::new();
+        let mut unreadable = 0;
+        while let Some(result) = records.next() {
+            match result {
+                Ok(record) => waveforms.add(records.next_index() - 1, &record),
+                Err(err) => {
+                    let _ = writeln!(io::stderr(), "Skipping record: {}", err);
+                    unreadable += 1;
+                }
+            }
+        }
+        waveforms.write_to_path(&args.arg_outfile)
+                 .unwrap_or_else(|e| error_exit("Unable to write waveforms", e));
+        println!("           records: {}", waveforms.len());
+        println!("unreadable records: {}", unreadable);
+        exit(0);
+    }
+
     unreachable!()
 }
@@ -0,0 +1,282 @@
+//! Multiple-time-around (MTA) zone resolution.
+//!
+//! High pulse-rate scanners fire their next pulse before the previous pulse's echoes have
+//! returned. An echo recorded in a record might therefore belong to a pulse fired one or more
+//! pulses earlier. `discretize` assumes every echo belongs to its own record's pulse (zone one);
+//! an echo that actually belongs to the pulse fired `n - 1` pulses earlier is in zone `n`, and its
+//! range is longer by the distance light travels, there and back, in the time between the pulses.
+//!
+//! The pulse repetition interval is estimated from consecutive records' `time_sorg` values.
+
+use std::collections::VecDeque;
+
+use Result;
+use convert::Point;
+use error::Error;
+use file::{FileInfo, Record};
+
+/// The number of pulse intervals used to estimate the pulse repetition interval.
+const PRI_WINDOW: usize = 16;
+
+#[derive(Clone, Copy, Debug)]
+struct Pulse {
+    time_sorg: f64,
+    origin: [f64; 3],
+    direction: [f64; 3],
+}
+
+/// Resolves the MTA zone of discretized points.
+///
+/// The zone is either fixed, e.g. from flight planning or the scanner's own setting, or chosen
+/// per point from an expected range window. Records must be passed to the resolver in order,
+/// since it keeps track of recent pulses. Points that are moved into a higher zone are
+/// re-projected along the direction of the pulse they belong to. Near the start of the data, when
+/// that pulse hasn't been seen, the point's own record's origin and direction are used, and the
+/// range shift is computed from the estimated pulse repetition interval.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::convert::discretize;
+/// use sdf::file::File;
+/// use sdf::mta::MtaResolver;
+/// let mut file = File::open("data/110630_174316.sdf").unwrap();
+/// let ref file_info = file.info().unwrap();
+/// file.reindex().unwrap();
+/// let mut resolver = MtaResolver::fixed(2).unwrap();
+/// for ref record in file.into_iter().take(10) {
+///     let points = discretize(record, file_info).unwrap();
+///     let points = resolver.resolve(record, points, file_info);
+/// }
+/// ```
+#[derive(Debug)]
+pub struct MtaResolver {
+    max_zone: u8,
+    window: Option<(f64, f64)>,
+    pulses: VecDeque<Pulse>,
+    intervals: VecDeque<f64>,
+}
+
+impl MtaResolver {
+    /// Creates a resolver that puts every point in the same zone.
+    ///
+    /// Returns an error if the zone is zero, since zones start at one.
+    pub fn fixed(zone: u8) -> Result<MtaResolver> {
+        if zone == 0 {
+            return Err(Error::InvalidMtaZone(zone));
+        }
+        Ok(MtaResolver {
+            max_zone: zone,
+            window: None,
+            pulses: VecDeque::new(),
+            intervals: VecDeque::new(),
+        })
+    }
+
+    /// Creates a resolver that puts each point in the lowest zone that places it between `min` and
+    /// `max` meters of range.
+    ///
+    /// This is a terrain-based heuristic: for airborne data, the window is the flying height
+    /// above ground plus or minus the terrain relief. Points that don't fit in any zone up to
+    /// `max_zone` are dropped, and it's an error for `max_zone` to be zero.
+    pub fn range_window(min: f64, max: f64, max_zone: u8) -> Result<MtaResolver> {
+        if max_zone == 0 {
+            return Err(Error::InvalidMtaZone(max_zone));
+        }
+        Ok(MtaResolver {
+            max_zone: max_zone,
+            window: Some((min, max)),
+            pulses: VecDeque::new(),
+            intervals: VecDeque::new(),
+        })
+    }
+
+    /// Returns the estimated pulse repetition interval, in seconds.
+    ///
+    /// This is the median of the most recent intervals between records, so gaps in the data,
+    /// e.g. between scan lines, don't throw it off. Returns `None` until at least two records
+    /// have been seen.
+    pub fn pulse_repetition_interval(&self) -> Option<f64> {
+        if self.intervals.is_empty() {
+            return None;
+        }
+        let mut intervals = self.intervals.iter().cloned().collect::<Vec<_>>();
+        intervals.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        Some(intervals[intervals.len() / 2])
+    }
+
+    /// Resolves the zones of a record's points.
+    ///
+    /// Returns the points, with their ranges, coordinates, angles and `mta_zone` updated. Points
+    /// that can't be placed in a zone are dropped.
+    pub fn resolve(&mut self,
+                   record: &Record,
+                   points: Vec<Point>,
+                   file_info: &FileInfo)
+                   -> Vec<Point> {
+        self.push(record);
+        let pri = self.pulse_repetition_interval();
+        points.into_iter()
+              .filter_map(|point| {
+                  let zone = match self.window {
+                      None => Some(self.max_zone),
+                      Some((min, max)) => {
+                          (1..self.max_zone as u16 + 1).map(|zone| zone as u8).find(|&zone| {
+                              self.shift(zone, pri, file_info)
+                                  .map_or(false, |(shift, _)| {
+                                      let range = point.range as f64 + shift;
+                                      range >= min && range <= max
+                                  })
+                          })
+                      }
+                  };
+                  zone.and_then(|zone| self.move_to_zone(point, zone, pri, file_info))
+              })
+              .collect()
+    }
+
+    fn push(&mut self, record: &Record) {
+        if let Some(last) = self.pulses.back() {
+            let interval = record.time_sorg - last.time_sorg;
+            if interval > 0.0 {
+                self.intervals.push_back(interval);
+                if self.intervals.len() > PRI_WINDOW {
+                    let _ = self.intervals.pop_front();
+                }
+            }
+        }
+        self.pulses.push_back(Pulse {
+            time_sorg: record.time_sorg,
+            origin: record.origin,
+            direction: record.direction,
+        });
+        while self.pulses.len() > (self.max_zone as usize).max(2) {
+            let _ = self.pulses.pop_front();
+        }
+    }
+
+    /// Returns the range shift for a zone and the pulse the point belongs to, if we've seen it.
+    fn shift(&self,
+             zone: u8,
+             pri: Option<f64>,
+             file_info: &FileInfo)
+             -> Option<(f64, Option<Pulse>)> {
+        if zone == 0 {
+            return None;
+        }
+        let pulses_back = zone as usize - 1;
+        let current = self.pulses.back().unwrap();
+        if pulses_back < self.pulses.len() {
+            let pulse = self.pulses[self.pulses.len() - 1 - pulses_back];
+            Some((file_info.v_group / 2.0 * (current.time_sorg - pulse.time_sorg),
+                  Some(pulse)))
+        } else {
+            pri.map(|pri| (file_info.v_group / 2.0 * pri * pulses_back as f64, None))
+        }
+    }
+
+    fn move_to_zone(&self,
+                    mut point: Point,
+                    zone: u8,
+                    pri: Option<f64>,
+                    file_info: &FileInfo)
+                    -> Option<Point> {
+        let (shift, pulse) = match self.shift(zone, pri, file_info) {
+            Some(shift) => shift,
+            None => return None,
+        };
+        let (origin, direction) = match pulse {
+            Some(pulse) => (pulse.origin, pulse.direction),
+            None => {
+                let current = self.pulses.back().unwrap();
+                (current.origin, current.direction)
+            }
+        };
+        let range = point.range as f64 + shift;
+        point.range = range as f32;
+        point.theta = (direction[2] / direction[0]).atan().to_degrees() as f32;
+        point.x = (origin[0] + direction[0] * range) as f32;
+        point.y = (origin[1] + direction[1] * range) as f32;
+        point.z = (origin[2] + direction[2] * range) as f32;
+        point.mta_zone = zone;
+        Some(point)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use backend::{test_info, test_record};
+    use convert::discretize;
+    use file::Record;
+
+    fn record(time: f64, direction: [f64; 3]) -> Record {
+        let mut record = test_record(time);
+        record.direction = direction;
+        record
+    }
+
+    #[test]
+    fn pulse_repetition_interval() {
+        let info = test_info();
+        let mut resolver = MtaResolver::fixed(1).unwrap();
+        assert_eq!(None, resolver.pulse_repetition_interval());
+        for &time in &[0.0, 2e-6, 4e-6, 6e-6, 1.0, 1.0 + 2e-6] {
+            let record = record(time, [1.0, 0.0, 0.0]);
+            let _ = resolver.resolve(&record, Vec::new(), &info);
+        }
+        assert!((resolver.pulse_repetition_interval().unwrap() - 2e-6).abs() < 1e-12);
+    }
+
+    #[test]
+    fn fixed_zone() {
+        let info = test_info();
+        let mut resolver = MtaResolver::fixed(2).unwrap();
+        let first = record(0.0, [0.0, 0.0, -1.0]);
+        let second = record(2e-6, [1.0, 0.0, 0.0]);
+        let _ = resolver.resolve(&first, discretize(&first, &info).unwrap(), &info);
+        let points = discretize(&second, &info).unwrap();
+        let range = points[0].range as f64;
+        let points = resolver.resolve(&second, points, &info);
+        assert_eq!(1, points.len());
+        assert_eq!(2, points[0].mta_zone);
+        let expected = range + info.v_group / 2.0 * 2e-6;
+        assert!((points[0].range as f64 - expected).abs() < 1e-3);
+        assert!((points[0].z as f64 + expected).abs() < 1e-3);
+        assert_eq!(0.0, points[0].x);
+    }
+
+    #[test]
+    fn range_window() {
+        let info = test_info();
+        let zone_width = info.v_group / 2.0 * 2e-6;
+        let mut resolver = MtaResolver::range_window(zone_width * 2.0, zone_width * 3.0, 4)
+                           .unwrap();
+        let mut zones = Vec::new();
+        for i in 0..4 {
+            let record = record(i as f64 * 2e-6, [1.0, 0.0, 0.0]);
+            let points = discretize(&record, &info).unwrap();
+            zones.extend(resolver.resolve(&record, points, &info).iter().map(|p| p.mta_zone));
+        }
+        // The first record's point is dropped, since we don't know the pulse rate yet.
+        assert_eq!(vec![3, 3, 3], zones);
+    }
+
+    #[test]
+    fn zone_zero() {
+        assert!(MtaResolver::fixed(0).is_err());
+        assert!(MtaResolver::range_window(0.0, 1.0, 0).is_err());
+    }
+
+    #[test]
+    fn max_zone_255() {
+        let info = test_info();
+        let mut resolver = MtaResolver::range_window(1e9, 2e9, 255).unwrap();
+        for i in 0..2 {
+            let record = record(i as f64 * 2e-6, [1.0, 0.0, 0.0]);
+            let points = discretize(&record, &info).unwrap();
+            assert!(resolver.resolve(&record, points, &info).is_empty());
+        }
+    }
+}
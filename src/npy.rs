@@ -0,0 +1,343 @@
+//! Write NumPy .npy arrays and .npz archives.
+//!
+//! An .npy file is a short header, describing the array's element type and shape as a Python
+//! dictionary literal, followed by the array's elements in C order. We write format version 1.0,
+//! little-endian.
+//!
+//! An .npz file is a zip archive of .npy files, one per named array. `numpy.load` reads both
+//! compressed and uncompressed archives; we write uncompressed ("stored") entries, so we only need
+//! a CRC-32 and no compression library. Archives use the original zip format, without the 64-bit
+//! extensions, so each array and the whole archive must be smaller than 4 GiB.
+
+use std::fs;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+use std::u16;
+use std::u32;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use Result;
+
+const MAGIC: &'static [u8] = b"\x93NUMPY";
+const HEADER_ALIGNMENT: usize = 64;
+
+const LOCAL_FILE_HEADER_SIGNATURE: u32 = 0x04034b50;
+const CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x02014b50;
+const END_OF_CENTRAL_DIRECTORY_SIGNATURE: u32 = 0x06054b50;
+const ZIP_VERSION: u16 = 20;
+/// 1980-01-01, the earliest date a zip file can hold.
+const DOS_DATE: u16 = (1 << 5) | 1;
+
+lazy_static! {
+    static ref CRC_TABLE: Vec<u32> = (0..256u32)
+        .map(|n| {
+            (0..8).fold(n, |c, _| if c & 1 == 1 { 0xedb88320 ^ (c >> 1) } else { c >> 1 })
+        })
+        .collect();
+}
+
+/// A type that can be an element of an .npy array.
+pub trait Element: Copy {
+    /// Returns the NumPy type description, e.g. `<u2`.
+    fn descr() -> &'static str;
+
+    /// Writes this element, little-endian.
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()>;
+}
+
+impl Element for u8 {
+    fn descr() -> &'static str {
+        "|u1"
+    }
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u8(*self)
+    }
+}
+
+impl Element for u16 {
+    fn descr() -> &'static str {
+        "<u2"
+    }
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u16::<LittleEndian>(*self)
+    }
+}
+
+impl Element for u32 {
+    fn descr() -> &'static str {
+        "<u4"
+    }
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_u32::<LittleEndian>(*self)
+    }
+}
+
+impl Element for f64 {
+    fn descr() -> &'static str {
+        "<f8"
+    }
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_f64::<LittleEndian>(*self)
+    }
+}
+
+/// Writes an array as an .npy file.
+///
+/// `data` holds the elements in C order, so its length must be the product of `shape`.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::npy::write_npy;
+/// let mut bytes = Vec::new();
+/// write_npy(&mut bytes, &[2, 3], &[1u16, 2, 3, 4, 5, 6]).unwrap();
+/// ```
+pub fn write_npy<W, T>(writer: &mut W, shape: &[usize], data: &[T]) -> Result<()>
+    where W: Write,
+          T: Element
+{
+    let len = shape.iter().fold(1, |len, &n| len * n);
+    if len != data.len() {
+        return Err(invalid_input(format!("An array of shape {:?} can't hold {} elements",
+                                         shape,
+                                         data.len())));
+    }
+    let shape = match shape.len() {
+        1 => format!("({},)", shape[0]),
+        _ => format!("({})", shape.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ")),
+    };
+    let mut header = format!("{{'descr': '{}', 'fortran_order': False, 'shape': {}, }}",
+                             T::descr(),
+                             shape);
+    // The magic, version and header length take ten bytes, and the header ends with a newline.
+    while (MAGIC.len() + 4 + header.len() + 1) % HEADER_ALIGNMENT != 0 {
+        header.push(' ');
+    }
+    header.push('\n');
+    if header.len() > u16::MAX as usize {
+        return Err(invalid_input(format!("The .npy header for shape {} is too long", shape)));
+    }
+    try!(writer.write_all(MAGIC));
+    try!(writer.write_all(&[1, 0]));
+    try!(writer.write_u16::<LittleEndian>(header.len() as u16));
+    try!(writer.write_all(header.as_bytes()));
+    for element in data {
+        try!(element.write(writer));
+    }
+    Ok(())
+}
+
+/// Writes named arrays to an .npz archive.
+///
+/// Each array is built in memory before it's written.
+///
+/// # Examples
+///
+/// ```
+/// use std::io::Cursor;
+/// use sdf::npy::NpzWriter;
+/// let mut writer = NpzWriter::new(Cursor::new(Vec::new()));
+/// writer.write_array("time", &[3], &[0.0, 0.5, 1.0]).unwrap();
+/// writer.write_array("samples", &[3, 2], &[1u16, 2, 3, 4, 5, 6]).unwrap();
+/// let archive = writer.finish().unwrap();
+/// ```
+#[derive(Debug)]
+pub struct NpzWriter<W: Write> {
+    writer: W,
+    offset: u64,
+    entries: Vec<Entry>,
+}
+
+#[derive(Debug)]
+struct Entry {
+    name: String,
+    crc: u32,
+    size: u32,
+    offset: u32,
+}
+
+impl NpzWriter<BufWriter<fs::File>> {
+    /// Creates a new .npz file at the given path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::npy::NpzWriter;
+    /// let writer = NpzWriter::from_path("/dev/null").unwrap();
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<NpzWriter<BufWriter<fs::File>>> {
+        let file = try!(fs::File::create(path));
+        Ok(NpzWriter::new(BufWriter::new(file)))
+    }
+}
+
+impl<W: Write> NpzWriter<W> {
+    /// Creates a new archive writer.
+    pub fn new(writer: W) -> NpzWriter<W> {
+        NpzWriter {
+            writer: writer,
+            offset: 0,
+            entries: Vec::new(),
+        }
+    }
+
+    /// Writes an array, which `numpy.load` will return under `name`.
+    ///
+    /// See `write_npy` for how the array is laid out.
+    pub fn write_array<T: Element>(&mut self,
+                                   name: &str,
+                                   shape: &[usize],
+                                   data: &[T])
+                                   -> Result<()> {
+        let mut bytes = Vec::new();
+        try!(write_npy(&mut bytes, shape, data));
+        let file_name = format!("{}.npy", name);
+        if bytes.len() > u32::MAX as usize || self.offset > u32::MAX as u64 {
+            return Err(invalid_input(format!("{} is too big for an .npz file", name)));
+        }
+        let entry = Entry {
+            name: file_name,
+            crc: crc32(&bytes),
+            size: bytes.len() as u32,
+            offset: self.offset as u32,
+        };
+        try!(self.writer.write_u32::<LittleEndian>(LOCAL_FILE_HEADER_SIGNATURE));
+        try!(self.writer.write_u16::<LittleEndian>(ZIP_VERSION));
+        try!(write_entry_fields(&mut self.writer, &entry));
+        try!(self.writer.write_all(entry.name.as_bytes()));
+        try!(self.writer.write_all(&bytes));
+        self.offset += 30 + entry.name.len() as u64 + bytes.len() as u64;
+        self.entries.push(entry);
+        Ok(())
+    }
+
+    /// Writes the archive's directory, and returns the underlying writer.
+    pub fn finish(mut self) -> Result<W> {
+        if self.offset > u32::MAX as u64 || self.entries.len() > u16::MAX as usize {
+            return Err(invalid_input("The archive is too big for an .npz file".to_string()));
+        }
+        let directory_offset = self.offset as u32;
+        let mut directory_size = 0u32;
+        for entry in &self.entries {
+            try!(self.writer.write_u32::<LittleEndian>(CENTRAL_DIRECTORY_SIGNATURE));
+            try!(self.writer.write_u16::<LittleEndian>(ZIP_VERSION)); // version made by
+            try!(self.writer.write_u16::<LittleEndian>(ZIP_VERSION)); // version needed
+            try!(write_entry_fields(&mut self.writer, entry));
+            try!(self.writer.write_u16::<LittleEndian>(0)); // comment length
+            try!(self.writer.write_u16::<LittleEndian>(0)); // disk number
+            try!(self.writer.write_u16::<LittleEndian>(0)); // internal attributes
+            try!(self.writer.write_u32::<LittleEndian>(0)); // external attributes
+            try!(self.writer.write_u32::<LittleEndian>(entry.offset));
+            try!(self.writer.write_all(entry.name.as_bytes()));
+            directory_size += 46 + entry.name.len() as u32;
+        }
+        try!(self.writer.write_u32::<LittleEndian>(END_OF_CENTRAL_DIRECTORY_SIGNATURE));
+        try!(self.writer.write_u16::<LittleEndian>(0)); // this disk
+        try!(self.writer.write_u16::<LittleEndian>(0)); // disk with the directory
+        try!(self.writer.write_u16::<LittleEndian>(self.entries.len() as u16));
+        try!(self.writer.write_u16::<LittleEndian>(self.entries.len() as u16));
+        try!(self.writer.write_u32::<LittleEndian>(directory_size));
+        try!(self.writer.write_u32::<LittleEndian>(directory_offset));
+        try!(self.writer.write_u16::<LittleEndian>(0)); // comment length
+        try!(self.writer.flush());
+        Ok(self.writer)
+    }
+}
+
+/// Writes the fields that the local file header and central directory share, from the flags
+/// through the extra field length.
+fn write_entry_fields<W: Write>(writer: &mut W, entry: &Entry) -> Result<()> {
+    try!(writer.write_u16::<LittleEndian>(0)); // flags
+    try!(writer.write_u16::<LittleEndian>(0)); // compression method, stored
+    try!(writer.write_u16::<LittleEndian>(0)); // modification time
+    try!(writer.write_u16::<LittleEndian>(DOS_DATE));
+    try!(writer.write_u32::<LittleEndian>(entry.crc));
+    try!(writer.write_u32::<LittleEndian>(entry.size)); // compressed size
+    try!(writer.write_u32::<LittleEndian>(entry.size)); // uncompressed size
+    try!(writer.write_u16::<LittleEndian>(entry.name.len() as u16));
+    try!(writer.write_u16::<LittleEndian>(0)); // extra field length
+    Ok(())
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0, |crc, &b| CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+fn invalid_input(message: String) -> ::Error {
+    io::Error::new(io::ErrorKind::InvalidInput, message).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::crc32;
+
+    use std::io::Cursor;
+
+    use byteorder::{LittleEndian, ReadBytesExt};
+
+    #[test]
+    fn crc() {
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+        assert_eq!(0, crc32(b""));
+    }
+
+    #[test]
+    fn npy_header() {
+        let mut bytes = Vec::new();
+        write_npy(&mut bytes, &[2, 3], &[1u16, 2, 3, 4, 5, 6]).unwrap();
+        assert_eq!(b"\x93NUMPY\x01\x00", &bytes[0..8]);
+        let header_len = (&bytes[8..10]).read_u16::<LittleEndian>().unwrap() as usize;
+        assert_eq!(0, (10 + header_len) % 64);
+        let header = String::from_utf8(bytes[10..10 + header_len].to_vec()).unwrap();
+        assert!(header.starts_with("{'descr': '<u2', 'fortran_order': False, 'shape': (2, 3), }"));
+        assert!(header.ends_with("\n"));
+        assert_eq!(10 + header_len + 12, bytes.len());
+        assert_eq!(6, (&bytes[bytes.len() - 2..]).read_u16::<LittleEndian>().unwrap());
+    }
+
+    #[test]
+    fn npy_one_dimensional() {
+        let mut bytes = Vec::new();
+        let empty: &[f64] = &[];
+        write_npy(&mut bytes, &[0], empty).unwrap();
+        assert!(String::from_utf8_lossy(&bytes).contains("'shape': (0,)"));
+    }
+
+    #[test]
+    fn npy_wrong_shape() {
+        assert!(write_npy(&mut Vec::new(), &[2, 2], &[1u16, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn npz_layout() {
+        let mut writer = NpzWriter::new(Cursor::new(Vec::new()));
+        writer.write_array("a", &[1], &[7u32]).unwrap();
+        writer.write_array("b", &[2], &[0.5, 1.5]).unwrap();
+        let bytes = writer.finish().unwrap().into_inner();
+        let mut cursor = Cursor::new(&bytes[..]);
+        assert_eq!(LOCAL_FILE_HEADER_SIGNATURE,
+                   cursor.read_u32::<LittleEndian>().unwrap());
+        cursor.set_position(14);
+        let crc = cursor.read_u32::<LittleEndian>().unwrap();
+        let size = cursor.read_u32::<LittleEndian>().unwrap() as usize;
+        assert_eq!(size, cursor.read_u32::<LittleEndian>().unwrap() as usize);
+        assert_eq!(5, cursor.read_u16::<LittleEndian>().unwrap());
+        assert_eq!(b"a.npy", &bytes[30..35]);
+        assert_eq!(crc, crc32(&bytes[35..35 + size]));
+
+        let end = bytes.len() - 22;
+        cursor.set_position(end as u64);
+        assert_eq!(END_OF_CENTRAL_DIRECTORY_SIGNATURE,
+                   cursor.read_u32::<LittleEndian>().unwrap());
+        cursor.set_position(end as u64 + 10);
+        assert_eq!(2, cursor.read_u16::<LittleEndian>().unwrap());
+        let directory_size = cursor.read_u32::<LittleEndian>().unwrap() as usize;
+        let directory_offset = cursor.read_u32::<LittleEndian>().unwrap() as usize;
+        assert_eq!(end, directory_offset + directory_size);
+        cursor.set_position(directory_offset as u64);
+        assert_eq!(CENTRAL_DIRECTORY_SIGNATURE,
+                   cursor.read_u32::<LittleEndian>().unwrap());
+    }
+}
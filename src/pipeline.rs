@@ -0,0 +1,421 @@
+//! Discretize records on several threads.
+//!
+//! Calls into sdfifc are serialized, so reading can't go any faster on more threads. Instead, a
+//! single reader thread owns the `File` and streams owned `Record`s through a bounded channel to a
+//! pool of worker threads, which run `discretize_with_options`. Results come back out in record
+//! order.
+//!
+//! The reader can't get more than the pipeline's capacity of records ahead of the consumer: it
+//! needs a slot for each record it sends, and the consumer only hands a slot back when it yields
+//! a result. That bounds both the channels and the results waiting to be put back in order.
+//!
+//! The pipeline takes a function that returns the `File`, which is called on the reader thread.
+//! That can either open the file there or, since a `File` can be sent between threads, hand over
+//! one that's already open.
+
+use std::collections::BTreeMap;
+use std::result;
+use std::sync::{Arc, Mutex};
+use std::sync::mpsc::{Receiver, SyncSender, sync_channel};
+use std::thread;
+
+use num_cpus;
+
+use Result;
+use backend::Backend;
+use convert::{DiscretizeOptions, Point, discretize_with_options};
+use error::{Error, RecordError};
+use file::{File, FileInfo, Record, TimeBase};
+
+/// The default number of records that can be waiting for a worker.
+const DEFAULT_CAPACITY: usize = 1024;
+
+/// Configures and starts a discretization pipeline.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::file::File;
+/// use sdf::pipeline::Pipeline;
+/// let points = Pipeline::new()
+///                  .threads(4)
+///                  .run(|| File::open("data/110630_174316.sdf"))
+///                  .unwrap();
+/// for result in points.take(10) {
+///     match result {
+///         Ok(points) => println!("{} points", points.len()),
+///         Err(err) => println!("Skipping record {}: {}", err.index, err.error),
+///     }
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct Pipeline {
+    threads: usize,
+    capacity: usize,
+    options: DiscretizeOptions,
+    window: Option<(f64, f64, TimeBase)>,
+}
+
+impl Pipeline {
+    /// Creates a new pipeline with one worker per CPU and the default discretization options.
+    pub fn new() -> Pipeline {
+        Pipeline {
+            threads: num_cpus::get(),
+            capacity: DEFAULT_CAPACITY,
+            options: DiscretizeOptions::default(),
+            window: None,
+        }
+    }
+
+    /// Sets the number of worker threads.
+    pub fn threads(mut self, threads: usize) -> Pipeline {
+        self.threads = threads.max(1);
+        self
+    }
+
+    /// Sets the number of records that can be in the pipeline at once, i.e. read but not yet
+    /// returned by the iterator.
+    pub fn capacity(mut self, capacity: usize) -> Pipeline {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    /// Sets the discretization options.
+    pub fn options(mut self, options: DiscretizeOptions) -> Pipeline {
+        self.options = options;
+        self
+    }
+
+    /// Only discretizes the records whose time is at least `start` and less than `end`.
+    ///
+    /// The records are chosen as by `File::records_between_times`. Without a window, the pipeline
+    /// reads from the file's current position to the end.
+    pub fn between_times(mut self, start: f64, end: f64, time_base: TimeBase) -> Pipeline {
+        self.window = Some((start, end, time_base));
+        self
+    }
+
+    /// Starts the pipeline.
+    ///
+    /// `open` is called on the reader thread to create the file. The file is reindexed if needed.
+    /// Returns an error if the file can't be opened, reindexed, or its info read; errors reading
+    /// records, or seeking to the start of the window, show up in the returned iterator. An error
+    /// seeking to the start of the window isn't about any one record, so it has index zero.
+    pub fn run<B, F>(self, open: F) -> Result<Discretized>
+        where B: Backend,
+              F: FnOnce() -> Result<File<B>> + Send + 'static
+    {
+        let (info_sender, info_receiver) = sync_channel(1);
+        let (record_sender, record_receiver) = sync_channel(self.capacity);
+        let (result_sender, result_receiver) = sync_channel(self.capacity);
+        let (slot_sender, slot_receiver) = sync_channel(self.capacity);
+        for _ in 0..self.capacity {
+            slot_sender.send(()).expect("The slot receiver is still in scope");
+        }
+        let reader_result_sender = result_sender.clone();
+        let window = self.window;
+        let _ = thread::spawn(move || {
+            read(open,
+                 window,
+                 info_sender,
+                 slot_receiver,
+                 record_sender,
+                 reader_result_sender)
+        });
+        let info = match info_receiver.recv() {
+            Ok(Ok(info)) => info,
+            Ok(Err(err)) => return Err(err),
+            Err(_) => return Err(Error::Runtime("The reader thread exited early".to_string())),
+        };
+        let record_receiver = Arc::new(Mutex::new(record_receiver));
+        for _ in 0..self.threads {
+            let record_receiver = record_receiver.clone();
+            let result_sender = result_sender.clone();
+            let info = info.clone();
+            let options = self.options.clone();
+            let _ = thread::spawn(move || work(record_receiver, result_sender, info, options));
+        }
+        Ok(Discretized {
+            receiver: result_receiver,
+            slots: slot_sender,
+            pending: BTreeMap::new(),
+            next: 0,
+        })
+    }
+}
+
+impl Default for Pipeline {
+    fn default() -> Pipeline {
+        Pipeline::new()
+    }
+}
+
+/// A result, along with its position in the output.
+type Message = (usize, result::Result<Vec<Point>, RecordError>);
+
+/// A record to be discretized, along with its position in the output and its index in the file.
+type Work = (usize, u32, Record);
+
+fn read<B, F>(open: F,
+              window: Option<(f64, f64, TimeBase)>,
+              info_sender: SyncSender<Result<FileInfo>>,
+              slots: Receiver<()>,
+              record_sender: SyncSender<Work>,
+              result_sender: SyncSender<Message>)
+    where B: Backend,
+          F: FnOnce() -> Result<File<B>>
+{
+    let mut file = match open().and_then(|mut file| file.reindex().map(|_| file)) {
+        Ok(file) => file,
+        Err(err) => {
+            let _ = info_sender.send(Err(err));
+            return;
+        }
+    };
+    match file.info() {
+        Ok(info) => {
+            if info_sender.send(Ok(info)).is_err() {
+                return;
+            }
+        }
+        Err(err) => {
+            let _ = info_sender.send(Err(err));
+            return;
+        }
+    }
+    let result = match window {
+        Some((start, end, time_base)) => {
+            file.records_between_times(start, end, time_base).map(|records| {
+                send(records,
+                     |records| records.next_index(),
+                     &slots,
+                     &record_sender,
+                     &result_sender)
+            })
+        }
+        None => {
+            file.records().map(|records| {
+                send(records,
+                     |records| records.next_index(),
+                     &slots,
+                     &record_sender,
+                     &result_sender)
+            })
+        }
+    };
+    if let Err(err) = result {
+        let _ = result_sender.send((0,
+                                    Err(RecordError {
+                                        index: 0,
+                                        error: err,
+                                    })));
+    }
+}
+
+/// Sends records to the workers, and unreadable ones straight to the results.
+///
+/// Waits for a free slot before sending each one, and stops once the consumer has hung up.
+fn send<I, F>(mut records: I,
+              next_index: F,
+              slots: &Receiver<()>,
+              record_sender: &SyncSender<Work>,
+              result_sender: &SyncSender<Message>)
+    where I: Iterator<Item = result::Result<Record, RecordError>>,
+          F: Fn(&I) -> u32
+{
+    let mut position = 0;
+    while let Some(result) = records.next() {
+        if slots.recv().is_err() {
+            return;
+        }
+        let sent = match result {
+            Ok(record) => record_sender.send((position, next_index(&records) - 1, record)).is_ok(),
+            Err(err) => result_sender.send((position, Err(err))).is_ok(),
+        };
+        if !sent {
+            return;
+        }
+        position += 1;
+    }
+}
+
+fn work(records: Arc<Mutex<Receiver<Work>>>,
+        results: SyncSender<Message>,
+        info: FileInfo,
+        options: DiscretizeOptions) {
+    loop {
+        let message = match records.lock() {
+            Ok(receiver) => receiver.recv(),
+            Err(_) => return,
+        };
+        let (position, index, record) = match message {
+            Ok(message) => message,
+            Err(_) => return,
+        };
+        let points = discretize_with_options(&record, &info, &options).map_err(|err| {
+            RecordError {
+                index: index,
+                error: err,
+            }
+        });
+        if results.send((position, points)).is_err() {
+            return;
+        }
+    }
+}
+
+/// An iterator over the discretized points of each record, in record order.
+///
+/// Each item is the result of discretizing one record, so a record that can't be read or
+/// discretized doesn't stop the iteration. Errors carry the one-based index of their record.
+///
+/// Dropping the iterator shuts down the pipeline's threads.
+#[derive(Debug)]
+pub struct Discretized {
+    receiver: Receiver<Message>,
+    slots: SyncSender<()>,
+    pending: BTreeMap<usize, result::Result<Vec<Point>, RecordError>>,
+    next: usize,
+}
+
+impl Iterator for Discretized {
+    type Item = result::Result<Vec<Point>, RecordError>;
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(result) = self.pending.remove(&self.next) {
+                self.next += 1;
+                // There are never more slots than the channel can hold, so this can't block.
+                let _ = self.slots.send(());
+                return Some(result);
+            }
+            match self.receiver.recv() {
+                Ok((index, result)) => {
+                    let _ = self.pending.insert(index, result);
+                }
+                Err(_) => {
+                    // Everyone's hung up, so if we're still missing a record a worker must have
+                    // panicked. Skip over the gap.
+                    return match self.pending.keys().next().cloned() {
+                        Some(index) => {
+                            self.next = index;
+                            self.next()
+                        }
+                        None => None,
+                    };
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use backend::{Memory, test_info, test_record};
+    use convert::discretize;
+    use error::{Error, RecordError};
+    use file::{Channel, File, Record, TimeBase};
+
+    fn record(i: usize) -> Record {
+        let mut record = test_record(i as f64 * 1e-5);
+        let mut samples = vec![0; 20];
+        samples[2 + i % 10] = 80;
+        samples[3 + i % 10] = 30;
+        record.blocks[1].samples = samples;
+        if i % 7 == 3 {
+            record.blocks[0].samples = vec![0, 0, 0, 0];
+        }
+        record
+    }
+
+    #[test]
+    fn in_order() {
+        let records = (0..200).map(record).collect::<Vec<_>>();
+        let expected = records.iter()
+                              .map(|r| discretize(r, &test_info()).map(|p| p[0].range))
+                              .collect::<Vec<_>>();
+        let results = Pipeline::new()
+                          .threads(4)
+                          .capacity(8)
+                          .run(move || Ok(File::new(Memory::new(test_info(), records))))
+                          .unwrap()
+                          .collect::<Vec<_>>();
+        assert_eq!(expected.len(), results.len());
+        for (expected, result) in expected.iter().zip(results.iter()) {
+            match (expected, result) {
+                (&Ok(range), &Ok(ref points)) => assert_eq!(range, points[0].range),
+                (&Err(_), &Err(RecordError { error: Error::NeedSingleReferencePeak(0), .. })) => {}
+                _ => panic!("Mismatch: {:?} {:?}", expected, result),
+            }
+        }
+    }
+
+    #[test]
+    fn housekeeping() {
+        let mut records = (0..20).map(record).collect::<Vec<_>>();
+        records[5].housekeeping = true;
+        records[5].blocks.clear();
+        let results = Pipeline::new()
+                          .threads(4)
+                          .capacity(8)
+                          .run(move || Ok(File::new(Memory::new(test_info(), records))))
+                          .unwrap()
+                          .collect::<Vec<_>>();
+        assert_eq!(20, results.len());
+        match results[5] {
+            Err(RecordError { index: 6, error: Error::MissingChannel(Channel::Reference) }) => {}
+            ref result => panic!("Unexpected result: {:?}", result),
+        }
+        assert!(results[6].is_ok());
+    }
+
+    #[test]
+    fn between_times() {
+        let records = (0..200).map(record).collect::<Vec<_>>();
+        let results = Pipeline::new()
+                          .threads(2)
+                          .between_times(0.95e-4, 1.95e-4, TimeBase::Internal)
+                          .run(move || Ok(File::new(Memory::new(test_info(), records))))
+                          .unwrap()
+                          .collect::<Vec<_>>();
+        assert_eq!(10, results.len());
+        let first = results.iter().filter_map(|r| r.as_ref().ok()).next().unwrap();
+        assert!(first[0].time >= 1e-4);
+    }
+
+    #[test]
+    fn error_indices() {
+        let records = (0..200).map(record).collect::<Vec<_>>();
+        let indices = Pipeline::new()
+                          .threads(2)
+                          .between_times(0.95e-4, 1.95e-4, TimeBase::Internal)
+                          .run(move || Ok(File::new(Memory::new(test_info(), records))))
+                          .unwrap()
+                          .filter_map(|r| r.err().map(|err| err.index))
+                          .collect::<Vec<_>>();
+        assert_eq!(vec![11, 18], indices);
+    }
+
+    #[test]
+    fn small_capacity() {
+        let records = (0..200).map(record).collect::<Vec<_>>();
+        let times = Pipeline::new()
+                        .threads(4)
+                        .capacity(1)
+                        .run(move || Ok(File::new(Memory::new(test_info(), records))))
+                        .unwrap()
+                        .filter_map(|r| r.ok().map(|points| points[0].time))
+                        .collect::<Vec<_>>();
+        assert_eq!(171, times.len());
+        assert!(times.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn open_error() {
+        let result = Pipeline::new().run(|| -> ::Result<File<Memory>> {
+            Err(Error::Runtime("could not open".to_string()))
+        });
+        assert!(result.is_err());
+    }
+}
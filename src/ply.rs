@@ -0,0 +1,154 @@
+//! Write discretized points to binary PLY files.
+//!
+//! Points are written as `vertex` elements in little-endian binary, with these properties:
+//!
+//! | Property | Type |
+//! | -------- | ---- |
+//! | x, y, z | `float` |
+//! | time | `double` |
+//! | range | `float` |
+//! | theta | `float` |
+//! | amplitude | `ushort` |
+//! | target | `uchar` |
+//! | num_target | `uchar` |
+//! | facet | `ushort` |
+//! | high_channel | `uchar` |
+//!
+//! The vertex count isn't known until all the points are written, so it's written into the header
+//! as a zero-padded placeholder, and filled in by `close`.
+
+use std::fs;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use Result;
+use convert::Point;
+
+const PROPERTIES: &'static [(&'static str, &'static str)] = &[("float", "x"),
+                                                                ("float", "y"),
+                                                                ("float", "z"),
+                                                                ("double", "time"),
+                                                                ("float", "range"),
+                                                                ("float", "theta"),
+                                                                ("ushort", "amplitude"),
+                                                                ("uchar", "target"),
+                                                                ("uchar", "num_target"),
+                                                                ("ushort", "facet"),
+                                                                ("uchar", "high_channel")];
+
+/// Writes points to a .ply file.
+///
+/// A writer that is dropped without being closed leaves a file with a vertex count of zero.
+#[derive(Debug)]
+pub struct PlyWriter<W: Write + Seek> {
+    writer: W,
+    number_of_points: u64,
+}
+
+impl PlyWriter<BufWriter<fs::File>> {
+    /// Creates a new .ply file at the given path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::ply::PlyWriter;
+    /// let writer = PlyWriter::from_path("/dev/null").unwrap();
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<PlyWriter<BufWriter<fs::File>>> {
+        let file = try!(fs::File::create(path));
+        PlyWriter::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write + Seek> PlyWriter<W> {
+    /// Creates a new writer and writes the header.
+    pub fn new(writer: W) -> Result<PlyWriter<W>> {
+        let mut writer = PlyWriter {
+            writer: writer,
+            number_of_points: 0,
+        };
+        try!(writer.write_header());
+        Ok(writer)
+    }
+
+    /// Writes a point.
+    pub fn write_point(&mut self, point: &Point) -> Result<()> {
+        try!(self.writer.write_f32::<LittleEndian>(point.x));
+        try!(self.writer.write_f32::<LittleEndian>(point.y));
+        try!(self.writer.write_f32::<LittleEndian>(point.z));
+        try!(self.writer.write_f64::<LittleEndian>(point.time));
+        try!(self.writer.write_f32::<LittleEndian>(point.range));
+        try!(self.writer.write_f32::<LittleEndian>(point.theta));
+        try!(self.writer.write_u16::<LittleEndian>(point.peak.amplitude));
+        try!(self.writer.write_u8(point.target));
+        try!(self.writer.write_u8(point.num_target));
+        try!(self.writer.write_u16::<LittleEndian>(point.facet));
+        try!(self.writer.write_u8(point.high_channel as u8));
+        self.number_of_points += 1;
+        Ok(())
+    }
+
+    /// Rewrites the header with the final vertex count, and returns the underlying writer.
+    pub fn close(mut self) -> Result<W> {
+        let _ = try!(self.writer.seek(SeekFrom::Start(0)));
+        try!(self.write_header());
+        let _ = try!(self.writer.seek(SeekFrom::End(0)));
+        try!(self.writer.flush());
+        Ok(self.writer)
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        try!(write!(self.writer,
+                    "ply\nformat binary_little_endian 1.0\nelement vertex {:020}\n",
+                    self.number_of_points));
+        for &(kind, name) in PROPERTIES {
+            try!(writeln!(self.writer, "property {} {}", kind, name));
+        }
+        try!(write!(self.writer, "end_header\n"));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Cursor, Read};
+
+    use byteorder::{LittleEndian, ReadBytesExt};
+
+    use backend::{test_info, test_record};
+    use convert::discretize;
+
+    fn points() -> Vec<Point> {
+        let record = test_record(1.0);
+        discretize(&record, &test_info()).unwrap()
+    }
+
+    #[test]
+    fn header_and_points() {
+        let points = points();
+        let mut writer = PlyWriter::new(Cursor::new(Vec::new())).unwrap();
+        for point in &points {
+            writer.write_point(point).unwrap();
+        }
+        let mut cursor = writer.close().unwrap();
+        let bytes = cursor.get_ref().clone();
+        let end = b"end_header\n";
+        let header_len = bytes.windows(end.len()).position(|w| w == end).unwrap() + end.len();
+        let header = String::from_utf8(bytes[..header_len].to_vec()).unwrap();
+        assert!(header.contains(&format!("element vertex {:020}\n", points.len())));
+        assert_eq!(header_len + 35 * points.len(), bytes.len());
+        cursor.set_position(header_len as u64);
+        let mut xyz = [0.0; 3];
+        for value in &mut xyz {
+            *value = cursor.read_f32::<LittleEndian>().unwrap();
+        }
+        assert_eq!([points[0].x, points[0].y, points[0].z], xyz);
+        let mut rest = Vec::new();
+        let _ = cursor.read_to_end(&mut rest).unwrap();
+        assert_eq!(23 + 35 * (points.len() - 1), rest.len());
+    }
+}
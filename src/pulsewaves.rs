@@ -0,0 +1,400 @@
+//! Write raw sample data records to PulseWaves files.
+//!
+//! PulseWaves stores pulses in a .pls file and their waveforms in a companion .wvs file with the
+//! same base name. Every `Record` becomes one pulse (format 0), whose anchor point is the record's
+//! origin and whose target point lies along the record's direction, at the one-way distance light
+//! travels in 1000 sampling intervals. The pulse's GPS time is the record's external time.
+//!
+//! All pulses share a single pulse descriptor with four samplings, one per channel:
+//!
+//! 1. The reference channel, as the outgoing waveform.
+//! 2. The high channel, as a returning waveform.
+//! 3. The low channel, as a returning waveform.
+//! 4. The saturation channel, as a returning waveform.
+//!
+//! Each sampling has a variable number of segments, one per sample block of that channel, so a
+//! record without e.g. a high channel block simply has zero high channel segments. Each segment
+//! stores its duration from the anchor (in thousandths of a sampling interval) and its number of
+//! samples, followed by the 16-bit samples themselves.
+
+use std::fs;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use Result;
+use file::{Channel, FileInfo, Record};
+use util::{creation_date, write_fixed_str};
+
+const HEADER_SIZE: u16 = 352;
+const PULSE_SIZE: u32 = 48;
+const VLR_HEADER_SIZE: u32 = 96;
+const COMPOSITION_SIZE: u32 = 116;
+const SAMPLING_SIZE: u32 = 104;
+const WAVES_HEADER_SIZE: usize = 60;
+const DESCRIPTOR_INDEX: u16 = 1;
+const DESCRIPTOR_RECORD_ID: u32 = 200000 + DESCRIPTOR_INDEX as u32;
+const SAMPLINGS: [Channel; 4] = [Channel::Reference,
+                                 Channel::High,
+                                 Channel::Low,
+                                 Channel::Saturation];
+const SAMPLING_TYPE_OUTGOING: u8 = 1;
+const SAMPLING_TYPE_RETURNING: u8 = 2;
+const TARGET_SAMPLES: f64 = 1000.0;
+const DURATION_SCALE: f32 = 0.001;
+const T_SCALE: f64 = 1e-6;
+const XYZ_SCALE: f64 = 0.001;
+
+/// Writes sample data records to a PulseWaves .pls/.wvs file pair.
+///
+/// The .pls header is written with placeholder values when the writer is created, and is
+/// rewritten with the final pulse count and bounds by `close`. A writer that is dropped without
+/// being closed leaves invalid files behind.
+#[derive(Debug)]
+pub struct PulseWavesWriter<W: Write + Seek, V: Write + Seek> {
+    pls: W,
+    wvs: V,
+    instrument: String,
+    serial: String,
+    v_group: f64,
+    sampling_time: f64,
+    wvs_offset: u64,
+    number_of_pulses: i64,
+    min_t: i64,
+    max_t: i64,
+    min: [f64; 3],
+    max: [f64; 3],
+}
+
+impl PulseWavesWriter<BufWriter<fs::File>, BufWriter<fs::File>> {
+    /// Creates a new .pls file at the given path, and a .wvs file alongside it.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::file::File;
+    /// use sdf::pulsewaves::PulseWavesWriter;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let ref file_info = file.info().unwrap();
+    /// let writer = PulseWavesWriter::from_path("/tmp/110630_174316.pls", file_info).unwrap();
+    /// ```
+    pub fn from_path<P: AsRef<Path>>
+        (path: P,
+         file_info: &FileInfo)
+         -> Result<PulseWavesWriter<BufWriter<fs::File>, BufWriter<fs::File>>> {
+        let pls = try!(fs::File::create(&path));
+        let wvs = try!(fs::File::create(path.as_ref().with_extension("wvs")));
+        PulseWavesWriter::new(BufWriter::new(pls), BufWriter::new(wvs), file_info)
+    }
+}
+
+impl<W: Write + Seek, V: Write + Seek> PulseWavesWriter<W, V> {
+    /// Creates a new writer, and writes the headers and the pulse descriptor.
+    pub fn new(pls: W, wvs: V, file_info: &FileInfo) -> Result<PulseWavesWriter<W, V>> {
+        let mut writer = PulseWavesWriter {
+            pls: pls,
+            wvs: wvs,
+            instrument: file_info.instrument.clone(),
+            serial: file_info.serial.clone(),
+            v_group: file_info.v_group,
+            sampling_time: file_info.sampling_time,
+            wvs_offset: WAVES_HEADER_SIZE as u64,
+            number_of_pulses: 0,
+            min_t: 0,
+            max_t: 0,
+            min: [0.0; 3],
+            max: [0.0; 3],
+        };
+        try!(writer.write_header());
+        try!(writer.write_descriptor());
+        try!(writer.wvs.write_all(b"PulseWavesWaves\0"));
+        try!(writer.wvs.write_u32::<LittleEndian>(0)); // compression
+        try!(writer.wvs.write_all(&[0; WAVES_HEADER_SIZE - 20]));
+        Ok(writer)
+    }
+
+    /// Writes a record as a pulse, and its sample blocks as waves.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use sdf::file::File;
+    /// use sdf::pulsewaves::PulseWavesWriter;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let ref file_info = file.info().unwrap();
+    /// file.reindex().unwrap();
+    /// let ref record = file.read().unwrap();
+    /// let mut writer = PulseWavesWriter::new(Cursor::new(Vec::new()),
+    ///                                        Cursor::new(Vec::new()),
+    ///                                        file_info)
+    ///                      .unwrap();
+    /// writer.write_record(record).unwrap();
+    /// writer.close().unwrap();
+    /// ```
+    pub fn write_record(&mut self, record: &Record) -> Result<()> {
+        let target_distance = self.v_group / 2.0 * self.sampling_time * TARGET_SAMPLES;
+        let mut target = [0.0; 3];
+        for i in 0..3 {
+            target[i] = record.origin[i] + record.direction[i] * target_distance;
+        }
+        let anchor = quantize(&record.origin);
+        let target = quantize(&target);
+        let t = (record.time_external / T_SCALE).round() as i64;
+        if self.number_of_pulses == 0 {
+            self.min_t = t;
+            self.max_t = t;
+            for i in 0..3 {
+                self.min[i] = anchor[i] as f64 * XYZ_SCALE;
+                self.max[i] = anchor[i] as f64 * XYZ_SCALE;
+            }
+        } else {
+            self.min_t = self.min_t.min(t);
+            self.max_t = self.max_t.max(t);
+        }
+        for point in &[anchor, target] {
+            for i in 0..3 {
+                self.min[i] = self.min[i].min(point[i] as f64 * XYZ_SCALE);
+                self.max[i] = self.max[i].max(point[i] as f64 * XYZ_SCALE);
+            }
+        }
+
+        let mut first_returning = None;
+        let mut last_returning = None;
+        let offset = self.wvs_offset;
+        for &channel in &SAMPLINGS {
+            let blocks = record.blocks
+                               .iter()
+                               .filter(|b| b.channel == channel)
+                               .collect::<Vec<_>>();
+            try!(self.wvs.write_u8(blocks.len() as u8));
+            self.wvs_offset += 1;
+            for block in blocks {
+                let duration = (block.time_sosbl - record.time_sorg) / self.sampling_time;
+                try!(self.wvs.write_i32::<LittleEndian>((duration / DURATION_SCALE as f64)
+                                                            .round() as
+                                                        i32));
+                try!(self.wvs.write_u16::<LittleEndian>(block.samples.len() as u16));
+                for &sample in &block.samples {
+                    try!(self.wvs.write_u16::<LittleEndian>(sample));
+                }
+                self.wvs_offset += 6 + 2 * block.samples.len() as u64;
+                if channel != Channel::Reference {
+                    let first = duration;
+                    let last = duration + block.samples.len() as f64 - 1.0;
+                    first_returning = Some(first_returning.map_or(first, |f: f64| f.min(first)));
+                    last_returning = Some(last_returning.map_or(last, |l: f64| l.max(last)));
+                }
+            }
+        }
+
+        try!(self.pls.write_i64::<LittleEndian>(t));
+        try!(self.pls.write_i64::<LittleEndian>(offset as i64));
+        for &value in anchor.iter().chain(target.iter()) {
+            try!(self.pls.write_i32::<LittleEndian>(value));
+        }
+        try!(self.pls.write_i16::<LittleEndian>(clamp_i16(first_returning.unwrap_or(0.0))));
+        try!(self.pls.write_i16::<LittleEndian>(clamp_i16(last_returning.unwrap_or(0.0))));
+        try!(self.pls.write_u16::<LittleEndian>(DESCRIPTOR_INDEX | (record.facet & 0b11) << 14));
+        try!(self.pls.write_u8(0)); // intensity
+        try!(self.pls.write_u8(0)); // classification
+        self.number_of_pulses += 1;
+        Ok(())
+    }
+
+    /// Finalizes the .pls header and returns the underlying writers.
+    pub fn close(mut self) -> Result<(W, V)> {
+        let _ = try!(self.pls.seek(SeekFrom::Start(0)));
+        try!(self.write_header());
+        let _ = try!(self.pls.seek(SeekFrom::End(0)));
+        try!(self.pls.flush());
+        try!(self.wvs.flush());
+        Ok((self.pls, self.wvs))
+    }
+
+    fn write_header(&mut self) -> Result<()> {
+        let (day, year) = creation_date();
+        let offset_to_pulse_data = HEADER_SIZE as i64 + VLR_HEADER_SIZE as i64 +
+                                   descriptor_size() as i64;
+        try!(self.pls.write_all(b"PulseWavesPulse\0"));
+        try!(self.pls.write_u32::<LittleEndian>(0)); // global parameters
+        try!(self.pls.write_u32::<LittleEndian>(0)); // file source id
+        try!(self.pls.write_all(&[0; 16])); // project id
+        try!(write_fixed_str(&mut self.pls,
+                             &format!("{} {}", self.instrument, self.serial),
+                             64));
+        try!(write_fixed_str(&mut self.pls,
+                             &format!("sdf-rs {}", env!("CARGO_PKG_VERSION")),
+                             64));
+        try!(self.pls.write_u16::<LittleEndian>(day));
+        try!(self.pls.write_u16::<LittleEndian>(year));
+        try!(self.pls.write_u8(1));
+        try!(self.pls.write_u8(0));
+        try!(self.pls.write_u16::<LittleEndian>(HEADER_SIZE));
+        try!(self.pls.write_i64::<LittleEndian>(offset_to_pulse_data));
+        try!(self.pls.write_i64::<LittleEndian>(self.number_of_pulses));
+        try!(self.pls.write_u32::<LittleEndian>(0)); // pulse format
+        try!(self.pls.write_u32::<LittleEndian>(0)); // pulse attributes
+        try!(self.pls.write_u32::<LittleEndian>(PULSE_SIZE));
+        try!(self.pls.write_u32::<LittleEndian>(0)); // pulse compression
+        try!(self.pls.write_i64::<LittleEndian>(0)); // reserved
+        try!(self.pls.write_u32::<LittleEndian>(1)); // number of variable length records
+        try!(self.pls.write_i32::<LittleEndian>(0)); // number of appended variable length records
+        try!(self.pls.write_f64::<LittleEndian>(T_SCALE));
+        try!(self.pls.write_f64::<LittleEndian>(0.0));
+        try!(self.pls.write_i64::<LittleEndian>(self.min_t));
+        try!(self.pls.write_i64::<LittleEndian>(self.max_t));
+        for _ in 0..3 {
+            try!(self.pls.write_f64::<LittleEndian>(XYZ_SCALE));
+        }
+        for _ in 0..3 {
+            try!(self.pls.write_f64::<LittleEndian>(0.0));
+        }
+        for i in 0..3 {
+            try!(self.pls.write_f64::<LittleEndian>(self.min[i]));
+            try!(self.pls.write_f64::<LittleEndian>(self.max[i]));
+        }
+        Ok(())
+    }
+
+    fn write_descriptor(&mut self) -> Result<()> {
+        try!(write_fixed_str(&mut self.pls, "PulseWaves_Spec", 16));
+        try!(self.pls.write_u32::<LittleEndian>(DESCRIPTOR_RECORD_ID));
+        try!(self.pls.write_u32::<LittleEndian>(0)); // reserved
+        try!(self.pls.write_i64::<LittleEndian>(descriptor_size() as i64));
+        try!(write_fixed_str(&mut self.pls, "sdf channels", 64));
+
+        try!(self.pls.write_u32::<LittleEndian>(COMPOSITION_SIZE));
+        try!(self.pls.write_u32::<LittleEndian>(0)); // reserved
+        try!(self.pls.write_i32::<LittleEndian>(0)); // optical center to anchor point
+        try!(self.pls.write_u16::<LittleEndian>(0)); // number of extra waves bytes
+        try!(self.pls.write_u16::<LittleEndian>(SAMPLINGS.len() as u16));
+        try!(self.pls.write_f32::<LittleEndian>((self.sampling_time * 1e9) as f32));
+        try!(self.pls.write_u32::<LittleEndian>(0)); // compression
+        try!(self.pls.write_u32::<LittleEndian>(0)); // scanner index
+        for _ in 0..6 {
+            // wavelength, outgoing pulse width, beam diameter and divergence, min and max range
+            try!(self.pls.write_f32::<LittleEndian>(0.0));
+        }
+        try!(write_fixed_str(&mut self.pls, "sdf record", 64));
+
+        for &channel in &SAMPLINGS {
+            try!(self.pls.write_u32::<LittleEndian>(SAMPLING_SIZE));
+            try!(self.pls.write_u32::<LittleEndian>(0)); // reserved
+            try!(self.pls.write_u8(if channel == Channel::Reference {
+                SAMPLING_TYPE_OUTGOING
+            } else {
+                SAMPLING_TYPE_RETURNING
+            }));
+            try!(self.pls.write_u8(channel_number(channel)));
+            try!(self.pls.write_u8(0)); // unused
+            try!(self.pls.write_u8(32)); // bits for duration from anchor
+            try!(self.pls.write_f32::<LittleEndian>(DURATION_SCALE));
+            try!(self.pls.write_f32::<LittleEndian>(0.0)); // offset for duration from anchor
+            try!(self.pls.write_u8(8)); // bits for number of segments
+            try!(self.pls.write_u8(16)); // bits for number of samples
+            try!(self.pls.write_u16::<LittleEndian>(0)); // variable number of segments
+            try!(self.pls.write_u32::<LittleEndian>(0)); // variable number of samples
+            try!(self.pls.write_u16::<LittleEndian>(16)); // bits per sample
+            try!(self.pls.write_u16::<LittleEndian>(0)); // lookup table index
+            try!(self.pls.write_f32::<LittleEndian>((self.sampling_time * 1e9) as f32));
+            try!(self.pls.write_u32::<LittleEndian>(0)); // compression
+            try!(write_fixed_str(&mut self.pls, &format!("{} channel", channel), 64));
+        }
+        Ok(())
+    }
+}
+
+fn quantize(coordinates: &[f64; 3]) -> [i32; 3] {
+    [(coordinates[0] / XYZ_SCALE).round() as i32,
+     (coordinates[1] / XYZ_SCALE).round() as i32,
+     (coordinates[2] / XYZ_SCALE).round() as i32]
+}
+
+fn descriptor_size() -> u32 {
+    COMPOSITION_SIZE + SAMPLINGS.len() as u32 * SAMPLING_SIZE
+}
+
+fn channel_number(channel: Channel) -> u8 {
+    match channel {
+        Channel::High => 0,
+        Channel::Low => 1,
+        Channel::Saturation => 2,
+        Channel::Reference => 3,
+    }
+}
+
+fn clamp_i16(value: f64) -> i16 {
+    value.round().max(i16::min_value() as f64).min(i16::max_value() as f64) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::{Cursor, Read};
+
+    use byteorder::{LittleEndian, ReadBytesExt};
+
+    use backend::{test_info, test_record};
+    use file::{Channel, Record};
+
+    fn record() -> Record {
+        let mut record = test_record(1.0);
+        record.time_external = 409397.9;
+        record.facet = 2;
+        record.blocks[0].samples = vec![1, 2, 3];
+        record.blocks[1].channel = Channel::Low;
+        record.blocks[1].samples = vec![4, 5];
+        record
+    }
+
+    #[test]
+    fn pulse_and_waves() {
+        let mut writer = PulseWavesWriter::new(Cursor::new(Vec::new()),
+                                               Cursor::new(Vec::new()),
+                                               &test_info())
+                             .unwrap();
+        writer.write_record(&record()).unwrap();
+        let (mut pls, mut wvs) = writer.close().unwrap();
+
+        let mut signature = [0; 16];
+        pls.set_position(0);
+        pls.read_exact(&mut signature).unwrap();
+        assert_eq!(b"PulseWavesPulse\0", &signature);
+        pls.set_position(176);
+        let offset_to_pulse_data = pls.read_i64::<LittleEndian>().unwrap();
+        assert_eq!(352 + 96 + 116 + 4 * 104, offset_to_pulse_data);
+        assert_eq!(1, pls.read_i64::<LittleEndian>().unwrap());
+        assert_eq!(offset_to_pulse_data as usize + 48, pls.get_ref().len());
+
+        pls.set_position(offset_to_pulse_data as u64);
+        assert_eq!(409397900000, pls.read_i64::<LittleEndian>().unwrap());
+        let offset = pls.read_i64::<LittleEndian>().unwrap();
+        assert_eq!(60, offset);
+        for _ in 0..3 {
+            assert_eq!(0, pls.read_i32::<LittleEndian>().unwrap());
+        }
+        assert_eq!(149854, pls.read_i32::<LittleEndian>().unwrap());
+        pls.set_position(offset_to_pulse_data as u64 + 40);
+        assert_eq!(1000, pls.read_i16::<LittleEndian>().unwrap());
+        assert_eq!(1001, pls.read_i16::<LittleEndian>().unwrap());
+        assert_eq!(1 | 2 << 14, pls.read_u16::<LittleEndian>().unwrap());
+
+        wvs.set_position(offset as u64);
+        assert_eq!(1, wvs.read_u8().unwrap()); // reference
+        assert_eq!(0, wvs.read_i32::<LittleEndian>().unwrap());
+        assert_eq!(3, wvs.read_u16::<LittleEndian>().unwrap());
+        for sample in 1..4 {
+            assert_eq!(sample, wvs.read_u16::<LittleEndian>().unwrap());
+        }
+        assert_eq!(0, wvs.read_u8().unwrap()); // high
+        assert_eq!(1, wvs.read_u8().unwrap()); // low
+        assert_eq!(1000000, wvs.read_i32::<LittleEndian>().unwrap());
+        assert_eq!(2, wvs.read_u16::<LittleEndian>().unwrap());
+        wvs.set_position(wvs.position() + 4);
+        assert_eq!(0, wvs.read_u8().unwrap()); // saturation
+        assert_eq!(wvs.get_ref().len() as u64, wvs.position());
+    }
+}
@@ -0,0 +1,355 @@
+//! Read and write Riegl's .sdc discrete-return files.
+//!
+//! An .sdc file is a short header followed by fixed-size point records, all little-endian. The
+//! header is the header size in bytes (`u32`) and the major and minor version (`u16` each). We
+//! write version 5.0 files, where each point record is:
+//!
+//! | Field | Type |
+//! | ----- | ---- |
+//! | time | `f64` |
+//! | range | `f32` |
+//! | theta | `f32` |
+//! | x, y, z | `f32` |
+//! | amplitude | `u16` |
+//! | width | `u16` |
+//! | target type | `u8` |
+//! | target | `u8` |
+//! | number of targets | `u8` |
+//! | range gate index | `u16` |
+//! | channel descriptor | `u8` |
+//! | class id | `u8` |
+//!
+//! The channel descriptor holds the facet number in its two lowest bits, and bit six is set if the
+//! point came from the high channel.
+
+use std::fs;
+use std::io::{self, BufReader, BufWriter, Cursor, ErrorKind, Read, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+
+use Result;
+use convert;
+use error::Error;
+
+const HEADER_SIZE: u32 = 8;
+const MAJOR_VERSION: u16 = 5;
+const MINOR_VERSION: u16 = 0;
+const POINT_SIZE: usize = 39;
+const HIGH_CHANNEL_BIT: u8 = 0b0100_0000;
+const FACET_MASK: u8 = 0b0000_0011;
+
+/// A discrete-return point as stored in an .sdc file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    /// The time that this point was collected, in seconds.
+    pub time: f64,
+    /// The raw range to the point, in meters.
+    pub range: f32,
+    /// The mirror scan angle in degrees.
+    pub theta: f32,
+    /// The x coordinate of the point in the scanner's own coordinate system.
+    pub x: f32,
+    /// The y coordinate of the point in the scanner's own coordinate system.
+    pub y: f32,
+    /// The z coordinate of the point in the scanner's own coordinate system.
+    pub z: f32,
+    /// The amplitude of the return, in digitizer units.
+    pub amplitude: u16,
+    /// The width of the return, in units of the sampling interval.
+    pub width: u16,
+    /// The target type, as defined by Riegl.
+    ///
+    /// Points discretized by this crate use zero.
+    pub target_type: u8,
+    /// The target number (1-indexed).
+    pub target: u8,
+    /// The total number of targets in this pulse.
+    pub num_target: u8,
+    /// The range gate index.
+    pub rg_index: u16,
+    /// The mirror facet used to reflect the laser energy.
+    pub facet: u8,
+    /// Was this point collected on the high channel?
+    pub high_channel: bool,
+    /// The point's class id.
+    pub class_id: u8,
+}
+
+impl<'a> From<&'a convert::Point> for Point {
+    fn from(point: &'a convert::Point) -> Point {
+        Point {
+            time: point.time,
+            range: point.range,
+            theta: point.theta,
+            x: point.x,
+            y: point.y,
+            z: point.z,
+            amplitude: point.peak.amplitude,
+            width: point.echo.map_or(0, |echo| echo.fwhm().round() as u16),
+            target_type: 0,
+            target: point.target,
+            num_target: point.num_target,
+            rg_index: 0,
+            facet: point.facet as u8 & FACET_MASK,
+            high_channel: point.high_channel,
+            class_id: 0,
+        }
+    }
+}
+
+/// Writes points to an .sdc file.
+#[derive(Debug)]
+pub struct SdcWriter<W: Write> {
+    writer: W,
+}
+
+impl SdcWriter<BufWriter<fs::File>> {
+    /// Creates a new .sdc file at the given path.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use sdf::sdc::SdcWriter;
+    /// let writer = SdcWriter::from_path("/dev/null").unwrap();
+    /// ```
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<SdcWriter<BufWriter<fs::File>>> {
+        let file = try!(fs::File::create(path));
+        SdcWriter::new(BufWriter::new(file))
+    }
+}
+
+impl<W: Write> SdcWriter<W> {
+    /// Creates a new writer and writes the .sdc header.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use sdf::sdc::SdcWriter;
+    /// let writer = SdcWriter::new(Cursor::new(Vec::new())).unwrap();
+    /// ```
+    pub fn new(mut writer: W) -> Result<SdcWriter<W>> {
+        try!(writer.write_u32::<LittleEndian>(HEADER_SIZE));
+        try!(writer.write_u16::<LittleEndian>(MAJOR_VERSION));
+        try!(writer.write_u16::<LittleEndian>(MINOR_VERSION));
+        Ok(SdcWriter { writer: writer })
+    }
+
+    /// Writes a point.
+    ///
+    /// Discretized points from `convert::discretize` can be written directly.
+    ///
+    /// # Examples
+    ///
+    /// ```
+    /// use std::io::Cursor;
+    /// use sdf::convert::discretize;
+    /// use sdf::file::File;
+    /// use sdf::sdc::SdcWriter;
+    /// let mut file = File::open("data/110630_174316.sdf").unwrap();
+    /// let ref file_info = file.info().unwrap();
+    /// file.reindex().unwrap();
+    /// let ref record = file.read().unwrap();
+    /// let mut writer = SdcWriter::new(Cursor::new(Vec::new())).unwrap();
+    /// for ref point in discretize(record, file_info).unwrap() {
+    ///     writer.write_point(point).unwrap();
+    /// }
+    /// ```
+    pub fn write_point<P: Into<Point>>(&mut self, point: P) -> Result<()> {
+        let point = point.into();
+        let mut channel_desc = point.facet & FACET_MASK;
+        if point.high_channel {
+            channel_desc |= HIGH_CHANNEL_BIT;
+        }
+        try!(self.writer.write_f64::<LittleEndian>(point.time));
+        try!(self.writer.write_f32::<LittleEndian>(point.range));
+        try!(self.writer.write_f32::<LittleEndian>(point.theta));
+        try!(self.writer.write_f32::<LittleEndian>(point.x));
+        try!(self.writer.write_f32::<LittleEndian>(point.y));
+        try!(self.writer.write_f32::<LittleEndian>(point.z));
+        try!(self.writer.write_u16::<LittleEndian>(point.amplitude));
+        try!(self.writer.write_u16::<LittleEndian>(point.width));
+        try!(self.writer.write_u8(point.target_type));
+        try!(self.writer.write_u8(point.target));
+        try!(self.writer.write_u8(point.num_target));
+        try!(self.writer.write_u16::<LittleEndian>(point.rg_index));
+        try!(self.writer.write_u8(channel_desc));
+        try!(self.writer.write_u8(point.class_id));
+        Ok(())
+    }
+
+    /// Flushes and returns the underlying writer.
+    pub fn into_inner(mut self) -> Result<W> {
+        try!(self.writer.flush());
+        Ok(self.writer)
+    }
+}
+
+/// Reads points from an .sdc file.
+#[derive(Debug)]
+pub struct SdcReader<R: Read> {
+    reader: R,
+    major: u16,
+    minor: u16,
+}
+
+impl SdcReader<BufReader<fs::File>> {
+    /// Opens an .sdc file for reading.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<SdcReader<BufReader<fs::File>>> {
+        let file = try!(fs::File::open(path));
+        SdcReader::new(BufReader::new(file))
+    }
+}
+
+impl<R: Read> SdcReader<R> {
+    /// Creates a new reader and reads the .sdc header.
+    ///
+    /// Only version 5.0 files are supported.
+    pub fn new(mut reader: R) -> Result<SdcReader<R>> {
+        let header_size = try!(reader.read_u32::<LittleEndian>());
+        let major = try!(reader.read_u16::<LittleEndian>());
+        let minor = try!(reader.read_u16::<LittleEndian>());
+        if header_size < HEADER_SIZE || major != MAJOR_VERSION || minor != MINOR_VERSION {
+            return Err(Error::UnsupportedFormat(format!("sdc version {}.{} with header size {}",
+                                                        major,
+                                                        minor,
+                                                        header_size)));
+        }
+        let _ = try!(io::copy(&mut (&mut reader).take((header_size - HEADER_SIZE) as u64),
+                              &mut io::sink()));
+        Ok(SdcReader {
+            reader: reader,
+            major: major,
+            minor: minor,
+        })
+    }
+
+    /// Returns the major and minor version of this file.
+    pub fn version(&self) -> (u16, u16) {
+        (self.major, self.minor)
+    }
+
+    /// Reads the next point, or returns `None` at the end of the file.
+    pub fn read_point(&mut self) -> Result<Option<Point>> {
+        let mut buf = [0u8; POINT_SIZE];
+        let mut filled = 0;
+        while filled < POINT_SIZE {
+            match self.reader.read(&mut buf[filled..]) {
+                Ok(0) => break,
+                Ok(n) => filled += n,
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => {}
+                Err(err) => return Err(Error::from(err)),
+            }
+        }
+        if filled == 0 {
+            return Ok(None);
+        } else if filled < POINT_SIZE {
+            return Err(Error::UnsupportedFormat(format!("Truncated sdc point record ({} of {} \
+                                                         bytes)",
+                                                        filled,
+                                                        POINT_SIZE)));
+        }
+        let mut cursor = Cursor::new(&buf[..]);
+        let time = try!(cursor.read_f64::<LittleEndian>());
+        let range = try!(cursor.read_f32::<LittleEndian>());
+        let theta = try!(cursor.read_f32::<LittleEndian>());
+        let x = try!(cursor.read_f32::<LittleEndian>());
+        let y = try!(cursor.read_f32::<LittleEndian>());
+        let z = try!(cursor.read_f32::<LittleEndian>());
+        let amplitude = try!(cursor.read_u16::<LittleEndian>());
+        let width = try!(cursor.read_u16::<LittleEndian>());
+        let target_type = try!(cursor.read_u8());
+        let target = try!(cursor.read_u8());
+        let num_target = try!(cursor.read_u8());
+        let rg_index = try!(cursor.read_u16::<LittleEndian>());
+        let channel_desc = try!(cursor.read_u8());
+        let class_id = try!(cursor.read_u8());
+        Ok(Some(Point {
+            time: time,
+            range: range,
+            theta: theta,
+            x: x,
+            y: y,
+            z: z,
+            amplitude: amplitude,
+            width: width,
+            target_type: target_type,
+            target: target,
+            num_target: num_target,
+            rg_index: rg_index,
+            facet: channel_desc & FACET_MASK,
+            high_channel: channel_desc & HIGH_CHANNEL_BIT == HIGH_CHANNEL_BIT,
+            class_id: class_id,
+        }))
+    }
+}
+
+impl<R: Read> Iterator for SdcReader<R> {
+    type Item = Result<Point>;
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_point() {
+            Ok(Some(point)) => Some(Ok(point)),
+            Ok(None) => None,
+            Err(err) => Some(Err(err)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    fn point(target: u8, high_channel: bool) -> Point {
+        Point {
+            time: 409397.90336020273,
+            range: 1234.5,
+            theta: -12.25,
+            x: 1.0,
+            y: 2.0,
+            z: 3.0,
+            amplitude: 200,
+            width: 4,
+            target_type: 0,
+            target: target,
+            num_target: 2,
+            rg_index: 0,
+            facet: 3,
+            high_channel: high_channel,
+            class_id: 0,
+        }
+    }
+
+    #[test]
+    fn roundtrip() {
+        let mut writer = SdcWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.write_point(point(1, true)).unwrap();
+        writer.write_point(point(2, false)).unwrap();
+        let bytes = writer.into_inner().unwrap().into_inner();
+        assert_eq!(8 + 2 * POINT_SIZE, bytes.len());
+
+        let reader = SdcReader::new(Cursor::new(bytes)).unwrap();
+        assert_eq!((5, 0), reader.version());
+        let points = reader.collect::<Result<Vec<_>>>().unwrap();
+        assert_eq!(vec![point(1, true), point(2, false)], points);
+    }
+
+    #[test]
+    fn truncated_point() {
+        let mut writer = SdcWriter::new(Cursor::new(Vec::new())).unwrap();
+        writer.write_point(point(1, true)).unwrap();
+        let mut bytes = writer.into_inner().unwrap().into_inner();
+        let len = bytes.len();
+        bytes.truncate(len - 1);
+        let mut reader = SdcReader::new(Cursor::new(bytes)).unwrap();
+        assert!(reader.read_point().is_err());
+    }
+
+    #[test]
+    fn unsupported_version() {
+        let bytes = vec![8, 0, 0, 0, 4, 0, 0, 0];
+        assert!(SdcReader::new(Cursor::new(bytes)).is_err());
+    }
+}
@@ -0,0 +1,330 @@
+//! Statistics over all of a file's records.
+//!
+//! `Stats` is built up one record at a time, so it can be fed from any iteration over a `File`.
+//! All of its fields are plain data, so it can be printed or encoded as-is.
+
+use std::collections::BTreeMap;
+use std::fmt;
+
+use Result;
+use backend::Backend;
+use error::Error;
+use file::{Channel, File, Record};
+
+/// The default width of the pulse rate bins, in seconds.
+pub const DEFAULT_BIN_WIDTH: f64 = 1.0;
+
+/// Statistics about a file's records.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::file::File;
+/// use sdf::stats::{DEFAULT_BIN_WIDTH, Stats};
+/// let mut file = File::open("data/110630_174316.sdf").unwrap();
+/// let stats = Stats::from_file(&mut file, DEFAULT_BIN_WIDTH).unwrap();
+/// println!("{}", stats);
+/// ```
+#[derive(Clone, Debug, PartialEq, RustcEncodable)]
+pub struct Stats {
+    /// The number of records that were read.
+    pub record_count: u64,
+    /// The number of records that couldn't be read.
+    pub unreadable_records: u64,
+    /// The number of housekeeping records.
+    pub housekeeping_records: u64,
+    /// The number of records with at least one saturation block.
+    pub saturated_records: u64,
+    /// The number of records for each facet.
+    pub facets: BTreeMap<u16, u64>,
+    /// Block and sample statistics for each channel, keyed by channel name.
+    pub channels: BTreeMap<String, ChannelStats>,
+    /// Runs of records that weren't GPS-synchronized, in record order.
+    pub sync_losses: Vec<SyncLoss>,
+    /// The width of the pulse rate bins, in seconds.
+    pub bin_width: f64,
+    /// The number of records in each pulse rate bin, in time order.
+    pub pulse_rate: Vec<RateBin>,
+}
+
+/// Statistics about the blocks from one channel.
+#[derive(Clone, Debug, Default, PartialEq, RustcEncodable)]
+pub struct ChannelStats {
+    /// The number of blocks.
+    pub blocks: u64,
+    /// The total number of samples.
+    pub samples: u64,
+    /// The number of blocks with each number of samples.
+    pub sample_counts: BTreeMap<usize, u64>,
+    /// The smallest sample.
+    pub min_amplitude: Option<u16>,
+    /// The largest sample.
+    pub max_amplitude: Option<u16>,
+    /// The mean of all samples.
+    pub mean_amplitude: f64,
+}
+
+/// A run of consecutive records that weren't GPS-synchronized.
+///
+/// A record counts as unsynchronized if either `synchronized` or `sync_lastsec` is false.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable)]
+pub struct SyncLoss {
+    /// The one-based index of the first record in the run.
+    pub start_index: u64,
+    /// The one-based index of the last record in the run.
+    pub end_index: u64,
+    /// The internal time of the first record in the run.
+    pub start_time: f64,
+    /// The internal time of the last record in the run.
+    pub end_time: f64,
+}
+
+/// The number of records in one slice of internal time.
+#[derive(Clone, Copy, Debug, PartialEq, RustcEncodable)]
+pub struct RateBin {
+    /// The start of the bin, in seconds of internal time.
+    pub start: f64,
+    /// The number of records in the bin.
+    pub records: u64,
+}
+
+impl Stats {
+    /// Creates empty statistics, with pulse rate bins of the given width in seconds.
+    ///
+    /// Returns `Error::InvalidOptions` unless the width is positive and finite.
+    pub fn new(bin_width: f64) -> Result<Stats> {
+        if !(bin_width > 0.0 && bin_width.is_finite()) {
+            return Err(Error::InvalidOptions(format!("The pulse rate bin width must be a \
+                                                      positive number of seconds, not {}",
+                                                     bin_width)));
+        }
+        Ok(Stats {
+            record_count: 0,
+            unreadable_records: 0,
+            housekeeping_records: 0,
+            saturated_records: 0,
+            facets: BTreeMap::new(),
+            channels: BTreeMap::new(),
+            sync_losses: Vec::new(),
+            bin_width: bin_width,
+            pulse_rate: Vec::new(),
+        })
+    }
+
+    /// Scans all of a file's records, from the first one.
+    ///
+    /// Records that can't be read are counted and skipped.
+    pub fn from_file<B: Backend>(file: &mut File<B>, bin_width: f64) -> Result<Stats> {
+        let mut stats = try!(Stats::new(bin_width));
+        try!(file.reindex());
+        try!(file.seek(1));
+        for result in try!(file.records()) {
+            match result {
+                Ok(record) => stats.add(&record),
+                Err(err) => {
+                    warn!("{}", err);
+                    stats.add_unreadable();
+                }
+            }
+        }
+        Ok(stats)
+    }
+
+    /// Adds the next record.
+    pub fn add(&mut self, record: &Record) {
+        let index = self.records_seen() + 1;
+        self.record_count += 1;
+        if record.housekeeping {
+            self.housekeeping_records += 1;
+        }
+        if record.blocks.iter().any(|b| b.channel == Channel::Saturation) {
+            self.saturated_records += 1;
+        }
+        *self.facets.entry(record.facet).or_insert(0) += 1;
+        for block in &record.blocks {
+            self.channels
+                .entry(block.channel.to_string())
+                .or_insert_with(ChannelStats::default)
+                .add(&block.samples);
+        }
+        if !record.synchronized || !record.sync_lastsec {
+            let continued = self.sync_losses.last().map_or(false, |l| l.end_index + 1 == index);
+            if continued {
+                let loss = self.sync_losses.last_mut().expect("there is a sync loss");
+                loss.end_index = index;
+                loss.end_time = record.time_sorg;
+            } else {
+                self.sync_losses.push(SyncLoss {
+                    start_index: index,
+                    end_index: index,
+                    start_time: record.time_sorg,
+                    end_time: record.time_sorg,
+                });
+            }
+        }
+        let start = (record.time_sorg / self.bin_width).floor() * self.bin_width;
+        if self.pulse_rate.last().map_or(false, |b| b.start == start) {
+            self.pulse_rate.last_mut().expect("there is a bin").records += 1;
+        } else {
+            self.pulse_rate.push(RateBin {
+                start: start,
+                records: 1,
+            });
+        }
+    }
+
+    /// Counts a record that couldn't be read.
+    pub fn add_unreadable(&mut self) {
+        self.unreadable_records += 1;
+    }
+
+    fn records_seen(&self) -> u64 {
+        self.record_count + self.unreadable_records
+    }
+}
+
+impl ChannelStats {
+    fn add(&mut self, samples: &[u16]) {
+        self.blocks += 1;
+        *self.sample_counts.entry(samples.len()).or_insert(0) += 1;
+        if samples.is_empty() {
+            return;
+        }
+        let sum = samples.iter().fold(0u64, |sum, &s| sum + s as u64);
+        let min = samples.iter().cloned().min();
+        let max = samples.iter().cloned().max();
+        self.min_amplitude = self.min_amplitude.into_iter().chain(min).min();
+        self.max_amplitude = self.max_amplitude.into_iter().chain(max).max();
+        let n = self.samples + samples.len() as u64;
+        self.mean_amplitude = (self.mean_amplitude * self.samples as f64 + sum as f64) / n as f64;
+        self.samples = n;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(writeln!(f, "             records: {}", self.record_count));
+        try!(writeln!(f, "  unreadable records: {}", self.unreadable_records));
+        try!(writeln!(f, "housekeeping records: {}", self.housekeeping_records));
+        try!(writeln!(f, "   saturated records: {}", self.saturated_records));
+        try!(writeln!(f, "\nFacets"));
+        for (facet, count) in &self.facets {
+            try!(writeln!(f, "  {}: {}", facet, count));
+        }
+        for (name, channel) in &self.channels {
+            try!(writeln!(f, "\nChannel {}", name));
+            try!(writeln!(f, "          blocks: {}", channel.blocks));
+            try!(writeln!(f, "         samples: {}", channel.samples));
+            try!(writeln!(f,
+                          "   min amplitude: {}",
+                          channel.min_amplitude.map_or("-".to_string(), |a| a.to_string())));
+            try!(writeln!(f,
+                          "   max amplitude: {}",
+                          channel.max_amplitude.map_or("-".to_string(), |a| a.to_string())));
+            try!(writeln!(f, "  mean amplitude: {:.2}", channel.mean_amplitude));
+            try!(writeln!(f, "  samples per block (samples: blocks)"));
+            for (samples, blocks) in &channel.sample_counts {
+                try!(writeln!(f, "    {}: {}", samples, blocks));
+            }
+        }
+        try!(writeln!(f, "\nGPS sync losses (records, internal times)"));
+        for loss in &self.sync_losses {
+            try!(writeln!(f,
+                          "  {}-{}: {}-{}",
+                          loss.start_index,
+                          loss.end_index,
+                          loss.start_time,
+                          loss.end_time));
+        }
+        try!(write!(f, "\nPulse rate ({} second bins, records per second)", self.bin_width));
+        for bin in &self.pulse_rate {
+            try!(write!(f, "\n  {}: {}", bin.start, bin.records as f64 / self.bin_width));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f64;
+
+    use rustc_serialize::json;
+
+    use backend::{Memory, test_info, test_record};
+    use error::Error;
+    use file::{Block, Channel, File, Record};
+
+    fn record(i: usize) -> Record {
+        let mut record = test_record(i as f64 * 0.4);
+        record.synchronized = i != 1 && i != 2 && i != 4;
+        record.housekeeping = i == 0;
+        record.facet = (i % 2) as u16;
+        record.blocks[1].samples = vec![i as u16, 10];
+        if i == 2 {
+            record.blocks.push(Block {
+                time_sosbl: 0.0,
+                channel: Channel::Saturation,
+                samples: vec![4, 4, 4],
+            });
+        }
+        record
+    }
+
+    fn file() -> File<Memory> {
+        File::new(Memory::new(test_info(), (0..6).map(record).collect()))
+    }
+
+    #[test]
+    fn from_file() {
+        let stats = Stats::from_file(&mut file(), 1.0).unwrap();
+        assert_eq!(6, stats.record_count);
+        assert_eq!(1, stats.housekeeping_records);
+        assert_eq!(1, stats.saturated_records);
+        assert_eq!(Some(&3), stats.facets.get(&0));
+        assert_eq!(Some(&3), stats.facets.get(&1));
+        let high = &stats.channels["high"];
+        assert_eq!(6, high.blocks);
+        assert_eq!(12, high.samples);
+        assert_eq!(Some(&6), high.sample_counts.get(&2));
+        assert_eq!(Some(0), high.min_amplitude);
+        assert_eq!(Some(10), high.max_amplitude);
+        assert!((high.mean_amplitude - 75.0 / 12.0).abs() < 1e-9);
+        assert_eq!(3, stats.channels["saturation"].samples);
+    }
+
+    #[test]
+    fn sync_losses() {
+        let stats = Stats::from_file(&mut file(), 1.0).unwrap();
+        assert_eq!(2, stats.sync_losses.len());
+        assert_eq!((2, 3), (stats.sync_losses[0].start_index, stats.sync_losses[0].end_index));
+        assert_eq!((5, 5), (stats.sync_losses[1].start_index, stats.sync_losses[1].end_index));
+        assert_eq!(1.6, stats.sync_losses[1].start_time);
+    }
+
+    #[test]
+    fn pulse_rate() {
+        let stats = Stats::from_file(&mut file(), 1.0).unwrap();
+        let bins = stats.pulse_rate.iter().map(|b| (b.start, b.records)).collect::<Vec<_>>();
+        assert_eq!(vec![(0.0, 3), (1.0, 2), (2.0, 1)], bins);
+    }
+
+    #[test]
+    fn bad_bin_width() {
+        for &width in &[0.0, -1.0, f64::NAN, f64::INFINITY] {
+            match Stats::from_file(&mut file(), width) {
+                Err(Error::InvalidOptions(_)) => {}
+                result => panic!("Expected invalid options for {}, got {:?}", width, result),
+            }
+        }
+    }
+
+    #[test]
+    fn encode() {
+        let stats = Stats::from_file(&mut file(), 1.0).unwrap();
+        let encoded = json::encode(&stats).unwrap();
+        assert!(encoded.contains("\"record_count\":6"));
+        let _ = json::Json::from_str(&encoded).unwrap();
+    }
+}
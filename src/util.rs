@@ -0,0 +1,39 @@
+//! Helpers shared by the file format writers.
+
+use std::io::Write;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use byteorder::WriteBytesExt;
+
+use Result;
+
+/// Writes a string into a fixed-width, nul-padded field, truncating if necessary.
+pub fn write_fixed_str<W: Write>(writer: &mut W, s: &str, len: usize) -> Result<()> {
+    let bytes = s.as_bytes();
+    let n = bytes.len().min(len);
+    try!(writer.write_all(&bytes[..n]));
+    for _ in n..len {
+        try!(writer.write_u8(0));
+    }
+    Ok(())
+}
+
+/// Returns today's (day of year, year), in UTC.
+pub fn creation_date() -> (u16, u16) {
+    let days = SystemTime::now()
+                   .duration_since(UNIX_EPOCH)
+                   .map(|d| d.as_secs() / 86400)
+                   .unwrap_or(0);
+    let mut year = 1970;
+    let mut remaining = days;
+    loop {
+        let leap = (year % 4 == 0 && year % 100 != 0) || year % 400 == 0;
+        let days_in_year = if leap { 366 } else { 365 };
+        if remaining < days_in_year {
+            break;
+        }
+        remaining -= days_in_year;
+        year += 1;
+    }
+    (remaining as u16 + 1, year as u16)
+}
@@ -0,0 +1,225 @@
+//! Export raw waveforms for analysis in Python.
+//!
+//! `Waveforms` collects records' sample blocks and writes them to an .npz archive, which
+//! `numpy.load` reads as a dictionary of arrays. There is one row per record in:
+//!
+//! | Array | Type | Shape |
+//! | ----- | ---- | ----- |
+//! | `index` | `uint32` | `(records,)` |
+//! | `time_sorg` | `float64` | `(records,)` |
+//! | `time_external` | `float64` | `(records,)` |
+//! | `origin` | `float64` | `(records, 3)` |
+//! | `direction` | `float64` | `(records, 3)` |
+//! | `facet` | `uint16` | `(records,)` |
+//!
+//! `index` is the one-based record index in the .sdf file. Each channel with any blocks gets
+//! arrays prefixed with its name, e.g. `high_samples`, with one row per block:
+//!
+//! | Array | Type | Shape |
+//! | ----- | ---- | ----- |
+//! | `<channel>_samples` | `uint16` | `(blocks, max samples)` |
+//! | `<channel>_length` | `uint32` | `(blocks,)` |
+//! | `<channel>_time_sosbl` | `float64` | `(blocks,)` |
+//! | `<channel>_record` | `uint32` | `(blocks,)` |
+//!
+//! Blocks shorter than the longest one are padded with zeros, and `<channel>_length` says how
+//! many samples are real. `<channel>_record` is the block's record's row in the per-record arrays,
+//! since a record can have any number of blocks from a channel.
+
+use std::collections::BTreeMap;
+use std::io::Write;
+use std::path::Path;
+
+use Result;
+use file::Record;
+use npy::NpzWriter;
+
+/// Raw waveforms from a set of records.
+///
+/// Everything is held in memory until it's written, so select records with care.
+///
+/// # Examples
+///
+/// ```
+/// use sdf::file::File;
+/// use sdf::waveforms::Waveforms;
+/// let mut file = File::open("data/110630_174316.sdf").unwrap();
+/// let mut waveforms = Waveforms::new();
+/// let mut records = file.records_in_range(1..11).unwrap();
+/// while let Some(result) = records.next() {
+///     waveforms.add(records.next_index() - 1, &result.unwrap());
+/// }
+/// waveforms.write_to_path("/tmp/110630_174316.npz").unwrap();
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct Waveforms {
+    index: Vec<u32>,
+    time_sorg: Vec<f64>,
+    time_external: Vec<f64>,
+    origin: Vec<f64>,
+    direction: Vec<f64>,
+    facet: Vec<u16>,
+    channels: BTreeMap<String, ChannelWaveforms>,
+}
+
+#[derive(Clone, Debug, Default)]
+struct ChannelWaveforms {
+    samples: Vec<Vec<u16>>,
+    time_sosbl: Vec<f64>,
+    record: Vec<u32>,
+}
+
+impl Waveforms {
+    /// Creates an empty set of waveforms.
+    pub fn new() -> Waveforms {
+        Waveforms::default()
+    }
+
+    /// Adds a record, given its one-based index in the file.
+    pub fn add(&mut self, index: u32, record: &Record) {
+        let row = self.index.len() as u32;
+        self.index.push(index);
+        self.time_sorg.push(record.time_sorg);
+        self.time_external.push(record.time_external);
+        self.origin.extend_from_slice(&record.origin);
+        self.direction.extend_from_slice(&record.direction);
+        self.facet.push(record.facet);
+        for block in &record.blocks {
+            let channel = self.channels
+                              .entry(block.channel.to_string())
+                              .or_insert_with(ChannelWaveforms::default);
+            channel.samples.push(block.samples.clone());
+            channel.time_sosbl.push(block.time_sosbl);
+            channel.record.push(row);
+        }
+    }
+
+    /// Returns the number of records.
+    pub fn len(&self) -> usize {
+        self.index.len()
+    }
+
+    /// Returns true if there are no records.
+    pub fn is_empty(&self) -> bool {
+        self.index.is_empty()
+    }
+
+    /// Writes the waveforms to an .npz archive at the given path.
+    pub fn write_to_path<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        self.write(try!(NpzWriter::from_path(path))).map(|_| ())
+    }
+
+    /// Writes the waveforms to an .npz archive, and returns the underlying writer.
+    pub fn write<W: Write>(&self, mut writer: NpzWriter<W>) -> Result<W> {
+        let n = self.len();
+        try!(writer.write_array("index", &[n], &self.index));
+        try!(writer.write_array("time_sorg", &[n], &self.time_sorg));
+        try!(writer.write_array("time_external", &[n], &self.time_external));
+        try!(writer.write_array("origin", &[n, 3], &self.origin));
+        try!(writer.write_array("direction", &[n, 3], &self.direction));
+        try!(writer.write_array("facet", &[n], &self.facet));
+        for (name, channel) in &self.channels {
+            let blocks = channel.samples.len();
+            let width = channel.samples.iter().map(|s| s.len()).max().unwrap_or(0);
+            let mut samples = Vec::with_capacity(blocks * width);
+            for block in &channel.samples {
+                samples.extend_from_slice(block);
+                samples.extend((block.len()..width).map(|_| 0));
+            }
+            let lengths = channel.samples.iter().map(|s| s.len() as u32).collect::<Vec<_>>();
+            try!(writer.write_array(&format!("{}_samples", name), &[blocks, width], &samples));
+            try!(writer.write_array(&format!("{}_length", name), &[blocks], &lengths));
+            try!(writer.write_array(&format!("{}_time_sosbl", name),
+                                    &[blocks],
+                                    &channel.time_sosbl));
+            try!(writer.write_array(&format!("{}_record", name), &[blocks], &channel.record));
+        }
+        writer.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::io::Cursor;
+
+    use byteorder::{LittleEndian, ReadBytesExt};
+
+    use backend::test_record;
+    use file::{Block, Channel, Record};
+    use npy::NpzWriter;
+
+    fn record(i: usize, high: Vec<Vec<u16>>) -> Record {
+        let mut record = test_record(i as f64);
+        record.time_external = 100.0 + i as f64;
+        record.origin = [0.0, 1.0, 2.0];
+        record.facet = i as u16;
+        record.blocks.truncate(1);
+        record.blocks.extend(high.into_iter().map(|samples| {
+            Block {
+                time_sosbl: i as f64 + 1e-6,
+                channel: Channel::High,
+                samples: samples,
+            }
+        }));
+        record
+    }
+
+    /// Walks the archive's local file headers, and returns the array names.
+    fn names(bytes: &[u8]) -> Vec<String> {
+        let mut names = Vec::new();
+        let mut position = 0;
+        let mut cursor = Cursor::new(bytes);
+        while cursor.read_u32::<LittleEndian>().unwrap() == 0x04034b50 {
+            cursor.set_position(position + 18);
+            let size = cursor.read_u32::<LittleEndian>().unwrap() as u64;
+            cursor.set_position(position + 26);
+            let name_len = cursor.read_u16::<LittleEndian>().unwrap() as u64;
+            let name = &bytes[(position + 30) as usize..(position + 30 + name_len) as usize];
+            names.push(String::from_utf8(name.to_vec()).unwrap().replace(".npy", ""));
+            position += 30 + name_len + size;
+            cursor.set_position(position);
+        }
+        names
+    }
+
+    #[test]
+    fn arrays() {
+        let mut waveforms = Waveforms::new();
+        waveforms.add(3, &record(0, vec![vec![1, 2, 3]]));
+        waveforms.add(4, &record(1, vec![vec![4], vec![5, 6]]));
+        assert_eq!(2, waveforms.len());
+        let channel = &waveforms.channels["high"];
+        assert_eq!(vec![0, 1, 1], channel.record);
+        assert_eq!(vec![0.0, 1.0, 2.0, 0.0, 1.0, 2.0], waveforms.origin);
+        let bytes = waveforms.write(NpzWriter::new(Cursor::new(Vec::new())))
+                             .unwrap()
+                             .into_inner();
+        assert_eq!(vec!["index",
+                        "time_sorg",
+                        "time_external",
+                        "origin",
+                        "direction",
+                        "facet",
+                        "high_samples",
+                        "high_length",
+                        "high_time_sosbl",
+                        "high_record",
+                        "reference_samples",
+                        "reference_length",
+                        "reference_time_sosbl",
+                        "reference_record"],
+                   names(&bytes));
+    }
+
+    #[test]
+    fn empty() {
+        let waveforms = Waveforms::new();
+        assert!(waveforms.is_empty());
+        let bytes = waveforms.write(NpzWriter::new(Cursor::new(Vec::new())))
+                             .unwrap()
+                             .into_inner();
+        assert_eq!(6, names(&bytes).len());
+    }
+}